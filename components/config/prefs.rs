@@ -266,6 +266,10 @@ mod gen {
                         allowed_in_nonsecure_contexts: bool,
                     }
                 },
+                render_blocking: {
+                    #[serde(default)]
+                    timeout_ms: i64,
+                },
                 script: {
                     asynch: bool,
                 },
@@ -279,7 +283,83 @@ mod gen {
                 servoparser: {
                     async_html_tokenizer: {
                         enabled: bool,
-                    }
+                        #[serde(default)]
+                        feed_size: i64,
+                        #[serde(default)]
+                        operation_batch_size: i64,
+                        #[serde(default)]
+                        size_threshold: i64,
+                    },
+                    chunked_fragment_parsing: {
+                        #[serde(default)]
+                        chunk_size: i64,
+                        #[serde(default)]
+                        enabled: bool,
+                        #[serde(default)]
+                        size_threshold: i64,
+                    },
+                    collect_resource_urls: {
+                        #[serde(default)]
+                        enabled: bool,
+                    },
+                    deprecation_warnings: {
+                        #[serde(default)]
+                        enabled: bool,
+                    },
+                    empty_document_placeholder: {
+                        #[serde(default)]
+                        enabled: bool,
+                    },
+                    #[serde(default)]
+                    encoding_change_buffer_size: i64,
+                    #[serde(default)]
+                    encoding_sniffing_buffer_size: i64,
+                    font_viewer: {
+                        #[serde(default)]
+                        enabled: bool,
+                    },
+                    icon_viewer: {
+                        #[serde(default)]
+                        enabled: bool,
+                    },
+                    image_viewer_details: {
+                        #[serde(default)]
+                        enabled: bool,
+                    },
+                    #[serde(default)]
+                    max_attributes_per_element: i64,
+                    #[serde(default)]
+                    max_chunk_size: i64,
+                    #[serde(default)]
+                    max_parse_bytes: i64,
+                    #[serde(default)]
+                    max_parse_nodes: i64,
+                    #[serde(default)]
+                    max_prefetch_requests: i64,
+                    mixed_content_warning: {
+                        #[serde(default)]
+                        enabled: bool,
+                    },
+                    pause_prefetch_while_suspended: {
+                        #[serde(default)]
+                        enabled: bool,
+                    },
+                    pdf_viewer: {
+                        #[serde(default)]
+                        enabled: bool,
+                    },
+                    #[serde(default)]
+                    reflow_node_threshold: i64,
+                    source_line_numbers: {
+                        #[serde(default)]
+                        enabled: bool,
+                    },
+                    strict: {
+                        #[serde(default)]
+                        enabled: bool,
+                    },
+                    #[serde(default)]
+                    unknown_content_type: String,
                 },
                 shadowdom: {
                     enabled: bool,
@@ -89,7 +89,13 @@ impl CookieStorage {
         }
     }
     pub fn clear_storage(&mut self, url: &ServoUrl) {
-        let domain = reg_host(url.host_str().unwrap_or(""));
+        self.clear_storage_for_domain(url.host_str().unwrap_or(""));
+    }
+
+    /// Expires the cookies of the registrable domain of `domain`, which
+    /// includes those of its subdomains.
+    pub fn clear_storage_for_domain(&mut self, domain: &str) {
+        let domain = reg_host(domain);
         let cookies = self.cookies_map.entry(domain).or_default();
         for cookie in cookies.iter_mut() {
             cookie.set_expiry_time_negative();
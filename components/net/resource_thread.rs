@@ -28,8 +28,8 @@ use net_traits::response::{Response, ResponseInit};
 use net_traits::storage_thread::StorageThreadMsg;
 use net_traits::{
     CookieSource, CoreResourceMsg, CoreResourceThread, CustomResponseMediator, DiscardFetch,
-    FetchChannels, FetchTaskTarget, ResourceFetchTiming, ResourceThreads, ResourceTimingType,
-    WebSocketDomAction, WebSocketNetworkEvent,
+    DiscardTrackedFetch, FetchChannels, FetchTaskTarget, ResourceFetchTiming, ResourceThreads,
+    ResourceTimingType, WebSocketDomAction, WebSocketNetworkEvent,
 };
 use profile_traits::mem::{ProfilerChan as MemProfilerChan, Report, ReportKind, ReportsChan};
 use profile_traits::path;
@@ -295,9 +295,20 @@ impl ResourceChannelManager {
                     action_receiver,
                     http_state,
                 ),
-                FetchChannels::Prefetch => {
+                FetchChannels::Prefetch(cancel_chan, None) => self.resource_manager.fetch(
+                    req_init,
+                    None,
+                    DiscardFetch,
+                    http_state,
+                    cancel_chan,
+                ),
+                FetchChannels::Prefetch(cancel_chan, Some(done_chan)) => {
+                    let target = DiscardTrackedFetch {
+                        url: req_init.url.clone(),
+                        done_chan,
+                    };
                     self.resource_manager
-                        .fetch(req_init, None, DiscardFetch, http_state, None)
+                        .fetch(req_init, None, target, http_state, cancel_chan)
                 },
             },
             CoreResourceMsg::DeleteCookies(request) => {
@@ -308,6 +319,14 @@ impl ResourceChannelManager {
                     .clear_storage(&request);
                 return true;
             },
+            CoreResourceMsg::DeleteCookiesForRegistrableDomain(domain) => {
+                http_state
+                    .cookie_jar
+                    .write()
+                    .unwrap()
+                    .clear_storage_for_domain(&domain);
+                return true;
+            },
             CoreResourceMsg::FetchRedirect(req_init, res_init, sender, cancel_chan) => self
                 .resource_manager
                 .fetch(req_init, Some(res_init), sender, http_state, cancel_chan),
@@ -367,6 +386,9 @@ impl ResourceChannelManager {
             CoreResourceMsg::ClearCache => {
                 http_state.http_cache.write().unwrap().clear();
             },
+            CoreResourceMsg::ClearCacheForOrigin(origin) => {
+                http_state.http_cache.write().unwrap().clear_origin(&origin);
+            },
             CoreResourceMsg::ToFileManager(msg) => self.resource_manager.filemanager.handle(msg),
             CoreResourceMsg::Exit(sender) => {
                 if let Some(ref config_dir) = self.config_dir {
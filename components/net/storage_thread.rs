@@ -83,6 +83,10 @@ impl StorageManager {
                     self.clear(sender, url, storage_type);
                     self.save_state()
                 },
+                StorageThreadMsg::ClearOrigin(url) => {
+                    self.clear_origin(url);
+                    self.save_state()
+                },
                 StorageThreadMsg::Exit(sender) => {
                     // Nothing to do since we save localstorage set eagerly.
                     let _ = sender.send(());
@@ -271,6 +275,16 @@ impl StorageManager {
             .unwrap();
     }
 
+    fn clear_origin(&mut self, url: ServoUrl) {
+        let origin = self.origin_as_string(url);
+        for storage_type in [StorageType::Local, StorageType::Session] {
+            if let Some((total, entry)) = self.select_data_mut(storage_type).get_mut(&origin) {
+                entry.clear();
+                *total = 0;
+            }
+        }
+    }
+
     fn origin_as_string(&self, url: ServoUrl) -> String {
         url.origin().ascii_serialization()
     }
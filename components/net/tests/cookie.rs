@@ -475,3 +475,32 @@ fn test_cookie_eviction_all_nonsecure_new_nonsecure() {
         "extra2=bar; extra3=bar; extra4=bar; extra5=bar; foo=bar"
     );
 }
+
+#[test]
+fn test_clear_storage_for_domain() {
+    let mut storage = CookieStorage::new(5);
+    let source = CookieSource::HTTP;
+    for set_location in [
+        "https://example.org/",
+        "https://home.example.org/",
+        "https://example.com/",
+    ] {
+        let url = ServoUrl::parse(set_location).unwrap();
+        let cookie = Cookie::from_cookie_string("foo=bar".to_owned(), &url, source).unwrap();
+        storage.push(cookie, &url, source);
+    }
+
+    // The cookies of the subdomains go with those of the registrable domain.
+    storage.clear_storage_for_domain("example.org");
+    for cleared_location in ["https://example.org/", "https://home.example.org/"] {
+        let url = ServoUrl::parse(cleared_location).unwrap();
+        storage.remove_expired_cookies_for_url(&url);
+        assert_eq!(storage.cookies_for_url(&url, source), None);
+    }
+    let url = ServoUrl::parse("https://example.com/").unwrap();
+    storage.remove_expired_cookies_for_url(&url);
+    assert_eq!(
+        storage.cookies_for_url(&url, source),
+        Some("foo=bar".to_owned())
+    );
+}
@@ -152,6 +152,18 @@ fn base64_ct() {
     );
 }
 
+#[test]
+fn base64_html_charset() {
+    assert_parse(
+        "data:text/html;charset=windows-1252;base64,PHA+Y2Fm6TwvcD4=",
+        Some(ContentType::from(
+            "text/html; charset=windows-1252".parse::<Mime>().unwrap(),
+        )),
+        Some("windows-1252"),
+        Some(b"<p>caf\xE9</p>"),
+    );
+}
+
 #[test]
 fn base64_charset() {
     assert_parse(
@@ -127,6 +127,7 @@ impl Formattable for ProfilerCategory {
             ProfilerCategory::ScriptInputEvent => "Script Input Event",
             ProfilerCategory::ScriptNetworkEvent => "Script Network Event",
             ProfilerCategory::ScriptParseHTML => "Script Parse HTML",
+            ProfilerCategory::ScriptParseHTMLFragment => "Script Parse HTML Fragment",
             ProfilerCategory::ScriptParseXML => "Script Parse XML",
             ProfilerCategory::ScriptPlannedNavigation => "Script Planned Navigation",
             ProfilerCategory::ScriptPortMessage => "Script Port Message",
@@ -100,6 +100,13 @@ impl DocumentLoader {
         canceled_any
     }
 
+    /// Stops waiting for the loads that were cancelled, i.e. every load but
+    /// that of the page source, so that they don't delay the load event.
+    pub fn forget_all_but_page_source_loads(&mut self) {
+        self.blocking_loads
+            .retain(|load| matches!(load, LoadType::PageSource(_)));
+    }
+
     /// Add a load to the list of blocking loads.
     fn add_blocking_load(&mut self, load: LoadType) {
         debug!(
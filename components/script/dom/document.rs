@@ -20,7 +20,7 @@ use cookie::Cookie;
 use cssparser::match_ignore_ascii_case;
 use devtools_traits::ScriptToDevtoolsControlMsg;
 use dom_struct::dom_struct;
-use embedder_traits::EmbedderMsg;
+use embedder_traits::{EmbedderMsg, SupportedColorSchemes};
 use encoding_rs::{Encoding, UTF_8};
 use euclid::default::{Point2D, Rect, Size2D};
 use html5ever::{local_name, namespace_url, ns, LocalName, Namespace, QualName};
@@ -140,8 +140,10 @@ use crate::dom::htmlhtmlelement::HTMLHtmlElement;
 use crate::dom::htmliframeelement::HTMLIFrameElement;
 use crate::dom::htmlimageelement::HTMLImageElement;
 use crate::dom::htmlinputelement::HTMLInputElement;
+use crate::dom::htmllinkelement::HTMLLinkElement;
 use crate::dom::htmlmetaelement::RefreshRedirectDue;
 use crate::dom::htmlscriptelement::{HTMLScriptElement, ScriptResult};
+use crate::dom::htmlstyleelement::HTMLStyleElement;
 use crate::dom::htmltextareaelement::HTMLTextAreaElement;
 use crate::dom::htmltitleelement::HTMLTitleElement;
 use crate::dom::keyboardevent::KeyboardEvent;
@@ -181,7 +183,7 @@ use crate::script_thread::{MainThreadScriptMsg, ScriptThread};
 use crate::stylesheet_set::StylesheetSetRef;
 use crate::task::TaskBox;
 use crate::task_source::{TaskSource, TaskSourceName};
-use crate::timers::OneshotTimerCallback;
+use crate::timers::{OneshotTimerCallback, OneshotTimerHandle};
 
 /// The number of times we are allowed to see spurious `requestAnimationFrame()` calls before
 /// falling back to fake ones.
@@ -192,6 +194,10 @@ const SPURIOUS_ANIMATION_FRAME_THRESHOLD: u8 = 5;
 /// The amount of time between fake `requestAnimationFrame()`s.
 const FAKE_REQUEST_ANIMATION_FRAME_DELAY: u64 = 16;
 
+/// How long elements may block rendering when `dom.render_blocking.timeout_ms`
+/// isn't set.
+const DEFAULT_RENDER_BLOCKING_TIMEOUT_MS: u64 = 5000;
+
 pub enum TouchEventResult {
     Processed(bool),
     Forwarded,
@@ -301,6 +307,20 @@ pub struct Document {
     pending_parsing_blocking_script: DomRefCell<Option<PendingScript>>,
     /// Number of stylesheets that block executing the next parser-inserted script
     script_blocking_stylesheets_count: Cell<u32>,
+    /// <https://html.spec.whatwg.org/multipage/#render-blocking-element-set>
+    render_blocking_elements: DomRefCell<Vec<Dom<Element>>>,
+    /// The timer unblocking rendering if the render-blocking elements take
+    /// too long, see `block_rendering`.
+    render_blocking_timeout: DomRefCell<Option<OneshotTimerHandle>>,
+    /// <https://html.spec.whatwg.org/multipage/#render-blocked>, kept up to
+    /// date by `block_rendering` and `update_render_blocking`, as it's checked
+    /// on every reflow.
+    render_blocked: Cell<bool>,
+    /// The color schemes declared by the first `<meta name="color-scheme">`
+    /// the parser inserted, see `declare_supported_color_schemes`.
+    #[ignore_malloc_size_of = "type from external crate"]
+    #[no_trace]
+    supported_color_schemes: DomRefCell<Option<SupportedColorSchemes>>,
     /// <https://html.spec.whatwg.org/multipage/#list-of-scripts-that-will-execute-when-the-document-has-finished-parsing>
     deferred_scripts: PendingInOrderScriptVec,
     /// <https://html.spec.whatwg.org/multipage/#list-of-scripts-that-will-execute-in-order-as-soon-as-possible>
@@ -329,6 +349,10 @@ pub struct Document {
     reflow_timeout: Cell<Option<u64>>,
     /// The cached first `base` element with an `href` attribute.
     base_element: MutNullableDom<HTMLBaseElement>,
+    /// The URL given by the `Content-Location` header of the response this
+    /// document was loaded from, if any, used as its fallback base URL.
+    #[no_trace]
+    content_location: DomRefCell<Option<ServoUrl>>,
     /// This field is set to the document itself for inert documents.
     /// <https://html.spec.whatwg.org/multipage/#appropriate-template-contents-owner-document>
     appropriate_template_contents_owner_document: MutNullableDom<Document>,
@@ -449,6 +473,9 @@ pub struct Document {
     dirty_root: MutNullableDom<Element>,
     /// <https://html.spec.whatwg.org/multipage/#will-declaratively-refresh>
     declarative_refresh: DomRefCell<Option<DeclarativeRefresh>>,
+    /// Whether the doctype given by the parser is the one of an XHTML document
+    /// while this is an HTML document, or the other way around.
+    doctype_mismatch: Cell<bool>,
 }
 
 #[derive(JSTraceable, MallocSizeOf)]
@@ -755,8 +782,19 @@ impl Document {
                 return browsing_context.creator_base_url().unwrap();
             }
         }
-        // Step 3: Return document's URL.
-        document_url
+        // Step 3: Return document's URL, or the same-origin URL given by the
+        // `Content-Location` header of its response, which takes its place
+        // for resolving relative URLs.
+        self.content_location
+            .borrow()
+            .clone()
+            .unwrap_or(document_url)
+    }
+
+    /// Sets the URL given by the `Content-Location` header of the response
+    /// this document was loaded from.
+    pub fn set_content_location(&self, url: Option<ServoUrl>) {
+        *self.content_location.borrow_mut() = url;
     }
 
     // https://html.spec.whatwg.org/multipage/#document-base-url
@@ -833,6 +871,17 @@ impl Document {
         self.dom_count.set(self.dom_count.get() - 1);
     }
 
+    /// Whether the doctype of the document doesn't match its content type,
+    /// as with XHTML served as `text/html`. This doesn't change how the
+    /// document is handled, but tells which documents rely on such content.
+    pub fn has_doctype_mismatch(&self) -> bool {
+        self.doctype_mismatch.get()
+    }
+
+    pub fn set_doctype_mismatch(&self, mismatch: bool) {
+        self.doctype_mismatch.set(mismatch)
+    }
+
     pub fn quirks_mode(&self) -> QuirksMode {
         self.quirks_mode.get()
     }
@@ -847,6 +896,10 @@ impl Document {
         }
     }
 
+    pub fn content_type(&self) -> &Mime {
+        &self.content_type
+    }
+
     pub fn encoding(&self) -> &'static Encoding {
         self.encoding.get()
     }
@@ -865,17 +918,29 @@ impl Document {
         node.dirty(NodeDamage::OtherNodeDamage);
     }
 
-    /// Reflows and disarms the timer if the reflow timer has expired.
-    pub fn reflow_if_reflow_timer_expired(&self) {
+    /// Reflows and disarms the timer if the reflow timer has expired. Returns
+    /// whether it did.
+    pub fn reflow_if_reflow_timer_expired(&self) -> bool {
         if let Some(reflow_timeout) = self.reflow_timeout.get() {
             if time::precise_time_ns() < reflow_timeout {
-                return;
+                return false;
             }
 
             self.reflow_timeout.set(None);
             self.window
                 .reflow(ReflowGoal::Full, ReflowReason::RefreshTick);
+            return true;
         }
+        false
+    }
+
+    /// Reflows right away, disarming the reflow timer if it's armed. The parser
+    /// uses this to display the content it inserted so far without waiting for
+    /// the timer.
+    pub fn reflow_for_parsed_content(&self) {
+        self.reflow_timeout.set(None);
+        self.window
+            .reflow(ReflowGoal::Full, ReflowReason::RefreshTick);
     }
 
     /// Schedules a reflow to be kicked off at the given `timeout` (in `time::precise_time_ns()`
@@ -1944,6 +2009,144 @@ impl Document {
         count_cell.set(count_cell.get() - 1);
     }
 
+    /// <https://html.spec.whatwg.org/multipage/#block-rendering>
+    ///
+    /// Elements leave the set once they no longer block rendering, see
+    /// `update_render_blocking`. As the spec allows, rendering is unblocked
+    /// anyway after `dom.render_blocking.timeout_ms`, so that a resource that
+    /// never loads doesn't keep the document from ever being painted.
+    pub fn block_rendering(&self, element: &Element) {
+        let mut elements = self.render_blocking_elements.borrow_mut();
+        if !elements.iter().any(|blocking| &**blocking == element) {
+            elements.push(Dom::from_ref(element));
+        }
+        self.render_blocked.set(true);
+        let mut timeout = self.render_blocking_timeout.borrow_mut();
+        if timeout.is_none() {
+            let callback = RenderBlockingTimeoutCallback {
+                document: Trusted::new(self),
+            };
+            *timeout = Some(self.global().schedule_callback(
+                OneshotTimerCallback::RenderBlockingTimeout(callback),
+                MsDuration::new(render_blocking_timeout_ms()),
+            ));
+        }
+    }
+
+    /// An element stops blocking rendering once it's no longer connected to
+    /// this document or render-blocking, once its stylesheet is loaded, and
+    /// once its script is executed, or won't be.
+    fn still_blocks_rendering(&self, element: &Element) -> bool {
+        let node = element.upcast::<Node>();
+        if !node.is_connected() || &*node.owner_doc() != self {
+            return false;
+        }
+        let blocking = element
+            .get_attribute(&ns!(), &local_name!("blocking"))
+            .map_or(false, |blocking| {
+                blocking
+                    .value()
+                    .split_ascii_whitespace()
+                    .any(|token| token.eq_ignore_ascii_case("render"))
+            });
+        if !blocking {
+            return false;
+        }
+        if let Some(link) = element.downcast::<HTMLLinkElement>() {
+            return link.is_loading_stylesheet();
+        }
+        if let Some(style) = element.downcast::<HTMLStyleElement>() {
+            return style.is_loading_stylesheet();
+        }
+        if let Some(script) = element.downcast::<HTMLScriptElement>() {
+            // A parser-inserted script that wasn't prepared yet is still to
+            // be executed.
+            return (script.get_parser_inserted() && !script.get_already_started()) ||
+                self.is_pending_script(script);
+        }
+        false
+    }
+
+    /// Whether `script` waits to be executed, once it's fetched or the
+    /// parser or the document gets to it.
+    fn is_pending_script(&self, script: &HTMLScriptElement) -> bool {
+        self.pending_parsing_blocking_script
+            .borrow()
+            .as_ref()
+            .map_or(false, |pending| &*pending.element == script) ||
+            self.asap_scripts_set
+                .borrow()
+                .iter()
+                .any(|asap| &**asap == script) ||
+            self.asap_in_order_scripts_list.contains(script) ||
+            self.deferred_scripts.contains(script)
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/#unblock-rendering>
+    ///
+    /// Drops the elements that no longer block rendering from the set, and
+    /// unblocks rendering once none is left. This has to be called whenever
+    /// an element may have stopped blocking rendering: once it's disconnected,
+    /// its `blocking` attribute changes, its stylesheet is loaded, or its
+    /// script is prepared or executed.
+    pub fn update_render_blocking(&self) {
+        if !self.render_blocked.get() {
+            return;
+        }
+        self.render_blocking_elements
+            .borrow_mut()
+            .retain(|element| self.still_blocks_rendering(element));
+        if self.render_blocking_elements.borrow().is_empty() {
+            self.unblock_all_rendering();
+            self.window.add_pending_reflow();
+        }
+    }
+
+    /// Unblocks rendering, whatever elements are still render-blocking.
+    fn unblock_all_rendering(&self) {
+        self.render_blocked.set(false);
+        self.render_blocking_elements.borrow_mut().clear();
+        if let Some(timeout) = self.render_blocking_timeout.borrow_mut().take() {
+            self.global().unschedule_callback(timeout);
+        }
+    }
+
+    fn render_blocking_timed_out(&self) {
+        self.render_blocking_timeout.borrow_mut().take();
+        if self.is_render_blocked() {
+            warn!("Unblocking rendering after the render-blocking elements timed out");
+            self.unblock_all_rendering();
+            self.window.add_pending_reflow();
+        }
+    }
+
+    /// Records the color schemes declared by a `<meta name="color-scheme">`
+    /// as soon as the parser inserts it, unless one was inserted before,
+    /// which wins. The embedder of a top-level document is told, so that it
+    /// can follow the color scheme of the page before it is rendered, rather
+    /// than after a flash of the wrong one.
+    /// <https://html.spec.whatwg.org/multipage/#meta-color-scheme>
+    pub fn declare_supported_color_schemes(&self, schemes: SupportedColorSchemes) {
+        let mut supported_color_schemes = self.supported_color_schemes.borrow_mut();
+        if supported_color_schemes.is_some() {
+            return;
+        }
+        *supported_color_schemes = Some(schemes.clone());
+        drop(supported_color_schemes);
+        if self.window().is_top_level() {
+            self.send_to_embedder(EmbedderMsg::ColorSchemeDeclared(schemes));
+        }
+    }
+
+    pub fn supported_color_schemes(&self) -> Option<SupportedColorSchemes> {
+        self.supported_color_schemes.borrow().clone()
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/#render-blocked>
+    pub fn is_render_blocked(&self) -> bool {
+        self.render_blocked.get()
+    }
+
     pub fn invalidate_stylesheets(&self) {
         self.stylesheets.borrow_mut().force_dirty(OriginSet::all());
 
@@ -2000,6 +2203,12 @@ impl Document {
 
     /// <https://html.spec.whatwg.org/multipage/#run-the-animation-frame-callbacks>
     pub fn run_the_animation_frame_callbacks(&self) {
+        // The rendering of a render-blocked document isn't updated, which
+        // includes running its animation frame callbacks.
+        if self.is_render_blocked() {
+            return;
+        }
+
         rooted_vec!(let mut animation_frame_list);
         mem::swap(
             &mut *animation_frame_list,
@@ -2083,6 +2292,9 @@ impl Document {
     ) {
         request.csp_list = self.get_csp_list().map(|x| x.clone());
         request.https_state = self.https_state.get();
+        if let Some(parser) = self.get_current_parser() {
+            parser.adopt_prefetch(&request.url);
+        }
         let mut loader = self.loader.borrow_mut();
         loader.fetch_async(load, request, fetch_target);
     }
@@ -2456,6 +2668,12 @@ impl Document {
         self.pending_parsing_blocking_script.borrow().is_some()
     }
 
+    /// Drops the pending parsing-blocking script without running it, because
+    /// the content it was parsed from is replaced.
+    pub fn forget_pending_parsing_blocking_script(&self) {
+        *self.pending_parsing_blocking_script.borrow_mut() = None;
+    }
+
     /// <https://html.spec.whatwg.org/multipage/#prepare-a-script> step 22.d.
     pub fn pending_parsing_blocking_script_loaded(
         &self,
@@ -2464,9 +2682,12 @@ impl Document {
     ) {
         {
             let mut blocking_script = self.pending_parsing_blocking_script.borrow_mut();
-            let entry = blocking_script.as_mut().unwrap();
-            assert!(&*entry.element == element);
-            entry.loaded(result);
+            match blocking_script.as_mut() {
+                Some(entry) if &*entry.element == element => entry.loaded(result),
+                // The script was forgotten while it loaded, see
+                // `forget_pending_parsing_blocking_script`.
+                _ => return,
+            }
         }
         self.process_pending_parsing_blocking_script();
     }
@@ -2616,6 +2837,7 @@ impl Document {
 
         // Step 2.
         self.script_blocking_stylesheets_count.set(0);
+        self.unblock_all_rendering();
         *self.pending_parsing_blocking_script.borrow_mut() = None;
         *self.asap_scripts_set.borrow_mut() = vec![];
         self.asap_in_order_scripts_list.clear();
@@ -2643,6 +2865,31 @@ impl Document {
         }
     }
 
+    /// Aborts a parser that replaces the content of the document with a page
+    /// of its own, e.g. a diagnostic of why it stopped. The loads of the
+    /// document are cancelled like in `abort`, but the load event isn't
+    /// inhibited: the cancelled loads don't delay it anymore, and the parser
+    /// finishes the load of the page source once the page is built.
+    pub fn abort_parser_to_replace_content(&self, parser: &ServoParser) {
+        self.script_blocking_stylesheets_count.set(0);
+        self.unblock_all_rendering();
+        *self.pending_parsing_blocking_script.borrow_mut() = None;
+        *self.asap_scripts_set.borrow_mut() = vec![];
+        self.asap_in_order_scripts_list.clear();
+        self.deferred_scripts.clear();
+        {
+            let mut loader = self.loader.borrow_mut();
+            loader.cancel_all_loads();
+            loader.forget_all_but_page_source_loads();
+        }
+        self.window
+            .cancel_all_tasks_from_source(TaskSourceName::Networking);
+
+        self.active_parser_was_aborted.set(true);
+        parser.abort();
+        self.salvageable.set(false);
+    }
+
     pub fn notify_constellation_load(&self) {
         self.window().send_to_constellation(ScriptMsg::LoadComplete);
     }
@@ -3162,6 +3409,10 @@ impl Document {
             current_script: Default::default(),
             pending_parsing_blocking_script: Default::default(),
             script_blocking_stylesheets_count: Cell::new(0u32),
+            render_blocking_elements: Default::default(),
+            render_blocking_timeout: Default::default(),
+            render_blocked: Cell::new(false),
+            supported_color_schemes: Default::default(),
             deferred_scripts: Default::default(),
             asap_in_order_scripts_list: Default::default(),
             asap_scripts_set: Default::default(),
@@ -3173,6 +3424,7 @@ impl Document {
             current_parser: Default::default(),
             reflow_timeout: Cell::new(None),
             base_element: Default::default(),
+            content_location: Default::default(),
             appropriate_template_contents_owner_document: Default::default(),
             pending_restyles: DomRefCell::new(HashMap::new()),
             needs_paint: Cell::new(false),
@@ -3227,6 +3479,7 @@ impl Document {
             animations: DomRefCell::new(Animations::new()),
             dirty_root: Default::default(),
             declarative_refresh: Default::default(),
+            doctype_mismatch: Default::default(),
         }
     }
 
@@ -3794,7 +4047,10 @@ impl Document {
         }
     }
 
-    fn reset_form_owner_for_listeners(&self, id: &Atom) {
+    /// Resets the form owner of the controls whose form attribute is `id`,
+    /// when an element with that id is added to or removed from this
+    /// document or one of its shadow trees.
+    pub fn reset_form_owner_for_listeners(&self, id: &Atom) {
         let map = self.form_id_listener_map.borrow();
         if let Some(listeners) = map.get(id) {
             for listener in listeners {
@@ -5361,6 +5617,29 @@ impl FakeRequestAnimationFrameCallback {
     }
 }
 
+/// Unblocks the rendering of a document whose render-blocking elements took
+/// too long, see `Document::block_rendering`.
+#[derive(JSTraceable, MallocSizeOf)]
+pub struct RenderBlockingTimeoutCallback {
+    #[ignore_malloc_size_of = "non-owning"]
+    document: Trusted<Document>,
+}
+
+impl RenderBlockingTimeoutCallback {
+    pub fn invoke(self) {
+        self.document.root().render_blocking_timed_out();
+    }
+}
+
+/// How long elements may block the rendering of a document, see
+/// `dom.render_blocking.timeout_ms`.
+fn render_blocking_timeout_ms() -> u64 {
+    match pref!(dom.render_blocking.timeout_ms) {
+        timeout if timeout > 0 => timeout as u64,
+        _ => DEFAULT_RENDER_BLOCKING_TIMEOUT_MS,
+    }
+}
+
 #[derive(JSTraceable, MallocSizeOf)]
 pub enum AnimationFrameCallback {
     DevtoolsFramerateTick {
@@ -5429,6 +5708,13 @@ impl PendingInOrderScriptVec {
     fn clear(&self) {
         *self.scripts.borrow_mut() = Default::default();
     }
+
+    fn contains(&self, element: &HTMLScriptElement) -> bool {
+        self.scripts
+            .borrow()
+            .iter()
+            .any(|entry| &*entry.element == element)
+    }
 }
 
 #[derive(JSTraceable, MallocSizeOf)]
@@ -80,7 +80,7 @@ impl DOMParserMethods for DOMParser {
                     None,
                     Default::default(),
                 );
-                ServoParser::parse_html_document(&document, Some(s), url);
+                ServoParser::parse_html_document(&document, Some(s), url, None, None);
                 document.set_ready_state(DocumentReadyState::Complete);
                 Ok(document)
             },
@@ -35,6 +35,7 @@ use selectors::sink::Push;
 use selectors::Element as SelectorsElement;
 use servo_arc::Arc;
 use servo_atoms::Atom;
+use servo_config::pref;
 use style::applicable_declarations::ApplicableDeclarationBlock;
 use style::attr::{AttrValue, LengthOrPercentageOrAuto};
 use style::context::QuirksMode;
@@ -70,7 +71,7 @@ use crate::dom::attr::{Attr, AttrHelpersForLayout};
 use crate::dom::bindings::cell::{ref_filter_map, DomRefCell, Ref, RefMut};
 use crate::dom::bindings::codegen::Bindings::AttrBinding::AttrMethods;
 use crate::dom::bindings::codegen::Bindings::DocumentBinding::DocumentMethods;
-use crate::dom::bindings::codegen::Bindings::ElementBinding::ElementMethods;
+use crate::dom::bindings::codegen::Bindings::ElementBinding::{ElementMethods, GetHTMLOptions};
 use crate::dom::bindings::codegen::Bindings::FunctionBinding::Function;
 use crate::dom::bindings::codegen::Bindings::HTMLTemplateElementBinding::HTMLTemplateElementMethods;
 use crate::dom::bindings::codegen::Bindings::NodeBinding::NodeMethods;
@@ -140,7 +141,7 @@ use crate::dom::node::{
 use crate::dom::nodelist::NodeList;
 use crate::dom::promise::Promise;
 use crate::dom::raredata::ElementRareData;
-use crate::dom::servoparser::ServoParser;
+use crate::dom::servoparser::{fragment_chunk_size, HtmlSerialization, ServoParser};
 use crate::dom::shadowroot::{IsUserAgentWidget, ShadowRoot};
 use crate::dom::text::Text;
 use crate::dom::validation::Validatable;
@@ -482,7 +483,7 @@ impl Element {
         self.ScrollHeight() > self.ClientHeight() || self.ScrollWidth() > self.ClientWidth()
     }
 
-    fn shadow_root(&self) -> Option<DomRoot<ShadowRoot>> {
+    pub fn shadow_root(&self) -> Option<DomRoot<ShadowRoot>> {
         self.rare_data()
             .as_ref()?
             .shadow_root
@@ -1292,11 +1293,21 @@ impl Element {
         }
     }
 
-    pub fn serialize(&self, traversal_scope: TraversalScope) -> Fallible<DOMString> {
+    /// Serializes this element as HTML. With `include_shadow_roots`, the
+    /// declarative shadow roots in it are serialized too, see
+    /// `HtmlSerialization`.
+    pub fn serialize(
+        &self,
+        traversal_scope: TraversalScope,
+        include_shadow_roots: bool,
+    ) -> Fallible<DOMString> {
         let mut writer = vec![];
         match serialize(
             &mut writer,
-            &self.upcast::<Node>(),
+            &HtmlSerialization {
+                node: self.upcast(),
+                include_shadow_roots,
+            },
             SerializeOpts {
                 traversal_scope: traversal_scope,
                 ..Default::default()
@@ -1381,6 +1392,18 @@ impl Element {
         }
     }
 
+    /// The element focused in place of this one, if this hosts a shadow root
+    /// that delegates focus: the first focusable area in its shadow tree.
+    /// <https://html.spec.whatwg.org/multipage/#focus-delegate>
+    pub fn focus_delegate(&self) -> Option<DomRoot<Element>> {
+        let shadow_root = self.shadow_root()?;
+        shadow_root
+            .upcast::<Node>()
+            .traverse_preorder(ShadowIncluding::Yes)
+            .filter_map(DomRoot::downcast::<Element>)
+            .find(|element| element.is_focusable_area())
+    }
+
     pub fn is_focusable_area(&self) -> bool {
         if self.is_actually_disabled() {
             return false;
@@ -1880,6 +1903,51 @@ impl Element {
         Ok(fragment)
     }
 
+    /// The size of the chunks in which the `innerHTML` and `insertAdjacentHTML`
+    /// setters parse `markup`, if it's large enough to be parsed in chunks.
+    fn fragment_parsing_chunk_size(markup: &DOMString) -> Option<usize> {
+        fragment_chunk_size(
+            pref!(dom.servoparser.chunked_fragment_parsing.enabled),
+            pref!(dom.servoparser.chunked_fragment_parsing.chunk_size),
+            pref!(dom.servoparser.chunked_fragment_parsing.size_threshold),
+            markup.len(),
+        )
+    }
+
+    /// Parses `markup` like `parse_fragment`, but `chunk_size` bytes at a time,
+    /// inserting the nodes into `parent` before `child` as they are parsed.
+    /// Parsing stops if `child` or the nodes inserted so far are moved out of
+    /// `parent` in the meantime, as there's no telling where the rest goes.
+    fn insert_fragment_in_chunks(
+        &self,
+        markup: DOMString,
+        chunk_size: usize,
+        parent: &Node,
+        child: Option<&Node>,
+    ) {
+        let parent = Trusted::new(parent);
+        let child = child.map(Trusted::new);
+        let mut last_inserted: Option<Trusted<Node>> = None;
+        ServoParser::parse_html_fragment_in_chunks(self, markup, chunk_size, move |nodes| {
+            let parent = parent.root();
+            if let Some(ref last_inserted) = last_inserted {
+                if last_inserted.root().GetParentNode().as_deref() != Some(&*parent) {
+                    return false;
+                }
+            }
+            let fragment = DocumentFragment::new(&parent.owner_doc());
+            for node in &nodes {
+                fragment.upcast::<Node>().AppendChild(node).unwrap();
+            }
+            let child = child.as_ref().map(Trusted::root);
+            if Node::pre_insert(fragment.upcast(), &parent, child.as_deref()).is_err() {
+                return false;
+            }
+            last_inserted = nodes.last().map(|node| Trusted::new(&**node));
+            true
+        });
+    }
+
     pub fn fragment_parsing_context(owner_doc: &Document, element: Option<&Self>) -> DomRoot<Self> {
         match element {
             Some(elem)
@@ -2594,7 +2662,7 @@ impl ElementMethods for Element {
             self.local_name().clone(),
         );
         if document_from_node(self).is_html_document() {
-            return self.serialize(ChildrenOnly(Some(qname)));
+            return self.serialize(ChildrenOnly(Some(qname)), false);
         } else {
             return self.xmlSerialize(XmlChildrenOnly(Some(qname)));
         }
@@ -2623,6 +2691,12 @@ impl ElementMethods for Element {
             return Ok(());
         }
 
+        if let Some(chunk_size) = Element::fragment_parsing_chunk_size(&value) {
+            Node::replace_all(None, &target);
+            self.insert_fragment_in_chunks(value, chunk_size, &target, None);
+            return Ok(());
+        }
+
         // Step 1.
         let frag = self.parse_fragment(value)?;
 
@@ -2630,10 +2704,22 @@ impl ElementMethods for Element {
         Ok(())
     }
 
+    /// <https://html.spec.whatwg.org/multipage/#dom-element-gethtml>
+    fn GetHTML(&self, options: &GetHTMLOptions) -> DOMString {
+        // The shadow roots declared in markup are the only serializable ones.
+        let qname = QualName::new(
+            self.prefix().clone(),
+            self.namespace().clone(),
+            self.local_name().clone(),
+        );
+        self.serialize(ChildrenOnly(Some(qname)), options.serializableShadowRoots)
+            .unwrap_or_default()
+    }
+
     // https://dvcs.w3.org/hg/innerhtml/raw-file/tip/index.html#widl-Element-outerHTML
     fn GetOuterHTML(&self) -> Fallible<DOMString> {
         if document_from_node(self).is_html_document() {
-            return self.serialize(IncludeNode);
+            return self.serialize(IncludeNode, false);
         } else {
             return self.xmlSerialize(XmlIncludeNode);
         }
@@ -2853,6 +2939,28 @@ impl ElementMethods for Element {
         let context =
             Element::fragment_parsing_context(&context.owner_doc(), context.downcast::<Element>());
 
+        if let Some(chunk_size) = Element::fragment_parsing_chunk_size(&text) {
+            let self_node = self.upcast::<Node>();
+            let (parent, child) = match position {
+                AdjacentPosition::BeforeBegin => (
+                    self_node.GetParentNode(),
+                    Some(DomRoot::from_ref(self_node)),
+                ),
+                AdjacentPosition::AfterBegin => (
+                    Some(DomRoot::from_ref(self_node)),
+                    self_node.GetFirstChild(),
+                ),
+                AdjacentPosition::BeforeEnd => (Some(DomRoot::from_ref(self_node)), None),
+                AdjacentPosition::AfterEnd => {
+                    (self_node.GetParentNode(), self_node.GetNextSibling())
+                },
+            };
+            if let Some(parent) = parent {
+                context.insert_fragment_in_chunks(text, chunk_size, &parent, child.as_deref());
+            }
+            return Ok(());
+        }
+
         // Step 3.
         let fragment = context.parse_fragment(text)?;
 
@@ -3036,6 +3144,7 @@ impl VirtualMethods for Element {
                     }
                 }
             },
+            &local_name!("blocking") => doc.update_render_blocking(),
             _ => {
                 // FIXME(emilio): This is pretty dubious, and should be done in
                 // the relevant super-classes.
@@ -3142,6 +3251,7 @@ impl VirtualMethods for Element {
         if let Some(ref value) = self.name_attribute() {
             doc.unregister_element_name(self, value.clone());
         }
+        doc.update_render_blocking();
         // This is used for layout optimization.
         doc.decrement_dom_count();
     }
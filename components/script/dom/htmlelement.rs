@@ -370,8 +370,21 @@ impl HTMLElementMethods for HTMLElement {
     fn Focus(&self) {
         // TODO: Mark the element as locked for focus and run the focusing steps.
         // https://html.spec.whatwg.org/multipage/#focusing-steps
+        let element = self.upcast::<Element>();
+        let delegates_focus = element
+            .shadow_root()
+            .map_or(false, |shadow_root| shadow_root.delegates_focus());
+        let target = if delegates_focus {
+            // Nothing is focused if the shadow tree has nothing focusable.
+            match element.focus_delegate() {
+                Some(delegate) => delegate,
+                None => return,
+            }
+        } else {
+            DomRoot::from_ref(element)
+        };
         let document = document_from_node(self);
-        document.request_focus(Some(self.upcast()), FocusType::Element);
+        document.request_focus(Some(&target), FocusType::Element);
     }
 
     // https://html.spec.whatwg.org/multipage/#dom-blur
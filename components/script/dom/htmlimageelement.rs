@@ -52,6 +52,7 @@ use crate::dom::activation::Activatable;
 use crate::dom::attr::Attr;
 use crate::dom::bindings::cell::{DomRefCell, RefMut};
 use crate::dom::bindings::codegen::Bindings::DOMRectBinding::DOMRect_Binding::DOMRectMethods;
+use crate::dom::bindings::codegen::Bindings::DocumentBinding::DocumentMethods;
 use crate::dom::bindings::codegen::Bindings::ElementBinding::Element_Binding::ElementMethods;
 use crate::dom::bindings::codegen::Bindings::HTMLImageElementBinding::HTMLImageElementMethods;
 use crate::dom::bindings::codegen::Bindings::MouseEventBinding::MouseEventMethods;
@@ -169,6 +170,9 @@ pub struct HTMLImageElement {
     #[ignore_malloc_size_of = "SourceSet"]
     source_set: DomRefCell<SourceSet>,
     last_selected_source: DomRefCell<Option<USVString>>,
+    /// The MIME type of the image, when this is the image of a synthesized
+    /// image document, whose title then describes the image once loaded.
+    image_viewer_mime_type: DomRefCell<Option<DOMString>>,
 }
 
 impl HTMLImageElement {
@@ -405,6 +409,10 @@ impl HTMLImageElement {
             },
         );
 
+        if let Some(parser) = document.get_current_parser() {
+            parser.adopt_prefetch(&request.url);
+        }
+
         // This is a background load because the load blocker already fulfills the
         // purpose of delaying the document's load event.
         document
@@ -412,6 +420,35 @@ impl HTMLImageElement {
             .fetch_async_background(request, action_sender);
     }
 
+    /// Makes this the image of a synthesized image document of `mime_type`,
+    /// whose title gets the type and natural dimensions of the image once it
+    /// is loaded.
+    pub fn set_image_viewer_mime_type(&self, mime_type: DOMString) {
+        *self.image_viewer_mime_type.borrow_mut() = Some(mime_type);
+    }
+
+    /// Describes the image in the title of its image document, if this is the
+    /// image of one. The title is left as is if the dimensions are unknown.
+    fn describe_in_image_viewer(&self) {
+        let mime_type = match *self.image_viewer_mime_type.borrow() {
+            Some(ref mime_type) => mime_type.clone(),
+            None => return,
+        };
+        let (width, height) = (self.NaturalWidth(), self.NaturalHeight());
+        if width == 0 || height == 0 {
+            return;
+        }
+        let document = document_from_node(self);
+        let title = format!(
+            "{} ({}, {} × {} pixels)",
+            document.url(),
+            mime_type,
+            width,
+            height
+        );
+        document.SetTitle(DOMString::from(title));
+    }
+
     // Steps common to when an image has been loaded.
     fn handle_loaded_image(&self, image: Arc<Image>, url: ServoUrl) {
         self.current_request.borrow_mut().metadata = Some(ImageMetadata {
@@ -473,6 +510,7 @@ impl HTMLImageElement {
 
         // Fire image.onload and loadend
         if trigger_image_load {
+            self.describe_in_image_viewer();
             // TODO: https://html.spec.whatwg.org/multipage/#fire-a-progress-event-or-event
             self.upcast::<EventTarget>().fire_event(atom!("load"));
             self.upcast::<EventTarget>().fire_event(atom!("loadend"));
@@ -993,6 +1031,7 @@ impl HTMLImageElement {
                                 current_request.source_url = Some(USVString(src));
                             }
                             // TODO: restart animation, if set.
+                            this.describe_in_image_viewer();
                             this.upcast::<EventTarget>().fire_event(atom!("load"));
                         }),
                         window.upcast(),
@@ -1200,6 +1239,7 @@ impl HTMLImageElement {
                 this.upcast::<Node>().dirty(NodeDamage::OtherNodeDamage);
 
                 // Step 15.7
+                this.describe_in_image_viewer();
                 this.upcast::<EventTarget>().fire_event(atom!("load"));
             }),
             window.upcast(),
@@ -1247,6 +1287,7 @@ impl HTMLImageElement {
             generation: Default::default(),
             source_set: DomRefCell::new(SourceSet::new()),
             last_selected_source: DomRefCell::new(None),
+            image_viewer_mime_type: DomRefCell::new(None),
         }
     }
 
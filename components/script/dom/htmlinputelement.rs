@@ -419,6 +419,12 @@ impl HTMLInputElement {
         self.sanitization_flag.set(false);
     }
 
+    /// Enables sanitization again without sanitizing the current value, which
+    /// keeps the value the content attributes gave.
+    pub fn enable_sanitization_keeping_value(&self) {
+        self.sanitization_flag.set(true);
+    }
+
     pub fn enable_sanitization(&self) {
         self.sanitization_flag.set(true);
         let mut textinput = self.textinput.borrow_mut();
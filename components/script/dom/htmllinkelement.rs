@@ -126,6 +126,11 @@ impl HTMLLinkElement {
         stylesheets_owner.add_stylesheet(self.upcast(), s);
     }
 
+    /// Whether the stylesheet, or one of its imports, is still being fetched.
+    pub fn is_loading_stylesheet(&self) -> bool {
+        self.pending_loads.get() > 0
+    }
+
     pub fn get_stylesheet(&self) -> Option<Arc<Stylesheet>> {
         self.stylesheet.borrow().clone()
     }
@@ -259,6 +264,9 @@ impl VirtualMethods for HTMLLinkElement {
                 },
                 _ => {},
             }
+
+            // No stylesheet may be fetched after all.
+            document_from_node(self).update_render_blocking();
         }
     }
 
@@ -983,6 +983,7 @@ impl HTMLScriptElement {
     pub fn execute(&self, result: ScriptResult) {
         // Step 1.
         let doc = document_from_node(self);
+        doc.update_render_blocking();
         if self.parser_inserted.get() && &*doc != &*self.parser_document {
             return;
         }
@@ -1135,7 +1136,6 @@ impl HTMLScriptElement {
             .dom_manipulation_task_source()
             .queue_simple_event(self.upcast(), atom!("error"), &window);
     }
-
     pub fn dispatch_load_event(&self) {
         self.dispatch_event(
             atom!("load"),
@@ -1216,6 +1216,10 @@ impl HTMLScriptElement {
         self.parser_inserted.get()
     }
 
+    pub fn get_already_started(&self) -> bool {
+        self.already_started.get()
+    }
+
     pub fn set_already_started(&self, already_started: bool) {
         self.already_started.set(already_started);
     }
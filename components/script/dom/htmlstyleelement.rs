@@ -156,6 +156,11 @@ impl HTMLStyleElement {
         stylesheets_owner.add_stylesheet(self.upcast(), s);
     }
 
+    /// Whether the stylesheet is still being parsed, or its imports fetched.
+    pub fn is_loading_stylesheet(&self) -> bool {
+        self.in_stack_of_open_elements.get() || self.pending_loads.get() > 0
+    }
+
     pub fn get_stylesheet(&self) -> Option<Arc<Stylesheet>> {
         self.stylesheet.borrow().clone()
     }
@@ -223,6 +228,7 @@ impl VirtualMethods for HTMLStyleElement {
         if self.upcast::<Node>().is_in_doc() {
             self.parse_own_css();
         }
+        document_from_node(self).update_render_blocking();
     }
 
     fn unbind_from_tree(&self, context: &UnbindContext) {
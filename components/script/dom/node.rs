@@ -98,7 +98,7 @@ use crate::dom::nodelist::NodeList;
 use crate::dom::processinginstruction::ProcessingInstruction;
 use crate::dom::range::WeakRangeVec;
 use crate::dom::raredata::NodeRareData;
-use crate::dom::shadowroot::{LayoutShadowRootHelpers, ShadowRoot};
+use crate::dom::shadowroot::{IsUserAgentWidget, LayoutShadowRootHelpers, ShadowRoot};
 use crate::dom::stylesheetlist::StyleSheetListOwner;
 use crate::dom::svgsvgelement::{LayoutSVGSVGElementHelpers, SVGSVGElement};
 use crate::dom::text::Text;
@@ -2362,6 +2362,30 @@ impl Node {
         // Step 5: cloning steps.
         vtable_for(&node).cloning_steps(&copy, maybe_doc, clone_children);
 
+        // A clonable shadow root is cloned along with its host, children
+        // included, whether the children of the host are cloned or not.
+        if let Some(shadow_root) = node.downcast::<Element>().and_then(Element::shadow_root) {
+            let copy_shadow_root = copy
+                .downcast::<Element>()
+                .filter(|_| shadow_root.is_clonable())
+                .and_then(|copy| copy.attach_shadow(IsUserAgentWidget::No).ok());
+            if let Some(copy_shadow_root) = copy_shadow_root {
+                copy_shadow_root.set_declarative(
+                    shadow_root.Mode(),
+                    true,
+                    shadow_root.delegates_focus(),
+                );
+                for child in shadow_root.upcast::<Node>().children() {
+                    let child_copy =
+                        Node::clone(&child, Some(&document), CloneChildrenFlag::CloneChildren);
+                    // The copies of the children of a shadow root can always
+                    // be children of a new shadow root.
+                    Node::pre_insert(&child_copy, copy_shadow_root.upcast(), None)
+                        .expect("Cloned shadow root child can't be inserted");
+                }
+            }
+        }
+
         // Step 6.
         if clone_children == CloneChildrenFlag::CloneChildren {
             for child in node.children() {
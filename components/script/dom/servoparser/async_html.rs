@@ -5,12 +5,13 @@
 #![allow(crown::unrooted_must_root)]
 
 use std::borrow::Cow;
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::collections::vec_deque::VecDeque;
 use std::collections::HashMap;
-use std::thread;
+use std::{mem, thread};
 
 use crossbeam_channel::{unbounded, Receiver, Sender};
+use encoding_rs::Encoding;
 use html5ever::buffer_queue::BufferQueue;
 use html5ever::tendril::fmt::UTF8;
 use html5ever::tendril::{SendTendril, StrTendril, Tendril};
@@ -22,6 +23,7 @@ use html5ever::tree_builder::{
 use html5ever::{
     local_name, namespace_url, ns, Attribute as HtmlAttribute, ExpandedName, QualName,
 };
+use servo_config::pref;
 use servo_url::ServoUrl;
 use style::context::QuirksMode as ServoQuirksMode;
 
@@ -39,7 +41,15 @@ use crate::dom::htmlscriptelement::HTMLScriptElement;
 use crate::dom::htmltemplateelement::HTMLTemplateElement;
 use crate::dom::node::Node;
 use crate::dom::processinginstruction::ProcessingInstruction;
-use crate::dom::servoparser::{create_element_for_token, ElementAttribute, ParsingAlgorithm};
+use crate::dom::servoparser::{
+    as_body_child, as_color_scheme_meta, as_encoding_declaration, as_head_element, cap_attributes,
+    count_newlines, create_element_for_token, doctype_mismatches_document, in_same_tree,
+    is_blocked_element, is_legacy_doctype, is_obsolete_element, max_attributes_per_element,
+    unless_blocked, BodyChildCompleteObserver, CommentTransformer, DeprecatedFeature,
+    DeprecationWarning, DoctypeObserver, ElementAttribute, ElementBlocker, HeadCompleteObserver,
+    LineTracker, ParserElementCounters, ParsingAlgorithm, RawAttributesObserver, StrictParseError,
+    TextExtractor,
+};
 use crate::dom::virtualmethods::vtable_for;
 
 type ParseNodeId = usize;
@@ -57,7 +67,7 @@ enum NodeOrText {
     Text(String),
 }
 
-#[derive(JSTraceable, MallocSizeOf)]
+#[derive(Clone, JSTraceable, MallocSizeOf)]
 struct Attribute {
     #[no_trace]
     name: QualName,
@@ -101,6 +111,7 @@ enum ParseOperation {
         name: String,
         public_id: String,
         system_id: String,
+        current_line: u64,
     },
 
     AddAttrsIfMissing {
@@ -140,6 +151,11 @@ enum ParseOperation {
         #[no_trace]
         mode: ServoQuirksMode,
     },
+
+    ParseError {
+        line: u64,
+        message: String,
+    },
 }
 
 #[derive(MallocSizeOf)]
@@ -158,6 +174,7 @@ enum ToTokenizerMsg {
 
     // From Sink
     ProcessOperation(ParseOperation),
+    ProcessOperations(Vec<ParseOperation>),
 }
 
 #[derive(MallocSizeOf)]
@@ -168,6 +185,19 @@ enum ToHtmlTokenizerMsg {
     },
     End,
     SetPlainTextState,
+    InsertScriptInput {
+        newlines: u64,
+    },
+}
+
+fn html_attributes(attrs: &[Attribute]) -> Vec<HtmlAttribute> {
+    attrs
+        .iter()
+        .map(|attr| HtmlAttribute {
+            name: attr.name.clone(),
+            value: StrTendril::from(attr.value.clone()),
+        })
+        .collect()
 }
 
 fn create_buffer_queue(mut buffers: VecDeque<SendTendril<UTF8>>) -> BufferQueue {
@@ -178,6 +208,53 @@ fn create_buffer_queue(mut buffers: VecDeque<SendTendril<UTF8>>) -> BufferQueue
     buffer_queue
 }
 
+/// The number of bytes of input the main thread hands to the parser thread
+/// at once, see `dom.servoparser.async_html_tokenizer.feed_size`, or zero to
+/// hand it all the input it has. The parser thread runs ahead of the main
+/// thread, so smaller batches bound the operations queued up for the main
+/// thread, and the memory they take, but at the end of each batch the parser
+/// thread waits for the main thread to catch up, which costs throughput.
+fn feed_size() -> usize {
+    pref!(dom.servoparser.async_html_tokenizer.feed_size).max(0) as usize
+}
+
+/// The number of tree operations the parser thread sends to the main thread
+/// at once, see `dom.servoparser.async_html_tokenizer.operation_batch_size`.
+/// Any number below two sends each operation by itself, as soon as it's
+/// created. Larger batches save the main thread from waking up for every
+/// operation, but it gets to them later, and while it waits for a batch to
+/// be full it does nothing else.
+fn operation_batch_size() -> usize {
+    pref!(dom.servoparser.async_html_tokenizer.operation_batch_size).max(1) as usize
+}
+
+/// Takes the input the parser thread tokenizes next off the front of
+/// `input`: all of it if `feed_size` is zero, and otherwise `feed_size`
+/// bytes, or slightly more for the last character to be whole.
+pub fn take_feed_batch(input: &mut BufferQueue, feed_size: usize) -> Vec<StrTendril> {
+    let mut batch = vec![];
+    let mut len = 0;
+    while let Some(mut tendril) = input.pop_front() {
+        if feed_size > 0 && len + tendril.len() > feed_size {
+            let mut split = feed_size - len;
+            while !tendril.is_char_boundary(split) {
+                split += 1;
+            }
+            let rest_len = (tendril.len() - split) as u32;
+            if rest_len > 0 {
+                input.push_front(tendril.subtendril(split as u32, rest_len));
+                tendril.pop_back(rest_len);
+            }
+        }
+        len += tendril.len();
+        batch.push(tendril);
+        if feed_size > 0 && len >= feed_size {
+            break;
+        }
+    }
+    batch
+}
+
 // The async HTML Tokenizer consists of two separate types working together: the Tokenizer
 // (defined below), which lives on the main thread, and the HtmlTokenizer, defined in html5ever, which
 // lives on the parser thread.
@@ -218,6 +295,53 @@ pub struct Tokenizer {
     #[no_trace]
     url: ServoUrl,
     parsing_algorithm: ParsingAlgorithm,
+    #[ignore_malloc_size_of = "Defined in style"]
+    #[no_trace]
+    quirks_mode_override: Option<ServoQuirksMode>,
+    #[ignore_malloc_size_of = "Function pointer"]
+    #[no_trace]
+    comment_transformer: Option<CommentTransformer>,
+    #[ignore_malloc_size_of = "Function pointer"]
+    #[no_trace]
+    raw_attributes_observer: Option<RawAttributesObserver>,
+    #[ignore_malloc_size_of = "Function pointer"]
+    #[no_trace]
+    element_blocker: Option<ElementBlocker>,
+    /// The elements blocked so far.
+    blocked_elements: Vec<Dom<Node>>,
+    /// Whether scripts never block the parser, see
+    /// `ServoParser::set_script_blocking_disabled`.
+    script_blocking_disabled: bool,
+    strict_parse_error: Option<StrictParseError>,
+    #[ignore_malloc_size_of = "Function pointer"]
+    #[no_trace]
+    doctype_observer: Option<DoctypeObserver>,
+    element_counters: ParserElementCounters,
+    /// The number of nodes inserted since the tokenizer was last asked.
+    inserted_nodes: usize,
+    /// Whether to sanitize the values of `<input>` elements.
+    sanitize_input_values: bool,
+    #[ignore_malloc_size_of = "Function pointer"]
+    #[no_trace]
+    head_complete_observer: Option<HeadCompleteObserver>,
+    /// Whether the head has been popped already.
+    head_complete: bool,
+    #[ignore_malloc_size_of = "Function pointer"]
+    #[no_trace]
+    body_child_complete_observer: Option<BodyChildCompleteObserver>,
+    /// The text inserted so far, if it is being extracted.
+    text_extractor: Option<TextExtractor>,
+    /// The line of the last element or doctype created.
+    current_line: u64,
+    deprecation_warnings: Vec<DeprecationWarning>,
+    /// See `feed_size`.
+    feed_size: usize,
+    /// The encoding declared by the first `<meta>` declaring one, until the
+    /// parser takes it.
+    #[no_trace]
+    declared_encoding: Option<&'static Encoding>,
+    /// Whether a `<meta>` declared an encoding already.
+    encoding_declared: bool,
 }
 
 impl Tokenizer {
@@ -243,6 +367,26 @@ impl Tokenizer {
             nodes: HashMap::new(),
             url: url,
             parsing_algorithm: algorithm,
+            quirks_mode_override: None,
+            comment_transformer: None,
+            raw_attributes_observer: None,
+            element_blocker: None,
+            blocked_elements: vec![],
+            script_blocking_disabled: false,
+            strict_parse_error: None,
+            doctype_observer: None,
+            element_counters: Default::default(),
+            inserted_nodes: 0,
+            sanitize_input_values: true,
+            head_complete_observer: None,
+            head_complete: false,
+            body_child_complete_observer: None,
+            text_extractor: None,
+            current_line: 1,
+            deprecation_warnings: vec![],
+            feed_size: feed_size(),
+            declared_encoding: None,
+            encoding_declared: false,
         };
         tokenizer.insert_node(0, Dom::from_ref(document.upcast()));
 
@@ -285,11 +429,35 @@ impl Tokenizer {
 
     #[must_use]
     pub fn feed(&mut self, input: &mut BufferQueue) -> TokenizerResult<DomRoot<HTMLScriptElement>> {
-        let mut send_tendrils = VecDeque::new();
-        while let Some(str) = input.pop_front() {
-            send_tendrils.push_back(SendTendril::from(str));
+        loop {
+            let batch = take_feed_batch(input, self.feed_size);
+            let result = self.feed_batch(batch.into_iter().map(SendTendril::from).collect());
+            let (result, updated_input) = match result {
+                (TokenizerResult::Done, updated_input) if !input.is_empty() => {
+                    (None, updated_input)
+                },
+                (result, updated_input) => (Some(result), updated_input),
+            };
+            // What the parser thread didn't tokenize comes before the input
+            // it wasn't given yet.
+            for tendril in updated_input.into_iter().rev() {
+                input.push_front(StrTendril::from(tendril));
+            }
+            if let Some(result) = result {
+                return result;
+            }
         }
+    }
 
+    /// Has the parser thread tokenize `send_tendrils`, returning the result
+    /// along with the input it didn't tokenize.
+    fn feed_batch(
+        &mut self,
+        send_tendrils: VecDeque<SendTendril<UTF8>>,
+    ) -> (
+        TokenizerResult<DomRoot<HTMLScriptElement>>,
+        VecDeque<SendTendril<UTF8>>,
+    ) {
         // Send message to parser thread, asking it to start reading from the input.
         // Parser operation messages will be sent to main thread as they are evaluated.
         self.html_tokenizer_sender
@@ -305,19 +473,39 @@ impl Tokenizer {
                 .expect("Unexpected channel panic in main thread.")
             {
                 ToTokenizerMsg::ProcessOperation(parse_op) => self.process_operation(parse_op),
+                ToTokenizerMsg::ProcessOperations(parse_ops) => {
+                    parse_ops
+                        .into_iter()
+                        .for_each(|parse_op| self.process_operation(parse_op));
+                },
                 ToTokenizerMsg::TokenizerResultDone { updated_input } => {
-                    let buffer_queue = create_buffer_queue(updated_input);
-                    *input = buffer_queue;
-                    return TokenizerResult::Done;
+                    return (TokenizerResult::Done, updated_input);
+                },
+                ToTokenizerMsg::TokenizerResultScript {
+                    script,
+                    updated_input,
+                } if self.script_blocking_disabled => {
+                    // The parser thread waits for the script, which is
+                    // skipped, to go on with the rest of the input.
+                    let script = self.get_node(&script.id);
+                    if let Some(script) = script.downcast::<HTMLScriptElement>() {
+                        script.set_already_started(true);
+                    }
+                    self.html_tokenizer_sender
+                        .send(ToHtmlTokenizerMsg::Feed {
+                            input: updated_input,
+                        })
+                        .unwrap();
                 },
                 ToTokenizerMsg::TokenizerResultScript {
                     script,
                     updated_input,
                 } => {
-                    let buffer_queue = create_buffer_queue(updated_input);
-                    *input = buffer_queue;
                     let script = self.get_node(&script.id);
-                    return TokenizerResult::Script(DomRoot::from_ref(script.downcast().unwrap()));
+                    return (
+                        TokenizerResult::Script(DomRoot::from_ref(script.downcast().unwrap())),
+                        updated_input,
+                    );
                 },
                 ToTokenizerMsg::End => unreachable!(),
             };
@@ -335,6 +523,11 @@ impl Tokenizer {
                 .expect("Unexpected channel panic in main thread.")
             {
                 ToTokenizerMsg::ProcessOperation(parse_op) => self.process_operation(parse_op),
+                ToTokenizerMsg::ProcessOperations(parse_ops) => {
+                    parse_ops
+                        .into_iter()
+                        .for_each(|parse_op| self.process_operation(parse_op));
+                },
                 ToTokenizerMsg::End => return,
                 _ => unreachable!(),
             };
@@ -351,6 +544,101 @@ impl Tokenizer {
             .unwrap();
     }
 
+    pub fn parsing_algorithm(&self) -> ParsingAlgorithm {
+        self.parsing_algorithm
+    }
+
+    pub fn set_quirks_mode_override(&mut self, mode: ServoQuirksMode) {
+        self.quirks_mode_override = Some(mode);
+    }
+
+    pub fn set_comment_transformer(&mut self, transformer: Option<CommentTransformer>) {
+        self.comment_transformer = transformer;
+    }
+
+    pub fn set_raw_attributes_observer(&mut self, observer: Option<RawAttributesObserver>) {
+        self.raw_attributes_observer = observer;
+    }
+
+    pub fn set_element_blocker(&mut self, blocker: Option<ElementBlocker>) {
+        self.element_blocker = blocker;
+    }
+
+    pub fn set_script_blocking_disabled(&mut self, disabled: bool) {
+        self.script_blocking_disabled = disabled;
+    }
+
+    pub fn set_doctype_observer(&mut self, observer: Option<DoctypeObserver>) {
+        self.doctype_observer = observer;
+    }
+
+    pub fn set_head_complete_observer(&mut self, observer: Option<HeadCompleteObserver>) {
+        self.head_complete_observer = observer;
+    }
+
+    pub fn set_body_child_complete_observer(
+        &mut self,
+        observer: Option<BodyChildCompleteObserver>,
+    ) {
+        self.body_child_complete_observer = observer;
+    }
+
+    pub fn insert_script_input(&mut self, input: &str) {
+        self.html_tokenizer_sender
+            .send(ToHtmlTokenizerMsg::InsertScriptInput {
+                newlines: count_newlines(input),
+            })
+            .unwrap();
+    }
+
+    pub fn enable_text_extraction(&mut self) {
+        self.text_extractor.get_or_insert_with(Default::default);
+    }
+
+    pub fn extracted_text(&self) -> Option<String> {
+        let text_extractor = self.text_extractor.as_ref();
+        text_extractor.map(|text_extractor| text_extractor.text().to_owned())
+    }
+
+    pub fn warn_deprecated(&mut self, feature: DeprecatedFeature) {
+        super::warn_deprecated(&mut self.deprecation_warnings, self.current_line, feature);
+    }
+
+    pub fn deprecation_warnings(&self) -> &[DeprecationWarning] {
+        &self.deprecation_warnings
+    }
+
+    pub fn set_input_value_sanitization(&mut self, enabled: bool) {
+        self.sanitize_input_values = enabled;
+    }
+
+    pub fn element_counters(&self) -> ParserElementCounters {
+        self.element_counters
+    }
+
+    pub fn take_inserted_node_count(&mut self) -> usize {
+        mem::take(&mut self.inserted_nodes)
+    }
+
+    pub fn take_strict_parse_error(&mut self) -> Option<StrictParseError> {
+        self.strict_parse_error.take()
+    }
+
+    pub fn take_declared_encoding(&mut self) -> Option<&'static Encoding> {
+        self.declared_encoding.take()
+    }
+
+    fn observe_raw_attributes(&self, element: &Element, attrs: &[Attribute]) {
+        if let Some(observer) = self.raw_attributes_observer {
+            observer(element, &html_attributes(attrs));
+        }
+    }
+
+    fn is_blocked(&self, name: &QualName, attrs: &[Attribute]) -> bool {
+        self.element_blocker.is_some() &&
+            is_blocked_element(self.element_blocker, name, &html_attributes(attrs))
+    }
+
     fn insert_node(&mut self, id: ParseNodeId, node: Dom<Node>) {
         assert!(self.nodes.insert(id, node).is_none());
     }
@@ -366,12 +654,17 @@ impl Tokenizer {
             },
             NodeOrText::Text(text) => HtmlNodeOrText::AppendText(Tendril::from(text)),
         };
-        let sibling = &**self.get_node(&sibling);
-        let parent = &*sibling
+        let node = unless_blocked(node, &self.blocked_elements, &self.document);
+        let sibling = DomRoot::from_ref(&**self.get_node(&sibling));
+        let parent = sibling
             .GetParentNode()
             .expect("append_before_sibling called on node without parent");
 
-        super::insert(parent, Some(sibling), node, self.parsing_algorithm);
+        if let Some(ref mut text_extractor) = self.text_extractor {
+            text_extractor.extract_inserted_text(&parent, &node);
+        }
+        super::insert(&parent, Some(&*sibling), node, self.parsing_algorithm);
+        self.inserted_nodes += 1;
     }
 
     fn append(&mut self, parent: ParseNodeId, node: NodeOrText) {
@@ -381,9 +674,14 @@ impl Tokenizer {
             },
             NodeOrText::Text(text) => HtmlNodeOrText::AppendText(Tendril::from(text)),
         };
+        let node = unless_blocked(node, &self.blocked_elements, &self.document);
 
-        let parent = &**self.get_node(&parent);
-        super::insert(parent, None, node, self.parsing_algorithm);
+        let parent = DomRoot::from_ref(&**self.get_node(&parent));
+        if let Some(ref mut text_extractor) = self.text_extractor {
+            text_extractor.extract_inserted_text(&parent, &node);
+        }
+        super::insert(&parent, None, node, self.parsing_algorithm);
+        self.inserted_nodes += 1;
     }
 
     fn has_parent_node(&self, node: ParseNodeId) -> bool {
@@ -391,12 +689,7 @@ impl Tokenizer {
     }
 
     fn same_tree(&self, x: ParseNodeId, y: ParseNodeId) -> bool {
-        let x = self.get_node(&x);
-        let y = self.get_node(&y);
-
-        let x = x.downcast::<Element>().expect("Element node expected");
-        let y = y.downcast::<Element>().expect("Element node expected");
-        x.is_in_same_home_subtree(y)
+        in_same_tree(self.get_node(&x), self.get_node(&y))
     }
 
     fn process_operation(&mut self, op: ParseOperation) {
@@ -418,20 +711,56 @@ impl Tokenizer {
                 attrs,
                 current_line,
             } => {
-                let attrs = attrs
-                    .into_iter()
-                    .map(|attr| ElementAttribute::new(attr.name, DOMString::from(attr.value)))
-                    .collect();
+                self.current_line = current_line;
+                if name.ns == ns!(html) && is_obsolete_element(&name.local) {
+                    let feature = DeprecatedFeature::ObsoleteElement(name.local.to_string());
+                    self.warn_deprecated(feature);
+                }
+                let blocked = self.is_blocked(&name, &attrs);
+                let raw_attrs = self
+                    .raw_attributes_observer
+                    .filter(|_| !blocked)
+                    .map(|_| attrs.clone());
+                let attrs = if blocked {
+                    vec![]
+                } else {
+                    attrs
+                        .into_iter()
+                        .map(|attr| ElementAttribute::new(attr.name, DOMString::from(attr.value)))
+                        .collect()
+                };
                 let element = create_element_for_token(
                     name,
                     attrs,
                     &*self.document,
                     ElementCreator::ParserCreated(current_line),
                     ParsingAlgorithm::Normal,
+                    &mut self.element_counters,
+                    self.sanitize_input_values,
                 );
+                if let Some(raw_attrs) = raw_attrs {
+                    self.observe_raw_attributes(&element, &raw_attrs);
+                }
+                if blocked {
+                    if let Some(script) = element.downcast::<HTMLScriptElement>() {
+                        script.set_already_started(true);
+                    }
+                    self.blocked_elements.push(Dom::from_ref(element.upcast()));
+                }
                 self.insert_node(node, Dom::from_ref(element.upcast()));
             },
             ParseOperation::CreateComment { text, node } => {
+                let text = match self.comment_transformer {
+                    None => text,
+                    Some(transform) => match transform(&StrTendril::from(text)) {
+                        Some(text) => text,
+                        None => {
+                            let fragment = super::dropped_comment(document);
+                            self.insert_node(node, Dom::from_ref(fragment.upcast()));
+                            return;
+                        },
+                    },
+                };
                 let comment = Comment::new(DOMString::from(text), document, None);
                 self.insert_node(node, Dom::from_ref(&comment.upcast()));
             },
@@ -456,7 +785,19 @@ impl Tokenizer {
                 name,
                 public_id,
                 system_id,
+                current_line,
             } => {
+                self.current_line = current_line;
+                if let Some(observer) = self.doctype_observer {
+                    observer(&name, &public_id, &system_id);
+                }
+                if doctype_mismatches_document(document.is_html_document(), &public_id, &system_id)
+                {
+                    document.set_doctype_mismatch(true);
+                }
+                if document.is_html_document() && is_legacy_doctype(&name, &public_id, &system_id) {
+                    self.warn_deprecated(DeprecatedFeature::LegacyDoctype);
+                }
                 let doctype = DocumentType::new(
                     DOMString::from(String::from(name)),
                     Some(DOMString::from(public_id)),
@@ -474,6 +815,7 @@ impl Tokenizer {
                     .get_node(&target)
                     .downcast::<Element>()
                     .expect("tried to set attrs on non-Element in HTML parsing");
+                self.observe_raw_attributes(elem, &attrs);
                 for attr in attrs {
                     elem.set_attribute_from_parser(attr.name, DOMString::from(attr.value), None);
                 }
@@ -524,7 +866,30 @@ impl Tokenizer {
                 }
             },
             ParseOperation::Pop { node } => {
-                vtable_for(self.get_node(&node)).pop();
+                let node = DomRoot::from_ref(&**self.get_node(&node));
+                if !self.head_complete {
+                    if let Some(head) = as_head_element(&node) {
+                        self.head_complete = true;
+                        if let Some(observer) = self.head_complete_observer {
+                            observer(head);
+                        }
+                    }
+                }
+                if let Some(observer) = self.body_child_complete_observer {
+                    if let Some(child) = as_body_child(document, &node) {
+                        observer(child);
+                    }
+                }
+                if self.parsing_algorithm == ParsingAlgorithm::Normal && node.is_in_doc() {
+                    if let Some(schemes) = as_color_scheme_meta(&node) {
+                        document.declare_supported_color_schemes(schemes);
+                    }
+                    if !self.encoding_declared && document.is_html_document() {
+                        self.declared_encoding = as_encoding_declaration(&node);
+                        self.encoding_declared = self.declared_encoding.is_some();
+                    }
+                }
+                vtable_for(&node).pop();
             },
             ParseOperation::CreatePI { node, target, data } => {
                 let pi = ProcessingInstruction::new(
@@ -535,7 +900,16 @@ impl Tokenizer {
                 self.insert_node(node, Dom::from_ref(pi.upcast()));
             },
             ParseOperation::SetQuirksMode { mode } => {
-                document.set_quirks_mode(mode);
+                if self.quirks_mode_override.is_none() {
+                    document.set_quirks_mode(mode);
+                }
+            },
+            ParseOperation::ParseError { line, message } => {
+                if self.parsing_algorithm == ParsingAlgorithm::Normal &&
+                    self.strict_parse_error.is_none()
+                {
+                    self.strict_parse_error = Some(StrictParseError { line, message });
+                }
             },
         }
     }
@@ -584,6 +958,9 @@ fn run(
                     updated_input.push_back(SendTendril::from(st));
                 }
 
+                // The main thread needs all the operations for the input
+                // tokenized so far before it goes on.
+                html_tokenizer.sink.sink.flush_operations();
                 let res = match res {
                     TokenizerResult::Done => ToTokenizerMsg::TokenizerResultDone { updated_input },
                     TokenizerResult::Script(script) => ToTokenizerMsg::TokenizerResultScript {
@@ -595,10 +972,15 @@ fn run(
             },
             ToHtmlTokenizerMsg::End => {
                 html_tokenizer.end();
+                html_tokenizer.sink.sink.flush_operations();
                 sender.send(ToTokenizerMsg::End).unwrap();
                 break;
             },
             ToHtmlTokenizerMsg::SetPlainTextState => html_tokenizer.set_plaintext_state(),
+            ToHtmlTokenizerMsg::InsertScriptInput { newlines } => {
+                let sink = &mut html_tokenizer.sink.sink;
+                sink.line_tracker.insert_script_newlines(newlines);
+            },
         };
     }
 }
@@ -611,16 +993,23 @@ struct ParseNodeData {
 
 pub struct Sink {
     current_line: u64,
+    line_tracker: LineTracker,
     parse_node_data: HashMap<ParseNodeId, ParseNodeData>,
     next_parse_node_id: Cell<ParseNodeId>,
     document_node: ParseNode,
     sender: Sender<ToTokenizerMsg>,
+    /// See `max_attributes_per_element`.
+    max_attributes: usize,
+    /// The operations not sent yet, see `operation_batch_size`.
+    pending_operations: RefCell<Vec<ParseOperation>>,
+    operation_batch_size: usize,
 }
 
 impl Sink {
     fn new(sender: Sender<ToTokenizerMsg>) -> Sink {
         let mut sink = Sink {
             current_line: 1,
+            line_tracker: Default::default(),
             parse_node_data: HashMap::new(),
             next_parse_node_id: Cell::new(1),
             document_node: ParseNode {
@@ -628,6 +1017,9 @@ impl Sink {
                 qual_name: None,
             },
             sender: sender,
+            max_attributes: max_attributes_per_element(),
+            pending_operations: RefCell::new(vec![]),
+            operation_batch_size: operation_batch_size(),
         };
         let data = ParseNodeData::default();
         sink.insert_parse_node_data(0, data);
@@ -646,11 +1038,45 @@ impl Sink {
     }
 
     fn send_op(&self, op: ParseOperation) {
+        if self.operation_batch_size == 1 {
+            self.sender
+                .send(ToTokenizerMsg::ProcessOperation(op))
+                .unwrap();
+            return;
+        }
+        let mut pending_operations = self.pending_operations.borrow_mut();
+        pending_operations.push(op);
+        if pending_operations.len() >= self.operation_batch_size {
+            drop(pending_operations);
+            self.flush_operations();
+        }
+    }
+
+    /// Sends the operations batched so far.
+    fn flush_operations(&self) {
+        let pending_operations = mem::take(&mut *self.pending_operations.borrow_mut());
+        if pending_operations.is_empty() {
+            return;
+        }
         self.sender
-            .send(ToTokenizerMsg::ProcessOperation(op))
+            .send(ToTokenizerMsg::ProcessOperations(pending_operations))
             .unwrap();
     }
 
+    /// Drops the attributes beyond the maximum number of attributes of an
+    /// element, with a parse error. The attributes an element already has are
+    /// only known to the main thread, so this only caps the attributes of a
+    /// single tag.
+    fn cap_attributes(&mut self, attrs: &mut Vec<HtmlAttribute>) {
+        let dropped = cap_attributes(attrs, 0, self.max_attributes);
+        if dropped > 0 {
+            self.parse_error(Cow::Owned(format!(
+                "Dropped {} attributes beyond the maximum of {} of an element",
+                dropped, self.max_attributes
+            )));
+        }
+    }
+
     fn insert_parse_node_data(&mut self, id: ParseNodeId, data: ParseNodeData) {
         assert!(self.parse_node_data.insert(id, data).is_none());
     }
@@ -712,9 +1138,10 @@ impl TreeSink for Sink {
     fn create_element(
         &mut self,
         name: QualName,
-        html_attrs: Vec<HtmlAttribute>,
+        mut html_attrs: Vec<HtmlAttribute>,
         _flags: ElementFlags,
     ) -> Self::Handle {
+        self.cap_attributes(&mut html_attrs);
         let mut node = self.new_parse_node();
         node.qual_name = Some(name.clone());
         {
@@ -811,6 +1238,12 @@ impl TreeSink for Sink {
 
     fn parse_error(&mut self, msg: Cow<'static, str>) {
         debug!("Parse error: {}", msg);
+        if pref!(dom.servoparser.strict.enabled) {
+            self.send_op(ParseOperation::ParseError {
+                line: self.current_line,
+                message: msg.into_owned(),
+            });
+        }
     }
 
     fn set_quirks_mode(&mut self, mode: QuirksMode) {
@@ -843,10 +1276,12 @@ impl TreeSink for Sink {
             name: String::from(name),
             public_id: String::from(public_id),
             system_id: String::from(system_id),
+            current_line: self.current_line,
         });
     }
 
-    fn add_attrs_if_missing(&mut self, target: &Self::Handle, html_attrs: Vec<HtmlAttribute>) {
+    fn add_attrs_if_missing(&mut self, target: &Self::Handle, mut html_attrs: Vec<HtmlAttribute>) {
+        self.cap_attributes(&mut html_attrs);
         let attrs = html_attrs
             .into_iter()
             .map(|attr| Attribute {
@@ -887,7 +1322,7 @@ impl TreeSink for Sink {
     }
 
     fn set_current_line(&mut self, line_number: u64) {
-        self.current_line = line_number;
+        self.current_line = self.line_tracker.set_tokenizer_line(line_number);
     }
 
     fn pop(&mut self, node: &Self::Handle) {
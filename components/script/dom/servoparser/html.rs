@@ -4,18 +4,22 @@
 
 #![allow(crown::unrooted_must_root)]
 
-use std::io;
+use std::{io, mem};
 
+use encoding_rs::Encoding;
 use html5ever::buffer_queue::BufferQueue;
 use html5ever::serialize::TraversalScope::IncludeNode;
 use html5ever::serialize::{AttrRef, Serialize, Serializer, TraversalScope};
 use html5ever::tokenizer::{Tokenizer as HtmlTokenizer, TokenizerOpts, TokenizerResult};
 use html5ever::tree_builder::{Tracer as HtmlTracer, TreeBuilder, TreeBuilderOpts};
-use html5ever::QualName;
+use html5ever::{local_name, namespace_url, ns, LocalName, QualName};
 use js::jsapi::JSTracer;
 use servo_url::ServoUrl;
+use style::context::QuirksMode;
 
 use crate::dom::bindings::codegen::Bindings::HTMLTemplateElementBinding::HTMLTemplateElementMethods;
+use crate::dom::bindings::codegen::Bindings::ShadowRootBinding::ShadowRootMode;
+use crate::dom::bindings::codegen::Bindings::ShadowRootBinding::ShadowRoot_Binding::ShadowRootMethods;
 use crate::dom::bindings::inheritance::{Castable, CharacterDataTypeId, NodeTypeId};
 use crate::dom::bindings::root::{Dom, DomRoot};
 use crate::dom::bindings::trace::{CustomTraceable, JSTraceable};
@@ -28,7 +32,12 @@ use crate::dom::htmlscriptelement::HTMLScriptElement;
 use crate::dom::htmltemplateelement::HTMLTemplateElement;
 use crate::dom::node::Node;
 use crate::dom::processinginstruction::ProcessingInstruction;
-use crate::dom::servoparser::{ParsingAlgorithm, Sink};
+use crate::dom::servoparser::{
+    max_attributes_per_element, BodyChildCompleteObserver, CommentTransformer, DeprecatedFeature,
+    DeprecationWarning, DoctypeObserver, ElementBlocker, HeadCompleteObserver,
+    ParserElementCounters, ParsingAlgorithm, RawAttributesObserver, Sink, StrictParseError,
+};
+use crate::dom::shadowroot::ShadowRoot;
 
 #[derive(JSTraceable, MallocSizeOf)]
 #[crown::unrooted_must_root_lint::must_root]
@@ -43,6 +52,22 @@ impl Tokenizer {
         url: ServoUrl,
         fragment_context: Option<super::FragmentContext>,
         parsing_algorithm: ParsingAlgorithm,
+    ) -> Self {
+        Self::with_scripting(document, url, fragment_context, parsing_algorithm, true)
+    }
+
+    /// Creates a tokenizer for a document that parses as if scripting was
+    /// disabled, and never runs the scripts it parses.
+    pub fn new_without_scripting(document: &Document, url: ServoUrl) -> Self {
+        Self::with_scripting(document, url, None, ParsingAlgorithm::Normal, false)
+    }
+
+    fn with_scripting(
+        document: &Document,
+        url: ServoUrl,
+        fragment_context: Option<super::FragmentContext>,
+        parsing_algorithm: ParsingAlgorithm,
+        scripting_enabled: bool,
     ) -> Self {
         let sink = Sink {
             base_url: url,
@@ -50,10 +75,34 @@ impl Tokenizer {
             current_line: 1,
             script: Default::default(),
             parsing_algorithm: parsing_algorithm,
+            quirks_mode_override: None,
+            comment_transformer: None,
+            raw_attributes_observer: None,
+            element_blocker: None,
+            blocked_elements: vec![],
+            strict_parse_error: None,
+            parse_errors: None,
+            doctype_observer: None,
+            element_counters: Default::default(),
+            inserted_nodes: 0,
+            sanitize_input_values: true,
+            max_attributes: max_attributes_per_element(),
+            declarative_shadow_roots: vec![],
+            head_complete_observer: None,
+            head_complete: false,
+            body_child_complete_observer: None,
+            text_extractor: None,
+            line_tracker: Default::default(),
+            deprecation_warnings: vec![],
+            scripting_enabled,
+            script_blocking_disabled: false,
+            declared_encoding: None,
+            encoding_declared: false,
         };
 
         let options = TreeBuilderOpts {
             ignore_missing_rules: true,
+            scripting_enabled,
             ..Default::default()
         };
 
@@ -78,6 +127,22 @@ impl Tokenizer {
         Tokenizer { inner: inner }
     }
 
+    /// A tokenizer to parse the document of this one again from the start,
+    /// set up like this one. The nodes this one inserted are left to the
+    /// caller to remove.
+    pub fn restarted(&self) -> Self {
+        let sink = &self.inner.sink.sink;
+        let mut tokenizer = Self::with_scripting(
+            &sink.document,
+            sink.base_url.clone(),
+            None,
+            sink.parsing_algorithm,
+            sink.scripting_enabled,
+        );
+        tokenizer.inner.sink.sink.configure_like(sink);
+        tokenizer
+    }
+
     #[must_use]
     pub fn feed(&mut self, input: &mut BufferQueue) -> TokenizerResult<DomRoot<HTMLScriptElement>> {
         match self.inner.feed(input) {
@@ -96,9 +161,101 @@ impl Tokenizer {
         &self.inner.sink.sink.base_url
     }
 
+    pub fn set_comment_transformer(&mut self, transformer: Option<CommentTransformer>) {
+        self.inner.sink.sink.comment_transformer = transformer;
+    }
+
+    pub fn set_raw_attributes_observer(&mut self, observer: Option<RawAttributesObserver>) {
+        self.inner.sink.sink.raw_attributes_observer = observer;
+    }
+
+    pub fn set_element_blocker(&mut self, blocker: Option<ElementBlocker>) {
+        self.inner.sink.sink.element_blocker = blocker;
+    }
+
+    pub fn set_script_blocking_disabled(&mut self, disabled: bool) {
+        self.inner.sink.sink.script_blocking_disabled = disabled;
+    }
+
+    pub fn set_doctype_observer(&mut self, observer: Option<DoctypeObserver>) {
+        self.inner.sink.sink.doctype_observer = observer;
+    }
+
+    #[cfg(feature = "testing")]
+    pub fn collect_parse_errors(&mut self) {
+        self.inner.sink.sink.parse_errors = Some(vec![]);
+    }
+
+    #[cfg(feature = "testing")]
+    pub fn take_parse_errors(&mut self) -> Vec<super::ParseErrorRecord> {
+        self.inner.sink.sink.parse_errors.take().unwrap_or_default()
+    }
+
+    pub fn set_head_complete_observer(&mut self, observer: Option<HeadCompleteObserver>) {
+        self.inner.sink.sink.head_complete_observer = observer;
+    }
+
+    pub fn set_body_child_complete_observer(
+        &mut self,
+        observer: Option<BodyChildCompleteObserver>,
+    ) {
+        self.inner.sink.sink.body_child_complete_observer = observer;
+    }
+
+    pub fn insert_script_input(&mut self, input: &str) {
+        self.inner.sink.sink.line_tracker.insert_script_input(input);
+    }
+
+    pub fn enable_text_extraction(&mut self) {
+        let sink = &mut self.inner.sink.sink;
+        sink.text_extractor.get_or_insert_with(Default::default);
+    }
+
+    pub fn extracted_text(&self) -> Option<String> {
+        let text_extractor = self.inner.sink.sink.text_extractor.as_ref();
+        text_extractor.map(|text_extractor| text_extractor.text().to_owned())
+    }
+
+    pub fn warn_deprecated(&mut self, feature: DeprecatedFeature) {
+        let sink = &mut self.inner.sink.sink;
+        super::warn_deprecated(&mut sink.deprecation_warnings, sink.current_line, feature);
+    }
+
+    pub fn deprecation_warnings(&self) -> &[DeprecationWarning] {
+        &self.inner.sink.sink.deprecation_warnings
+    }
+
+    pub fn set_input_value_sanitization(&mut self, enabled: bool) {
+        self.inner.sink.sink.sanitize_input_values = enabled;
+    }
+
+    pub fn element_counters(&self) -> ParserElementCounters {
+        self.inner.sink.sink.element_counters
+    }
+
+    pub fn take_inserted_node_count(&mut self) -> usize {
+        mem::take(&mut self.inner.sink.sink.inserted_nodes)
+    }
+
+    pub fn take_strict_parse_error(&mut self) -> Option<StrictParseError> {
+        self.inner.sink.sink.strict_parse_error.take()
+    }
+
+    pub fn take_declared_encoding(&mut self) -> Option<&'static Encoding> {
+        self.inner.sink.sink.declared_encoding.take()
+    }
+
     pub fn set_plaintext_state(&mut self) {
         self.inner.set_plaintext_state();
     }
+
+    pub fn parsing_algorithm(&self) -> ParsingAlgorithm {
+        self.inner.sink.sink.parsing_algorithm
+    }
+
+    pub fn set_quirks_mode_override(&mut self, mode: QuirksMode) {
+        self.inner.sink.sink.quirks_mode_override = Some(mode);
+    }
 }
 
 #[allow(unsafe_code)]
@@ -147,14 +304,47 @@ fn end_element<S: Serializer>(node: &Element, serializer: &mut S) -> io::Result<
     serializer.end_elem(name)
 }
 
+/// Serializes a declarative shadow root as the `<template shadowrootmode>`
+/// it was parsed from.
+fn start_shadow_root<S: Serializer>(
+    shadow_root: &ShadowRoot,
+    serializer: &mut S,
+) -> io::Result<()> {
+    let mode = match shadow_root.Mode() {
+        ShadowRootMode::Open => "open",
+        ShadowRootMode::Closed => "closed",
+    };
+    let mode_name = QualName::new(None, ns!(), LocalName::from("shadowrootmode"));
+    let clonable_name = QualName::new(None, ns!(), LocalName::from("shadowrootclonable"));
+    let delegates_focus_name =
+        QualName::new(None, ns!(), LocalName::from("shadowrootdelegatesfocus"));
+    let mut attrs: Vec<AttrRef> = vec![(&mode_name, mode)];
+    if shadow_root.is_clonable() {
+        attrs.push((&clonable_name, ""));
+    }
+    if shadow_root.delegates_focus() {
+        attrs.push((&delegates_focus_name, ""));
+    }
+    serializer.start_elem(template_name(), attrs.into_iter())
+}
+
+fn template_name() -> QualName {
+    QualName::new(None, ns!(html), local_name!("template"))
+}
+
 enum SerializationCommand {
     OpenElement(DomRoot<Element>),
     CloseElement(DomRoot<Element>),
+    OpenShadowRoot(DomRoot<ShadowRoot>),
+    CloseShadowRoot,
     SerializeNonelement(DomRoot<Node>),
 }
 
 struct SerializationIterator {
     stack: Vec<SerializationCommand>,
+    /// Whether declarative shadow roots are serialized, before the children
+    /// of their host.
+    include_shadow_roots: bool,
 }
 
 fn rev_children_iter(n: &Node) -> impl Iterator<Item = DomRoot<Node>> {
@@ -169,18 +359,33 @@ fn rev_children_iter(n: &Node) -> impl Iterator<Item = DomRoot<Node>> {
 }
 
 impl SerializationIterator {
-    fn new(node: &Node, skip_first: bool) -> SerializationIterator {
-        let mut ret = SerializationIterator { stack: vec![] };
+    fn new(node: &Node, skip_first: bool, include_shadow_roots: bool) -> SerializationIterator {
+        let mut ret = SerializationIterator {
+            stack: vec![],
+            include_shadow_roots,
+        };
         if skip_first || node.is::<DocumentFragment>() || node.is::<Document>() {
-            for c in rev_children_iter(node) {
-                ret.push_node(&*c);
-            }
+            ret.push_children(node);
         } else {
             ret.push_node(node);
         }
         ret
     }
 
+    fn push_children(&mut self, n: &Node) {
+        for c in rev_children_iter(n) {
+            self.push_node(&c);
+        }
+        let shadow_root = n
+            .downcast::<Element>()
+            .and_then(Element::shadow_root)
+            .filter(|shadow_root| self.include_shadow_roots && shadow_root.is_declarative());
+        if let Some(shadow_root) = shadow_root {
+            self.stack
+                .push(SerializationCommand::OpenShadowRoot(shadow_root));
+        }
+    }
+
     fn push_node(&mut self, n: &Node) {
         match n.downcast::<Element>() {
             Some(e) => self
@@ -199,69 +404,114 @@ impl Iterator for SerializationIterator {
     fn next(&mut self) -> Option<SerializationCommand> {
         let res = self.stack.pop();
 
-        if let Some(SerializationCommand::OpenElement(ref e)) = res {
-            self.stack
-                .push(SerializationCommand::CloseElement(e.clone()));
-            for c in rev_children_iter(&*e.upcast::<Node>()) {
-                self.push_node(&c);
-            }
+        match res {
+            Some(SerializationCommand::OpenElement(ref e)) => {
+                self.stack
+                    .push(SerializationCommand::CloseElement(e.clone()));
+                self.push_children(e.upcast());
+            },
+            Some(SerializationCommand::OpenShadowRoot(ref shadow_root)) => {
+                self.stack.push(SerializationCommand::CloseShadowRoot);
+                self.push_children(shadow_root.upcast());
+            },
+            _ => {},
         }
 
         res
     }
 }
 
+/// The HTML serialization of a node. If `include_shadow_roots` is set, the
+/// declarative shadow roots in it are serialized as the
+/// `<template shadowrootmode>` elements they were parsed from, so that parsing
+/// the result gives them back. Other shadow roots are never serialized.
+pub struct HtmlSerialization<'a> {
+    pub node: &'a Node,
+    pub include_shadow_roots: bool,
+}
+
+impl<'a> Serialize for HtmlSerialization<'a> {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: &mut S,
+        traversal_scope: TraversalScope,
+    ) -> io::Result<()> {
+        serialize_node(
+            self.node,
+            serializer,
+            traversal_scope,
+            self.include_shadow_roots,
+        )
+    }
+}
+
 impl<'a> Serialize for &'a Node {
     fn serialize<S: Serializer>(
         &self,
         serializer: &mut S,
         traversal_scope: TraversalScope,
     ) -> io::Result<()> {
-        let node = *self;
+        serialize_node(self, serializer, traversal_scope, false)
+    }
+}
+
+fn serialize_node<S: Serializer>(
+    node: &Node,
+    serializer: &mut S,
+    traversal_scope: TraversalScope,
+    include_shadow_roots: bool,
+) -> io::Result<()> {
+    let iter =
+        SerializationIterator::new(node, traversal_scope != IncludeNode, include_shadow_roots);
+
+    for cmd in iter {
+        match cmd {
+            SerializationCommand::OpenElement(n) => {
+                start_element(&n, serializer)?;
+            },
+
+            SerializationCommand::CloseElement(n) => {
+                end_element(&&n, serializer)?;
+            },
 
-        let iter = SerializationIterator::new(node, traversal_scope != IncludeNode);
+            SerializationCommand::OpenShadowRoot(shadow_root) => {
+                start_shadow_root(&shadow_root, serializer)?;
+            },
+
+            SerializationCommand::CloseShadowRoot => {
+                serializer.end_elem(template_name())?;
+            },
 
-        for cmd in iter {
-            match cmd {
-                SerializationCommand::OpenElement(n) => {
-                    start_element(&n, serializer)?;
+            SerializationCommand::SerializeNonelement(n) => match n.type_id() {
+                NodeTypeId::DocumentType => {
+                    let doctype = n.downcast::<DocumentType>().unwrap();
+                    serializer.write_doctype(&doctype.name())?;
                 },
 
-                SerializationCommand::CloseElement(n) => {
-                    end_element(&&n, serializer)?;
+                NodeTypeId::CharacterData(CharacterDataTypeId::Text(_)) => {
+                    let cdata = n.downcast::<CharacterData>().unwrap();
+                    serializer.write_text(&cdata.data())?;
                 },
 
-                SerializationCommand::SerializeNonelement(n) => match n.type_id() {
-                    NodeTypeId::DocumentType => {
-                        let doctype = n.downcast::<DocumentType>().unwrap();
-                        serializer.write_doctype(&doctype.name())?;
-                    },
-
-                    NodeTypeId::CharacterData(CharacterDataTypeId::Text(_)) => {
-                        let cdata = n.downcast::<CharacterData>().unwrap();
-                        serializer.write_text(&cdata.data())?;
-                    },
-
-                    NodeTypeId::CharacterData(CharacterDataTypeId::Comment) => {
-                        let cdata = n.downcast::<CharacterData>().unwrap();
-                        serializer.write_comment(&cdata.data())?;
-                    },
-
-                    NodeTypeId::CharacterData(CharacterDataTypeId::ProcessingInstruction) => {
-                        let pi = n.downcast::<ProcessingInstruction>().unwrap();
-                        let data = pi.upcast::<CharacterData>().data();
-                        serializer.write_processing_instruction(&pi.target(), &data)?;
-                    },
-
-                    NodeTypeId::DocumentFragment(_) => {},
-
-                    NodeTypeId::Document(_) => panic!("Can't serialize Document node itself"),
-                    NodeTypeId::Element(_) => panic!("Element shouldn't appear here"),
-                    NodeTypeId::Attr => panic!("Attr shouldn't appear here"),
+                NodeTypeId::CharacterData(CharacterDataTypeId::Comment) => {
+                    let cdata = n.downcast::<CharacterData>().unwrap();
+                    serializer.write_comment(&cdata.data())?;
                 },
-            }
-        }
 
-        Ok(())
+                NodeTypeId::CharacterData(CharacterDataTypeId::ProcessingInstruction) => {
+                    let pi = n.downcast::<ProcessingInstruction>().unwrap();
+                    let data = pi.upcast::<CharacterData>().data();
+                    serializer.write_processing_instruction(&pi.target(), &data)?;
+                },
+
+                NodeTypeId::DocumentFragment(_) => {},
+
+                NodeTypeId::Document(_) => panic!("Can't serialize Document node itself"),
+                NodeTypeId::Element(_) => panic!("Element shouldn't appear here"),
+                NodeTypeId::Attr => panic!("Attr shouldn't appear here"),
+            },
+        }
     }
+
+    Ok(())
 }
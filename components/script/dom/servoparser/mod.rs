@@ -3,35 +3,51 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
 use std::borrow::Cow;
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashSet, VecDeque};
 use std::mem;
+use std::ops::DerefMut;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use base64::engine::general_purpose;
 use base64::Engine as _;
+use chardetng::EncodingDetector;
+use chrono::DateTime;
 use content_security_policy::{self as csp, CspList};
 use dom_struct::dom_struct;
 use embedder_traits::resources::{self, Resource};
-use encoding_rs::Encoding;
+use embedder_traits::{EmbedderMsg, SupportedColorSchemes};
+use encoding_rs::{Decoder, Encoding};
 use html5ever::buffer_queue::BufferQueue;
+use html5ever::serialize::TraversalScope;
 use html5ever::tendril::fmt::UTF8;
 use html5ever::tendril::{ByteTendril, StrTendril, TendrilSink};
 use html5ever::tokenizer::TokenizerResult;
 use html5ever::tree_builder::{ElementFlags, NextParserState, NodeOrText, QuirksMode, TreeSink};
 use html5ever::{local_name, namespace_url, ns, Attribute, ExpandedName, LocalName, QualName};
+use http::header::HeaderMap;
 use hyper_serde::Serde;
 use mime::{self, Mime};
 use msg::constellation_msg::PipelineId;
+use net_traits::image::base::is_supported_image_mime_type;
+use net_traits::pub_domains::reg_suffix;
+use net_traits::request::Destination;
+use net_traits::storage_thread::StorageThreadMsg;
 use net_traits::{
-    FetchMetadata, FetchResponseListener, Metadata, NetworkError, ResourceFetchTiming,
-    ResourceTimingType,
+    CoreResourceMsg, FetchChannels, FetchMetadata, FetchResponseListener, IpcSend, Metadata,
+    NetworkError, ResourceFetchTiming, ResourceThreads, ResourceTimingType,
 };
 use profile_traits::time::{
     profile, ProfilerCategory, TimerMetadata, TimerMetadataFrameType, TimerMetadataReflowType,
 };
 use script_traits::DocumentActivity;
+use serde::{Deserialize, Serialize};
 use servo_config::pref;
-use servo_url::ServoUrl;
+use servo_url::{ImmutableOrigin, ServoUrl};
 use style::context::QuirksMode as ServoQuirksMode;
+use style::str::HTML_SPACE_CHARACTERS;
 use tendril::stream::LossyDecoder;
 
 use crate::document_loader::{DocumentLoader, LoadType};
@@ -42,6 +58,8 @@ use crate::dom::bindings::codegen::Bindings::DocumentBinding::{
 use crate::dom::bindings::codegen::Bindings::HTMLImageElementBinding::HTMLImageElementMethods;
 use crate::dom::bindings::codegen::Bindings::HTMLTemplateElementBinding::HTMLTemplateElementMethods;
 use crate::dom::bindings::codegen::Bindings::NodeBinding::NodeMethods;
+use crate::dom::bindings::codegen::Bindings::PerformanceBinding::PerformanceMethods;
+use crate::dom::bindings::codegen::Bindings::ShadowRootBinding::ShadowRootMode;
 use crate::dom::bindings::inheritance::Castable;
 use crate::dom::bindings::refcounted::Trusted;
 use crate::dom::bindings::reflector::{reflect_dom_object, DomObject, Reflector};
@@ -51,28 +69,35 @@ use crate::dom::bindings::str::{DOMString, USVString};
 use crate::dom::characterdata::CharacterData;
 use crate::dom::comment::Comment;
 use crate::dom::document::{Document, DocumentSource, HasBrowsingContext, IsHTMLDocument};
+use crate::dom::documentfragment::DocumentFragment;
 use crate::dom::documenttype::DocumentType;
 use crate::dom::element::{CustomElementCreationMode, Element, ElementCreator};
 use crate::dom::globalscope::GlobalScope;
 use crate::dom::htmlformelement::{FormControlElementHelpers, HTMLFormElement};
 use crate::dom::htmlimageelement::HTMLImageElement;
 use crate::dom::htmlinputelement::HTMLInputElement;
+use crate::dom::htmllinkelement::HTMLLinkElement;
 use crate::dom::htmlscriptelement::{HTMLScriptElement, ScriptResult};
+use crate::dom::htmlstyleelement::HTMLStyleElement;
 use crate::dom::htmltemplateelement::HTMLTemplateElement;
 use crate::dom::node::{Node, ShadowIncluding};
 use crate::dom::performanceentry::PerformanceEntry;
 use crate::dom::performancenavigationtiming::PerformanceNavigationTiming;
 use crate::dom::processinginstruction::ProcessingInstruction;
+use crate::dom::shadowroot::{IsUserAgentWidget, ShadowRoot};
 use crate::dom::text::Text;
 use crate::dom::virtualmethods::vtable_for;
 use crate::network_listener::PreInvoke;
 use crate::realms::enter_realm;
 use crate::script_thread::ScriptThread;
+use crate::task_source::TaskSource;
 
-mod async_html;
+pub(crate) mod async_html;
 mod html;
-mod prefetch;
-mod xml;
+pub(crate) mod prefetch;
+pub(crate) mod xml;
+
+pub(crate) use self::html::HtmlSerialization;
 
 #[dom_struct]
 /// The parser maintains two input streams: one for input from script through
@@ -91,6 +116,10 @@ pub struct ServoParser {
     reflector: Reflector,
     /// The document associated with this parser.
     document: Dom<Document>,
+    /// The URL of the input, which is that of the tokenizer, kept here so it
+    /// can be known while the tokenizer is in use.
+    #[no_trace]
+    url: ServoUrl,
     /// The BOM sniffing state.
     ///
     /// `None` means we've found the BOM, we've found there isn't one, or
@@ -117,17 +146,428 @@ pub struct ServoParser {
     script_nesting_level: Cell<usize>,
     /// <https://html.spec.whatwg.org/multipage/#abort-a-parser>
     aborted: Cell<bool>,
+    /// Whether this parser has stopped parsing, after which the tokenizer
+    /// has ended and must not be fed anymore.
+    finished: Cell<bool>,
     /// <https://html.spec.whatwg.org/multipage/#script-created-parser>
     script_created_parser: bool,
     /// We do a quick-and-dirty parse of the input looking for resources to prefetch.
+    /// The tokenizer is only created once there's input to scan, which only
+    /// happens for documents with browsing contexts, so fragment parsers don't
+    /// pay for it.
     // TODO: if we had speculative parsing, we could do this when speculatively
     // building the DOM. https://github.com/servo/servo/pull/19203
-    prefetch_tokenizer: DomRefCell<prefetch::Tokenizer>,
+    prefetch_tokenizer: DomRefCell<Option<prefetch::Tokenizer>>,
     #[ignore_malloc_size_of = "Defined in html5ever"]
     #[no_trace]
     prefetch_input: DomRefCell<BufferQueue>,
+    /// Whether the prefetch tokenizer was dropped by `trim_memory`, after
+    /// which nothing more is prefetched.
+    prefetch_dropped: Cell<bool>,
+    /// The network input held back before decoding it, while it could still
+    /// start with an XML declaration whose encoding should be used to decode
+    /// it. `None` once the declaration, if any, was looked at.
+    xml_declaration_buffer: DomRefCell<Option<Vec<u8>>>,
+    /// The network input held back before decoding it, while its encoding is
+    /// sniffed from its content, see `dom.servoparser.encoding_sniffing_buffer_size`.
+    encoding_sniffing_buffer: DomRefCell<Option<Vec<u8>>>,
+    /// What determined the encoding of the network input, see
+    /// [`EncodingSource`].
+    encoding_source: Cell<EncodingSource>,
+    /// The scripts encountered so far, see [`ParserScriptCounters`].
+    script_counters: Cell<ParserScriptCounters>,
+    /// When the tokenizer first handed a script to prepare, in milliseconds
+    /// since the epoch.
+    first_script_prepare_time: Cell<Option<u64>>,
+    /// Whether to style the document with the built-in XML viewer stylesheet
+    /// once it is parsed, if it has no style of its own.
+    use_xml_viewer: Cell<bool>,
+    /// The number of bytes of input received so far, before decoding.
+    bytes_received: Cell<usize>,
+    /// The number of characters of input the tokenizer consumed so far.
+    chars_tokenized: Cell<usize>,
+    /// Whether no input was received yet, whose leading U+FEFF BYTE ORDER
+    /// MARK is then dropped, see [`strip_bom_at_start_of_input`].
+    at_start_of_input: Cell<bool>,
+    /// The size of the input buffered in `network_input`, `script_input` and
+    /// `prefetch_input`, see [`BufferedInputSize`].
+    buffered_input_size: Cell<BufferedInputSize>,
+    /// The input and nodes the parse has taken so far, and how much of them
+    /// it may take.
+    parse_budget: Cell<ParseBudget>,
+    /// The number of nodes inserted by the tokenizer since the parser last
+    /// made the document reflow, see `dom.servoparser.reflow_node_threshold`.
+    nodes_inserted_since_reflow: Cell<usize>,
+    /// Whether the document is a page synthesized by Servo or the embedder,
+    /// such as an error page, instead of the body of the response, which is
+    /// then ignored.
+    synthesized_document: Cell<bool>,
+    /// Whether the parser was aborted or halted from within its tokenizer,
+    /// which is then only ended once it returns.
+    tokenizer_end_pending: Cell<bool>,
+    #[ignore_malloc_size_of = "Function pointer"]
+    #[no_trace]
+    microtask_checkpoint_observer: Cell<Option<MicrotaskCheckpointObserver>>,
+    #[ignore_malloc_size_of = "Function pointer"]
+    #[no_trace]
+    custom_element_creation_observer: Cell<Option<CustomElementCreationObserver>>,
+    #[ignore_malloc_size_of = "Boxed closure"]
+    #[no_trace]
+    finish_callback: DomRefCell<Option<ParserCompletionCallback>>,
+    #[ignore_malloc_size_of = "Boxed closure"]
+    #[no_trace]
+    abort_callback: DomRefCell<Option<ParserCompletionCallback>>,
+}
+
+/// Counts of the scripts the parser went through, which give profilers a
+/// script-level view of the parsing timeline.
+#[derive(Clone, Copy, Debug, Default, JSTraceable, MallocSizeOf, PartialEq)]
+pub struct ParserScriptCounters {
+    /// Scripts prepared directly by the parser.
+    pub prepared: usize,
+    /// Scripts prepared while another script was executing, e.g. scripts
+    /// inserted through `document.write()`.
+    pub nested_prepared: usize,
+    /// Prepared scripts that blocked the parser.
+    pub parser_blocking: usize,
+    /// Parser-blocking scripts that were executed when resuming the parser.
+    pub resumed: usize,
+}
+
+/// A snapshot of the input of a parser and of what keeps it from parsing it,
+/// to diagnose parsers that never finish.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PendingInputState {
+    /// The size in bytes of the decoded network input not tokenized yet, or
+    /// `None` if the tokenizer is reading it.
+    pub network_bytes: Option<usize>,
+    /// The size in bytes of the input from `document.write()` not tokenized
+    /// yet, or `None` if the tokenizer is reading it.
+    pub script_bytes: Option<usize>,
+    pub suspended: bool,
+    pub last_chunk_received: bool,
+    pub aborted: bool,
+}
+
+/// The combined size in bytes of the input queues of a parser, and the largest
+/// it got to during the parse. Input is pushed to the queues by the parser,
+/// but taken from them by the tokenizers, so the size is only known exactly
+/// when the queues are measured again after tokenizing, and is otherwise that
+/// measure plus the size of the input pushed since.
+#[derive(Clone, Copy, Debug, Default, JSTraceable, MallocSizeOf, PartialEq)]
+pub struct BufferedInputSize {
+    current: usize,
+    peak: usize,
+}
+
+impl BufferedInputSize {
+    /// Records `len` bytes of input being pushed to one of the queues.
+    pub fn push(&mut self, len: usize) {
+        self.current = self.current.saturating_add(len);
+        self.peak = self.peak.max(self.current);
+    }
+
+    /// Records the size the queues were measured at, or nothing if some of
+    /// them couldn't be measured as they are being tokenized.
+    pub fn measure(&mut self, current: Option<usize>) {
+        if let Some(current) = current {
+            self.current = current;
+            self.peak = self.peak.max(current);
+        }
+    }
+
+    /// The largest size the queues got to.
+    pub fn peak(&self) -> usize {
+        self.peak
+    }
+}
+
+/// How many bytes of input and how many nodes a parse may take, see
+/// `dom.servoparser.max_parse_bytes` and `dom.servoparser.max_parse_nodes`,
+/// and how many it took so far. A parse exceeding either is aborted, which
+/// protects against decompression bombs and pathological input.
+#[derive(Clone, Copy, Debug, JSTraceable, MallocSizeOf, PartialEq)]
+pub struct ParseBudget {
+    max_bytes: usize,
+    max_nodes: usize,
+    bytes: usize,
+    nodes: usize,
+}
+
+/// What determined the encoding the network input of a parser is decoded
+/// with, the last of them to apply winning.
+/// <https://html.spec.whatwg.org/multipage/#encoding-sniffing-algorithm>
+#[derive(Clone, Copy, Debug, JSTraceable, MallocSizeOf, PartialEq)]
+pub enum EncodingSource {
+    /// The encoding the document was created with, which is the default
+    /// encoding unless the document was created with an encoding.
+    Initial,
+    /// A byte order mark at the start of the input.
+    Bom,
+    /// The `charset` parameter of the content type of the response.
+    TransportLayer,
+    /// The encoding mandated by the format of the document, e.g. UTF-8 for
+    /// WebVTT.
+    Format,
+    /// The encoding declaration of an XML document.
+    XmlDeclaration,
+    /// The content sniffed before decoding it, see
+    /// `dom.servoparser.encoding_sniffing_buffer_size`.
+    Sniffed,
+    /// An encoding declaration found while parsing, e.g. a late
+    /// `<meta charset>`, see `ServoParser::change_encoding`.
+    Meta,
+}
+
+/// What a parse took more of than its [`ParseBudget`] allows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseBudgetExceeded {
+    Bytes(usize),
+    Nodes(usize),
+}
+
+impl ParseBudget {
+    pub fn new(max_bytes: usize, max_nodes: usize) -> ParseBudget {
+        ParseBudget {
+            max_bytes,
+            max_nodes,
+            bytes: 0,
+            nodes: 0,
+        }
+    }
+
+    fn from_prefs() -> ParseBudget {
+        let max = |value: i64, default| if value > 0 { value as usize } else { default };
+        ParseBudget::new(
+            max(
+                pref!(dom.servoparser.max_parse_bytes),
+                DEFAULT_MAX_PARSE_BYTES,
+            ),
+            max(
+                pref!(dom.servoparser.max_parse_nodes),
+                DEFAULT_MAX_PARSE_NODES,
+            ),
+        )
+    }
+
+    /// Records `len` bytes of input being pushed to the parser.
+    pub fn add_bytes(&mut self, len: usize) {
+        self.bytes = self.bytes.saturating_add(len);
+    }
+
+    /// Records `count` nodes being inserted by the parser.
+    pub fn add_nodes(&mut self, count: usize) {
+        self.nodes = self.nodes.saturating_add(count);
+    }
+
+    /// What the parse took more of than allowed, if anything, with the
+    /// maximum it was allowed.
+    pub fn exceeded(&self) -> Option<ParseBudgetExceeded> {
+        if self.bytes > self.max_bytes {
+            Some(ParseBudgetExceeded::Bytes(self.max_bytes))
+        } else if self.nodes > self.max_nodes {
+            Some(ParseBudgetExceeded::Nodes(self.max_nodes))
+        } else {
+            None
+        }
+    }
+}
+
+/// The input state of a parser, from which another parser can go on parsing
+/// the same response, e.g. after the pipeline moved to another process. This
+/// is only the input received so far that wasn't tokenized yet, not the
+/// document parsed from the rest of it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ParserCheckpoint {
+    /// The name of the encoding the network input is decoded with, or `None`
+    /// if the input isn't from a byte stream.
+    pub encoding: Option<String>,
+    /// The bytes received last that aren't decoded yet, since they don't make
+    /// a whole character.
+    pub undecoded_bytes: Vec<u8>,
+    /// The BOM bytes found so far, if the parser is still sniffing for a BOM.
+    pub bom_sniff: Option<Vec<u8>>,
+    /// The decoded network input not tokenized yet.
+    pub pending_input: String,
+    pub last_chunk_received: bool,
+    pub bytes_received: usize,
+    pub chars_tokenized: usize,
+}
+
+/// Counts of the elements the parser created, which tell how much of the
+/// cost of a parse goes to custom elements.
+#[derive(Clone, Copy, Debug, Default, JSTraceable, MallocSizeOf, PartialEq)]
+pub struct ParserElementCounters {
+    /// Elements created for start tags, including custom elements.
+    pub created: usize,
+    /// Custom elements constructed synchronously, which runs script and
+    /// performs a microtask checkpoint in the middle of the parse.
+    pub custom_synchronous: usize,
+    /// Custom elements whose construction is left to an upgrade, as when
+    /// parsing a fragment.
+    pub custom_asynchronous: usize,
+    /// Stylesheet links created before the body, which block rendering until
+    /// they're loaded, and so gate the first paint of the document.
+    /// <https://html.spec.whatwg.org/multipage/#implicitly-potentially-render-blocking>
+    pub render_blocking_stylesheets: usize,
+}
+
+/// The first parse error of a document parsed in strict mode, which aborts
+/// the parser. See `dom.servoparser.strict.enabled`.
+#[derive(Clone, Debug, JSTraceable, MallocSizeOf, PartialEq)]
+pub struct StrictParseError {
+    /// The line the parse error was found on.
+    pub line: u64,
+    pub message: String,
+}
+
+/// A parse error reported by html5ever, collected by
+/// `ServoParser::parse_html_fragment_with_errors`.
+#[derive(Clone, Debug, JSTraceable, MallocSizeOf, PartialEq)]
+pub struct ParseErrorRecord {
+    /// The line of the token the parse error was found at.
+    pub line: u64,
+    pub message: String,
+}
+
+/// A deprecated or legacy construct the parser came across, collected for
+/// developer tools if `dom.servoparser.deprecation_warnings.enabled` is set.
+#[derive(Clone, Debug, JSTraceable, MallocSizeOf, PartialEq)]
+pub struct DeprecationWarning {
+    /// The line the construct was found on.
+    pub line: u64,
+    pub feature: DeprecatedFeature,
+}
+
+/// The kinds of constructs a [`DeprecationWarning`] is about.
+#[derive(Clone, Debug, JSTraceable, MallocSizeOf, PartialEq)]
+pub enum DeprecatedFeature {
+    /// An obsolete HTML element, by its local name, see
+    /// [`is_obsolete_element`].
+    ObsoleteElement(String),
+    /// A doctype of an HTML document other than `<!DOCTYPE html>`, see
+    /// [`is_legacy_doctype`].
+    LegacyDoctype,
+    /// A call to `document.write()`.
+    DocumentWrite,
+}
+
+/// Records a warning about `feature` found on `line` in `warnings`, if
+/// deprecation warnings are enabled.
+fn warn_deprecated(warnings: &mut Vec<DeprecationWarning>, line: u64, feature: DeprecatedFeature) {
+    if pref!(dom.servoparser.deprecation_warnings.enabled) {
+        warnings.push(DeprecationWarning { line, feature });
+    }
+}
+
+/// The kind of tokenizer a parser is using.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TokenizerKind {
+    /// The HTML tokenizer, running on the script thread.
+    Html,
+    /// The HTML tokenizer, running off the script thread.
+    AsyncHtml,
+    /// The XML tokenizer.
+    Xml,
+}
+
+/// A hook handed the text of every comment encountered by the parser, which
+/// returns the text of the comment node to create, or `None` to drop the
+/// comment altogether.
+pub type CommentTransformer = fn(&StrTendril) -> Option<String>;
+
+/// A hook handed every element created by the parser along with the list of
+/// attributes it was created with, in source order and before the element
+/// processes them. Also called when the parser adds attributes to an existing
+/// `<html>` or `<body>` element.
+///
+/// Note that html5ever has already lowercased the names of HTML attributes and
+/// dropped duplicate attributes at this point.
+pub type RawAttributesObserver = fn(&Element, &[Attribute]);
+
+/// A hook handed the name and attributes of every element the parser is about
+/// to create, which returns whether to block the element, for example to keep
+/// known trackers from loading. A blocked element is created without its
+/// attributes and never inserted into the document: a comment stands in for it
+/// instead, and what the parser inserts into the element stays out of the
+/// document with it. Blocked scripts never run.
+///
+/// See [`is_blocked_element`] for the elements that can't be blocked.
+pub type ElementBlocker = fn(&QualName, &[Attribute]) -> bool;
+
+/// A hook handed the name, public identifier and system identifier of the
+/// doctype of a document, as it is appended to the document. The identifiers
+/// are empty if missing.
+pub type DoctypeObserver = fn(&str, &str, &str);
+
+/// A hook called with the `<head>` of the document once it has been parsed,
+/// whether it is closed by `</head>` or implicitly, by content that doesn't
+/// belong in it. All the `<meta>` and `<link>` elements of the head are known
+/// by then, save for those misplaced after it, which the parser still inserts
+/// into it.
+pub type HeadCompleteObserver = fn(&Element);
+
+/// A hook called with each element that is a child of the `<body>` of the
+/// document once it has been parsed, i.e. once none of its subtree is left to
+/// parse, save for the content that a misnested end tag later moves into it.
+/// It is called the same when the `<body>` is implied by the content rather
+/// than written in the document.
+pub type BodyChildCompleteObserver = fn(&Element);
+
+/// Where the parser performs a microtask checkpoint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MicrotaskCheckpointSite {
+    /// Before running the script of a `</script>` end tag.
+    ScriptEndTag,
+    /// Before synchronously creating a custom element.
+    CustomElementCreation,
+}
+
+/// A hook called whenever the parser performs a microtask checkpoint, to
+/// trace the order of what it runs.
+pub type MicrotaskCheckpointObserver = fn(MicrotaskCheckpointSite);
+
+/// Where the parser is in the synchronous creation of a custom element.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CustomElementCreationPhase {
+    /// After the microtask checkpoint, before pushing the element queue of
+    /// the reactions enqueued while creating the element.
+    Start,
+    /// After popping that element queue, which runs those reactions.
+    End,
 }
 
+/// A hook called around the synchronous creation of a custom element by the
+/// parser, with the local name of the element, to debug the order in which
+/// custom element reactions run during the parse. Elements only upgraded
+/// later, such as those of fragments, don't go through it.
+pub type CustomElementCreationObserver = fn(&LocalName, CustomElementCreationPhase);
+
+/// A callback run once when the parser finishes or is aborted, see
+/// [`ServoParser::set_finish_callback`] and [`ServoParser::set_abort_callback`].
+pub type ParserCompletionCallback = Box<dyn FnOnce()>;
+
+/// The number of attributes an element can get from the parser when
+/// `dom.servoparser.max_attributes_per_element` isn't set, which is far more
+/// than any real content has.
+const DEFAULT_MAX_ATTRIBUTES_PER_ELEMENT: usize = 10_000;
+
+/// The bytes of input a parse can take when `dom.servoparser.max_parse_bytes`
+/// isn't set, which only decompression bombs and the like get to.
+const DEFAULT_MAX_PARSE_BYTES: usize = 1 << 30;
+
+/// The nodes a parse can insert when `dom.servoparser.max_parse_nodes` isn't
+/// set, which only pathological input gets to.
+const DEFAULT_MAX_PARSE_NODES: usize = 50_000_000;
+
+/// The smallest size of the pieces large chunks of network input are split in,
+/// see `dom.servoparser.max_chunk_size`.
+const MIN_SUB_CHUNK_SIZE: usize = 1024;
+
+/// The most network input held back while looking for the end of an XML
+/// declaration, past which the document is decoded as if it had none.
+const MAX_XML_DECLARATION_LEN: usize = 1024;
+
 #[derive(PartialEq)]
 enum LastChunkState {
     Received,
@@ -159,27 +599,44 @@ impl ServoParser {
         self.can_write() || self.tokenizer.try_borrow_mut().is_ok()
     }
 
-    pub fn parse_html_document(document: &Document, input: Option<DOMString>, url: ServoUrl) {
-        let parser = if pref!(dom.servoparser.async_html_tokenizer.enabled) {
-            ServoParser::new(
-                document,
-                Tokenizer::AsyncHtml(self::async_html::Tokenizer::new(document, url, None)),
-                LastChunkState::NotReceived,
-                ParserKind::Normal,
-            )
+    /// Creates a parser for an HTML document. If `quirks_mode_override` is
+    /// given, the document is put in that quirks mode right away and the
+    /// doctype encountered during parsing can't change it anymore.
+    /// `size_hint` is the expected size of the document in bytes, such as
+    /// given by the `Content-Length` of its response, which decides whether it
+    /// is tokenized off the main thread, see `uses_async_html_tokenizer`.
+    pub fn parse_html_document(
+        document: &Document,
+        input: Option<DOMString>,
+        url: ServoUrl,
+        quirks_mode_override: Option<ServoQuirksMode>,
+        size_hint: Option<u64>,
+    ) {
+        let use_async_tokenizer = uses_async_html_tokenizer(
+            pref!(dom.servoparser.async_html_tokenizer.enabled),
+            pref!(dom.servoparser.async_html_tokenizer.size_threshold),
+            size_hint,
+        );
+        let mut tokenizer = if use_async_tokenizer {
+            Tokenizer::AsyncHtml(self::async_html::Tokenizer::new(document, url, None))
         } else {
-            ServoParser::new(
+            Tokenizer::Html(self::html::Tokenizer::new(
                 document,
-                Tokenizer::Html(self::html::Tokenizer::new(
-                    document,
-                    url,
-                    None,
-                    ParsingAlgorithm::Normal,
-                )),
-                LastChunkState::NotReceived,
-                ParserKind::Normal,
-            )
+                url,
+                None,
+                ParsingAlgorithm::Normal,
+            ))
         };
+        if let Some(mode) = quirks_mode_override {
+            document.set_quirks_mode(mode);
+            tokenizer.set_quirks_mode_override(mode);
+        }
+        let parser = ServoParser::new(
+            document,
+            tokenizer,
+            LastChunkState::NotReceived,
+            ParserKind::Normal,
+        );
 
         // Set as the document's current parser and initialize with `input`, if given.
         if let Some(input) = input {
@@ -189,15 +646,140 @@ impl ServoParser {
         }
     }
 
+    /// Creates a parser for an HTML document that never runs any of its
+    /// scripts, whether the document has a browsing context or not: they are
+    /// all marked as already started, and `<noscript>` elements are parsed as
+    /// if scripting was disabled, i.e. their content is parsed as markup.
+    ///
+    /// Note that this only covers `<script>` elements, and that event handler
+    /// attributes are still set as usual.
+    pub fn parse_html_document_without_scripting(
+        document: &Document,
+        input: DOMString,
+        url: ServoUrl,
+    ) {
+        let parser = ServoParser::new(
+            document,
+            Tokenizer::Html(self::html::Tokenizer::new_without_scripting(document, url)),
+            LastChunkState::NotReceived,
+            ParserKind::Normal,
+        );
+        parser.parse_string_chunk(String::from(input));
+    }
+
     // https://html.spec.whatwg.org/multipage/#parsing-html-fragments
+    //
+    // The nodes returned are the children of the root element of the temporary
+    // document, even when `context` is a `<template>`: the context element only
+    // determines how the input is parsed. It is up to the caller to put the
+    // nodes into the template contents if need be, as `innerHTML` does.
     pub fn parse_html_fragment(
         context: &Element,
         input: DOMString,
     ) -> impl Iterator<Item = DomRoot<Node>> {
+        ServoParser::parse_html_fragment_with_base_url(context, input, None)
+    }
+
+    /// Parses a fragment like `parse_html_fragment`, but resolving the relative
+    /// URLs found while parsing against `base_url` instead of the URL of the
+    /// document of `context`, if given. A `<base>` element in `input` still
+    /// takes precedence, as it would in a document.
+    ///
+    /// Note that this only holds for the temporary document the fragment is
+    /// parsed in: once the nodes are moved to another document, they resolve
+    /// their URLs against the base URL of that one.
+    pub fn parse_html_fragment_with_base_url(
+        context: &Element,
+        input: DOMString,
+        base_url: Option<ServoUrl>,
+    ) -> impl Iterator<Item = DomRoot<Node>> {
+        let parser = ServoParser::new_fragment_parser(context, base_url, LastChunkState::Received);
+        parser.parse_string_chunk(String::from(input));
+
+        // Step 14.
+        let root_element = parser
+            .document
+            .GetDocumentElement()
+            .expect("no document element");
+        FragmentParsingResult {
+            inner: root_element.upcast::<Node>().children(),
+        }
+    }
+
+    /// Parses a fragment like `parse_html_fragment`, and returns the parse
+    /// errors found along with the nodes parsed, for conformance tests against
+    /// the html5lib test data. The errors are those html5ever reports, which
+    /// are as many as the test data expects, but don't have its codes.
+    #[cfg(feature = "testing")]
+    pub fn parse_html_fragment_with_errors(
+        context: &Element,
+        input: DOMString,
+    ) -> (Vec<DomRoot<Node>>, Vec<ParseErrorRecord>) {
+        let parser = ServoParser::new_fragment_parser(context, None, LastChunkState::Received);
+        if let Tokenizer::Html(ref mut tokenizer) = *parser.tokenizer.borrow_mut() {
+            tokenizer.collect_parse_errors();
+        }
+        parser.parse_string_chunk(String::from(input));
+
+        let parse_errors = match *parser.tokenizer.borrow_mut() {
+            Tokenizer::Html(ref mut tokenizer) => tokenizer.take_parse_errors(),
+            _ => vec![],
+        };
+        let root_element = parser
+            .document
+            .GetDocumentElement()
+            .expect("no document element");
+        let nodes = FragmentParsingResult {
+            inner: root_element.upcast::<Node>().children(),
+        }
+        .collect();
+        (nodes, parse_errors)
+    }
+
+    /// Parses a fragment like `parse_html_fragment`, but `chunk_size` bytes of
+    /// `input` at a time, in a task of its own for each chunk but the first, so
+    /// that parsing a very large fragment doesn't block the event loop.
+    ///
+    /// `on_nodes` is called with the nodes parsed so far after every chunk,
+    /// removed from the temporary document. The last node parsed may still get
+    /// content from the next chunks, so it's only passed once it's known to be
+    /// complete, and at the latest once all the input was parsed. Parsing stops
+    /// early if `on_nodes` returns false, for instance because the nodes have
+    /// nowhere to go anymore. Fragments don't run scripts, and the context
+    /// element is only looked at by name, which can't change, so the caller can
+    /// safely mutate the context while the fragment is parsed; the fragment is
+    /// parsed as if it hadn't.
+    ///
+    /// Note that the nodes aren't passed to `on_nodes` at all if the window
+    /// is closed before the fragment is fully parsed.
+    pub fn parse_html_fragment_in_chunks<F>(
+        context: &Element,
+        input: DOMString,
+        chunk_size: usize,
+        on_nodes: F,
+    ) where
+        F: FnMut(Vec<DomRoot<Node>>) -> bool + Send + 'static,
+    {
+        let parser = ServoParser::new_fragment_parser(context, None, LastChunkState::NotReceived);
+        let chunks = FragmentChunks {
+            parser: Trusted::new(&*parser),
+            input: split_at_char_boundaries(String::from(input), chunk_size),
+            on_nodes,
+        };
+        chunks.parse_next();
+    }
+
+    fn new_fragment_parser(
+        context: &Element,
+        base_url: Option<ServoUrl>,
+        last_chunk_state: LastChunkState,
+    ) -> DomRoot<ServoParser> {
         let context_node = context.upcast::<Node>();
         let context_document = context_node.owner_doc();
         let window = context_document.window();
-        let url = context_document.url();
+        // The temporary document gets the base URL as its URL, which is what
+        // its fallback base URL is then.
+        let url = base_url.unwrap_or_else(|| context_document.url());
 
         // Step 1.
         let loader = DocumentLoader::new_with_threads(
@@ -233,7 +815,7 @@ impl ServoParser {
             form_elem: form.as_deref(),
         };
 
-        let parser = ServoParser::new(
+        ServoParser::new(
             &document,
             Tokenizer::Html(self::html::Tokenizer::new(
                 &document,
@@ -241,16 +823,9 @@ impl ServoParser {
                 Some(fragment_context),
                 ParsingAlgorithm::Fragment,
             )),
-            LastChunkState::Received,
+            last_chunk_state,
             ParserKind::Normal,
-        );
-        parser.parse_string_chunk(String::from(input));
-
-        // Step 14.
-        let root_element = document.GetDocumentElement().expect("no document element");
-        FragmentParsingResult {
-            inner: root_element.upcast::<Node>().children(),
-        }
+        )
     }
 
     pub fn parse_html_script_input(document: &Document, url: ServoUrl) {
@@ -265,7 +840,6 @@ impl ServoParser {
             LastChunkState::NotReceived,
             ParserKind::ScriptCreated,
         );
-        *parser.bom_sniff.borrow_mut() = None;
         document.set_current_parser(Some(&parser));
     }
 
@@ -276,6 +850,12 @@ impl ServoParser {
             LastChunkState::NotReceived,
             ParserKind::Normal,
         );
+        // The encoding given by the transport layer takes precedence over the
+        // one declared in the document, and string input is decoded already.
+        // https://www.rfc-editor.org/rfc/rfc7303#section-3.2
+        if input.is_none() && document.content_type().get_param(mime::CHARSET).is_none() {
+            *parser.xml_declaration_buffer.borrow_mut() = Some(vec![]);
+        }
 
         // Set as the document's current parser and initialize with `input`, if given.
         if let Some(input) = input {
@@ -293,6 +873,17 @@ impl ServoParser {
         self.script_created_parser
     }
 
+    /// The encoding actually used to decode network input, which may differ
+    /// from the document's encoding if the latter isn't supported by the
+    /// decoder. `None` once the decoder has been flushed, or for parsers that
+    /// never decode bytes.
+    pub fn network_decoder_encoding(&self) -> Option<&'static Encoding> {
+        self.network_decoder
+            .borrow()
+            .as_ref()
+            .map(NetworkDecoder::encoding)
+    }
+
     /// Corresponds to the latter part of the "Otherwise" branch of the 'An end
     /// tag whose tag name is "script"' of
     /// <https://html.spec.whatwg.org/multipage/#parsing-main-incdata>
@@ -313,19 +904,25 @@ impl ServoParser {
         result: ScriptResult,
     ) {
         assert!(self.suspended.get());
+        // A halted parser is still the current parser of its document, but
+        // runs no script anymore.
+        if self.aborted.get() {
+            return;
+        }
         self.suspended.set(false);
+        // Catch up on the input that came in while the prefetch tokenizer
+        // was paused, before the script can write more.
+        self.feed_prefetch_tokenizer();
 
-        mem::swap(
-            &mut *self.script_input.borrow_mut(),
-            &mut *self.network_input.borrow_mut(),
+        prepend_script_input(
+            &mut self.script_input.borrow_mut(),
+            &mut self.network_input.borrow_mut(),
         );
-        while let Some(chunk) = self.script_input.borrow_mut().pop_front() {
-            self.network_input.borrow_mut().push_back(chunk);
-        }
 
         let script_nesting_level = self.script_nesting_level.get();
         assert_eq!(script_nesting_level, 0);
 
+        self.update_script_counters(|counters| counters.resumed += 1);
         self.script_nesting_level.set(script_nesting_level + 1);
         script.execute(result);
         self.script_nesting_level.set(script_nesting_level);
@@ -335,19 +932,286 @@ impl ServoParser {
         }
     }
 
+    /// The document this parser is parsing into.
+    pub fn document(&self) -> DomRoot<Document> {
+        DomRoot::from_ref(&*self.document)
+    }
+
+    /// The URL of the input this parser is parsing.
+    pub fn url(&self) -> ServoUrl {
+        self.url.clone()
+    }
+
+    pub fn tokenizer_kind(&self) -> TokenizerKind {
+        self.tokenizer.borrow().kind()
+    }
+
+    pub fn script_counters(&self) -> ParserScriptCounters {
+        self.script_counters.get()
+    }
+
+    pub fn element_counters(&self) -> ParserElementCounters {
+        self.tokenizer.borrow().element_counters()
+    }
+
+    /// When this parse first yielded to a script, by preparing it, in
+    /// milliseconds since the epoch like the navigation timing marks of the
+    /// document. This is `None` until then, and reset when the parser is
+    /// aborted or its input replaced.
+    pub fn first_script_prepare_time(&self) -> Option<u64> {
+        self.first_script_prepare_time.get()
+    }
+
+    /// Measures the input the parser has yet to tokenize, leaving it as is.
+    pub fn pending_input_state(&self) -> PendingInputState {
+        PendingInputState {
+            network_bytes: pending_input_len(&self.network_input),
+            script_bytes: pending_input_len(&self.script_input),
+            suspended: self.suspended.get(),
+            last_chunk_received: self.last_chunk_received.get(),
+            aborted: self.aborted.get(),
+        }
+    }
+
+    /// The number of bytes of input received so far, from the network or
+    /// from script, before decoding. This says how far a parse got, e.g. to
+    /// report progress, and is reset when the parser is aborted.
+    pub fn bytes_received(&self) -> usize {
+        self.bytes_received.get()
+    }
+
+    /// The number of characters of decoded input the tokenizer consumed so
+    /// far, including input written with `document.write()`. Input that waits
+    /// for a blocking script isn't counted until it's tokenized. This is reset
+    /// when the parser is aborted.
+    pub fn chars_tokenized(&self) -> usize {
+        self.chars_tokenized.get()
+    }
+
+    /// The largest combined size in bytes of the decoded input the parser
+    /// held at once, from the network, from `document.write()` and for the
+    /// prefetch tokenizer. This is large for pages whose blocking scripts
+    /// take long enough for a lot of input to pile up. It is reset when the
+    /// parser is aborted or its input replaced.
+    pub fn peak_buffered_input_size(&self) -> usize {
+        self.buffered_input_size.get().peak()
+    }
+
+    fn update_buffered_input_size(&self, f: impl FnOnce(&mut BufferedInputSize)) {
+        let mut size = self.buffered_input_size.get();
+        f(&mut size);
+        self.buffered_input_size.set(size);
+    }
+
+    fn update_parse_budget(&self, f: impl FnOnce(&mut ParseBudget)) {
+        let mut budget = self.parse_budget.get();
+        f(&mut budget);
+        self.parse_budget.set(budget);
+    }
+
+    /// Frees what memory the parser can do without, for embedders to call
+    /// under memory pressure. The parse goes on the same, only without
+    /// prefetching.
+    ///
+    /// What's dropped is the prefetch tokenizer and its input: prefetching
+    /// is only an optimization, the resources it would have prefetched are
+    /// still fetched once the parser gets to them. It is dropped for the rest
+    /// of the parse, as a new one would scan the remaining input from the
+    /// wrong state, e.g. see URLs in the text of a script.
+    ///
+    /// What's kept is the input from the network and from `document.write()`
+    /// that wasn't tokenized yet, which is needed to build the document, but
+    /// is copied to buffers of its own size, see [`compact_input`]. The input
+    /// held back while sniffing the encoding or a BOM is small, and needed
+    /// too. The queues being tokenized can't be touched, so calling this from
+    /// a script run by the parser only drops the prefetch tokenizer.
+    pub fn trim_memory(&self) {
+        if self.prefetch_tokenizer.try_borrow().is_err() {
+            return;
+        }
+        self.prefetch_dropped.set(true);
+        *self.prefetch_tokenizer.borrow_mut() = None;
+        *self.prefetch_input.borrow_mut() = BufferQueue::new();
+        for queue in [&self.network_input, &self.script_input].iter() {
+            if let Ok(mut queue) = queue.try_borrow_mut() {
+                compact_input(&mut queue);
+            }
+        }
+        self.measure_buffered_input();
+    }
+
+    /// Whether this parser prefetches the resources of its input, which it
+    /// does for documents with browsing contexts, unless its memory was
+    /// trimmed.
+    fn prefetches(&self) -> bool {
+        self.document.browsing_context().is_some() && !self.prefetch_dropped.get()
+    }
+
+    /// Measures the input queues again, once some of their input may have
+    /// been tokenized.
+    fn measure_buffered_input(&self) {
+        let current: Option<usize> = [
+            &self.network_input,
+            &self.script_input,
+            &self.prefetch_input,
+        ]
+        .iter()
+        .map(|queue| pending_input_len(queue))
+        .sum();
+        self.update_buffered_input_size(|size| size.measure(current));
+    }
+
+    /// Takes a checkpoint of the input of this parser, see [`ParserCheckpoint`].
+    /// There is none if the parser is in the middle of tokenizing, has input
+    /// from `document.write()` pending, was aborted, is still sniffing the
+    /// encoding of its input, or is decoding with an encoding whose decoder
+    /// state can't be told from the bytes decoded.
+    pub fn checkpoint(&self) -> Option<ParserCheckpoint> {
+        if self.aborted.get() || !self.script_input.try_borrow().ok()?.is_empty() {
+            return None;
+        }
+        if self.encoding_sniffing_buffer.borrow().is_some() ||
+            self.xml_declaration_buffer.borrow().is_some()
+        {
+            return None;
+        }
+        let encoding;
+        let undecoded_bytes;
+        match *self.network_decoder.try_borrow().ok()? {
+            Some(ref decoder) => {
+                encoding = Some(decoder.encoding().name().to_owned());
+                undecoded_bytes = decoder.undecoded_bytes()?.to_vec();
+            },
+            None => {
+                encoding = None;
+                undecoded_bytes = vec![];
+            },
+        }
+        let mut pending_input = String::new();
+        for_each_pending_input(&mut *self.network_input.try_borrow_mut().ok()?, |chunk| {
+            pending_input.push_str(chunk)
+        });
+        Some(ParserCheckpoint {
+            encoding,
+            undecoded_bytes,
+            bom_sniff: self.bom_sniff.borrow().clone(),
+            pending_input,
+            last_chunk_received: self.last_chunk_received.get(),
+            bytes_received: self.bytes_received.get(),
+            chars_tokenized: self.chars_tokenized.get(),
+        })
+    }
+
+    /// Goes on parsing from `checkpoint`, taken from another parser, into the
+    /// document of this parser, which is expected to hold what was parsed
+    /// before. Input received afterwards is parsed as usual. Returns false,
+    /// changing nothing, if this parser already received input or the
+    /// encoding of the checkpoint is unknown.
+    pub fn restore_checkpoint(&self, checkpoint: ParserCheckpoint) -> bool {
+        if self.bytes_received.get() > 0 || self.chars_tokenized.get() > 0 {
+            return false;
+        }
+        let decoder = match checkpoint.encoding {
+            Some(ref name) => match Encoding::for_label(name.as_bytes()) {
+                Some(encoding) => Some(NetworkDecoder::restore(
+                    encoding,
+                    &checkpoint.undecoded_bytes,
+                )),
+                None => return false,
+            },
+            None => None,
+        };
+        *self.network_decoder.borrow_mut() = decoder;
+        *self.bom_sniff.borrow_mut() = checkpoint.bom_sniff;
+        // The XML declaration is only looked for at the start of the input.
+        if checkpoint.bytes_received > 0 {
+            *self.xml_declaration_buffer.borrow_mut() = None;
+        }
+        self.at_start_of_input
+            .set(checkpoint.chars_tokenized == 0 && checkpoint.pending_input.is_empty());
+        if !checkpoint.pending_input.is_empty() {
+            let len = checkpoint.pending_input.len();
+            self.network_input
+                .borrow_mut()
+                .push_back(StrTendril::from(checkpoint.pending_input));
+            self.update_buffered_input_size(|size| size.push(len));
+        }
+        self.last_chunk_received.set(checkpoint.last_chunk_received);
+        self.bytes_received.set(checkpoint.bytes_received);
+        self.chars_tokenized.set(checkpoint.chars_tokenized);
+
+        self.document.set_current_parser(Some(self));
+        if !self.suspended.get() {
+            self.parse_sync();
+        }
+        true
+    }
+
+    /// Drops the U+FEFF BYTE ORDER MARK the input starts with, if any, so it
+    /// never ends up in a text node. The network decoder already drops the BOM
+    /// of byte input, but input given as a string may still start with one.
+    fn strip_bom_at_start_of_input(&self, chunk: &mut StrTendril) {
+        let mut at_start = self.at_start_of_input.get();
+        strip_bom_at_start_of_input(chunk, &mut at_start);
+        self.at_start_of_input.set(at_start);
+    }
+
+    /// Lets the tokenizer know that a script inserts `chunk` into the input,
+    /// so that it keeps counting the lines of the network input. The input a
+    /// script-created parser is written to when no script of its own runs is
+    /// its network input.
+    fn insert_script_input(&self, chunk: &str) {
+        if self.script_nesting_level.get() > 0 {
+            self.tokenizer.borrow_mut().insert_script_input(chunk);
+        }
+    }
+
+    /// The URLs of the subresources referenced by the input seen so far,
+    /// as found while scanning the input for resources to prefetch. These are
+    /// only collected if `dom.servoparser.collect_resource_urls.enabled` is set,
+    /// and only for documents with a browsing context. A `document.open()`
+    /// replaces the parser, and with it the URLs collected so far.
+    pub fn resource_urls(&self) -> Vec<(ServoUrl, Destination)> {
+        self.prefetch_tokenizer
+            .borrow()
+            .as_ref()
+            .map_or(vec![], |tokenizer| tokenizer.resource_urls().to_vec())
+    }
+
     pub fn can_write(&self) -> bool {
         self.script_created_parser || self.script_nesting_level.get() > 0
     }
 
     /// Steps 6-8 of <https://html.spec.whatwg.org/multipage/#document.write()>
     pub fn write(&self, text: Vec<DOMString>) {
-        assert!(self.can_write());
+        // The document only writes to a parser that can be written to, but
+        // scripts running at an unexpected time may still get a parser that
+        // has already stopped parsing here. Don't feed its tokenizer.
+        if self.aborted.get() || self.finished.get() {
+            warn!("Ignoring document.write() to a parser that stopped parsing");
+            return;
+        }
+        if !self.can_write() {
+            warn!("Ignoring document.write() to a parser that can't be written to");
+            return;
+        }
+
+        self.tokenizer
+            .borrow_mut()
+            .warn_deprecated(DeprecatedFeature::DocumentWrite);
+
+        // Script input is already decoded, so there is no BOM to sniff anymore.
+        if self.bom_sniff.borrow().is_some() {
+            *self.bom_sniff.borrow_mut() = None;
+        }
 
         if self.document.has_pending_parsing_blocking_script() {
             // There is already a pending parsing blocking script so the
             // parser is suspended, we just append everything to the
             // script input and abort these steps.
             for chunk in text {
+                self.insert_script_input(&chunk);
+                self.update_buffered_input_size(|size| size.push(chunk.len()));
                 self.script_input
                     .borrow_mut()
                     .push_back(String::from(chunk).into());
@@ -362,59 +1226,374 @@ impl ServoParser {
 
         let mut input = BufferQueue::new();
         for chunk in text {
-            input.push_back(String::from(chunk).into());
+            let mut chunk = StrTendril::from(String::from(chunk));
+            self.strip_bom_at_start_of_input(&mut chunk);
+            self.insert_script_input(&chunk);
+            input.push_back(chunk);
         }
 
-        self.tokenize(|tokenizer| tokenizer.feed(&mut input));
+        let input = RefCell::new(input);
+        self.tokenize(|| input.borrow_mut());
+        let mut input = input.into_inner();
+        self.measure_buffered_input();
+
+        if self.aborted.get() {
+            return;
+        }
 
         if self.suspended.get() {
             // Parser got suspended, insert remaining input at end of
             // script input, following anything written by scripts executed
             // reentrantly during this call.
             while let Some(chunk) = input.pop_front() {
+                self.update_buffered_input_size(|size| size.push(chunk.len()));
                 self.script_input.borrow_mut().push_back(chunk);
             }
             return;
         }
 
         assert!(input.is_empty());
+
+        // A script in the input closed the parser, which couldn't finish
+        // while the script ran.
+        if self.last_chunk_received.get() && self.script_nesting_level.get() == 0 {
+            self.parse_sync();
+        }
     }
 
-    // Steps 4-6 of https://html.spec.whatwg.org/multipage/#dom-document-close
-    pub fn close(&self) {
+    /// Feeds raw bytes to a script-created parser. The bytes go through BOM
+    /// sniffing and the network decoder, exactly like input received from the
+    /// network, and end up in the network input after any pending script input.
+    ///
+    /// Once `close()` has been called, the last chunk has been received and the
+    /// network decoder has been flushed, so any further bytes are ignored.
+    pub fn feed_script_bytes(&self, bytes: Vec<u8>) {
         assert!(self.script_created_parser);
+        assert!(self.can_write());
 
-        // Step 4.
-        self.last_chunk_received.set(true);
-
-        if self.suspended.get() {
-            // Step 5.
+        if self.last_chunk_received.get() || self.aborted.get() {
             return;
         }
 
-        // Step 6.
-        self.parse_sync();
+        self.push_bytes_input_chunk(bytes);
+        if !self.suspended.get() {
+            self.parse_sync();
+        }
     }
 
-    // https://html.spec.whatwg.org/multipage/#abort-a-parser
+    // Steps 4-6 of https://html.spec.whatwg.org/multipage/#dom-document-close
+    pub fn close(&self) {
+        assert!(self.script_created_parser);
+
+        let stopped = self.aborted.get() || self.finished.get();
+        let action = close_action(
+            stopped,
+            self.suspended.get(),
+            self.script_nesting_level.get(),
+        );
+        if action == CloseAction::Ignore {
+            return;
+        }
+
+        // Step 4.
+        self.last_chunk_received.set(true);
+
+        if action == CloseAction::Defer {
+            // Step 5.
+            return;
+        }
+
+        // Step 6.
+        self.parse_sync();
+    }
+
+    // https://html.spec.whatwg.org/multipage/#abort-a-parser
     pub fn abort(&self) {
-        assert!(!self.aborted.get());
+        // A halted parser stopped parsing already, but it's still the current
+        // parser of its document, which only has to finish loading now.
+        if self.aborted.get() {
+            self.document
+                .set_ready_state(DocumentReadyState::Interactive);
+            self.document.set_current_parser(None);
+            self.document.set_ready_state(DocumentReadyState::Complete);
+            return;
+        }
         self.aborted.set(true);
 
         // Step 1.
         *self.script_input.borrow_mut() = BufferQueue::new();
         *self.network_input.borrow_mut() = BufferQueue::new();
+        self.bytes_received.set(0);
+        self.chars_tokenized.set(0);
+        self.buffered_input_size.set(Default::default());
+        self.measure_buffered_input();
+        self.first_script_prepare_time.set(None);
 
         // Step 2.
         self.document
             .set_ready_state(DocumentReadyState::Interactive);
 
         // Step 3.
-        self.tokenizer.borrow_mut().end();
+        self.end_tokenizer();
         self.document.set_current_parser(None);
 
         // Step 4.
         self.document.set_ready_state(DocumentReadyState::Complete);
+
+        self.cancel_prefetches();
+        self.run_abort_callback();
+    }
+
+    /// Stops parsing like `abort`, but leaves the document as it is: its
+    /// readiness doesn't change, and this remains its current parser, so the
+    /// document doesn't finish loading until it's aborted itself. Unlike with
+    /// `abort`, the input that wasn't tokenized yet is kept for inspection,
+    /// see `pending_input_state`. Halting is final: the tokenizer is ended,
+    /// and nothing of the input is ever tokenized anymore. A parser that
+    /// already stopped parsing, e.g. because it was aborted, is left alone.
+    pub fn halt(&self) {
+        if self.aborted.get() || self.finished.get() {
+            warn!("Ignoring a halt of a parser that stopped parsing");
+            return;
+        }
+        self.aborted.set(true);
+        self.end_tokenizer();
+        self.cancel_prefetches();
+        self.run_abort_callback();
+    }
+
+    /// Nothing more of the document is parsed once the parser is aborted or
+    /// halted, so whatever was prefetched for it and not requested by the
+    /// document itself yet is wasting bandwidth.
+    fn cancel_prefetches(&self) {
+        if let Some(ref mut tokenizer) = *self.prefetch_tokenizer.borrow_mut() {
+            tokenizer.cancel_pending_fetches();
+        }
+    }
+
+    /// Ends the tokenizer of an aborted parser, or, if the parser was aborted
+    /// by script running from within the tokenizer, e.g. a script opening the
+    /// document, leaves it to `tokenize` to end it once it returns.
+    fn end_tokenizer(&self) {
+        match self.tokenizer.try_borrow_mut() {
+            Ok(mut tokenizer) => tokenizer.end(),
+            Err(_) => self.tokenizer_end_pending.set(true),
+        }
+    }
+
+    /// Runs the abort callback, unless the tokenizer is still to be ended, in
+    /// which case `tokenize` runs it once it did, so that the callback never
+    /// runs from within the tokenizer.
+    fn run_abort_callback(&self) {
+        self.finish_callback.borrow_mut().take();
+        if !self.tokenizer_end_pending.get() {
+            self.run_completion_callback(&self.abort_callback);
+        }
+    }
+
+    /// Runs a completion callback in the realm of the document. It's taken out
+    /// of its cell first, so that it runs at most once, and without anything
+    /// of the parser being borrowed.
+    fn run_completion_callback(&self, callback: &DomRefCell<Option<ParserCompletionCallback>>) {
+        let callback = callback.borrow_mut().take();
+        if let Some(callback) = callback {
+            let _realm = enter_realm(&*self.document);
+            callback();
+        }
+    }
+
+    /// Aborts the parser on the first parse error in strict mode, and replaces
+    /// the content of the document with a description of the error.
+    fn abort_on_strict_parse_error(&self, error: StrictParseError) {
+        warn!(
+            "Aborting the parse of {} on line {}: {}",
+            self.document.url(),
+            error.line,
+            error.message
+        );
+        self.abort_and_replace_content_with_text(format!(
+            "Parse error on line {}: {}",
+            error.line, error.message
+        ));
+    }
+
+    /// Aborts a parse that took more input or nodes than its budget allows,
+    /// and replaces the content of the document with a page saying so.
+    fn abort_on_exceeded_parse_budget(&self, exceeded: ParseBudgetExceeded) {
+        let message = match exceeded {
+            ParseBudgetExceeded::Bytes(max) => format!("more than {} bytes", max),
+            ParseBudgetExceeded::Nodes(max) => format!("more than {} nodes", max),
+        };
+        warn!(
+            "Aborting the parse of {}, which has {}",
+            self.document.url(),
+            message
+        );
+        self.abort_and_replace_content_with_text(format!(
+            "This document was not shown because it is too large: it has {}.",
+            message
+        ));
+    }
+
+    /// Aborts the parser through its document, which cancels the loads of the
+    /// document, replaces the content of the document with a `<pre>` of
+    /// `text`, and then finishes loading the document, so that its load event
+    /// still fires.
+    fn abort_and_replace_content_with_text(&self, text: String) {
+        self.document.abort_parser_to_replace_content(self);
+        self.replace_content_with_text(text);
+        self.document.finish_load(LoadType::PageSource(self.url()));
+    }
+
+    /// Replaces the content of the document with a `<pre>` of `text`.
+    fn replace_content_with_text(&self, text: String) {
+        let document = &*self.document;
+        let create_element = |name| {
+            Element::create(
+                QualName::new(None, ns!(html), name),
+                None,
+                document,
+                ElementCreator::ScriptCreated,
+                CustomElementCreationMode::Synchronous,
+                None,
+            )
+        };
+        let html = create_element(local_name!("html"));
+        let body = create_element(local_name!("body"));
+        let pre = create_element(local_name!("pre"));
+        let text = Text::new(DOMString::from(text), document);
+        Node::replace_all(None, document.upcast());
+        for (parent, child) in [
+            (document.upcast::<Node>(), html.upcast::<Node>()),
+            (html.upcast(), body.upcast()),
+            (body.upcast(), pre.upcast()),
+            (pre.upcast(), text.upcast()),
+        ] {
+            parent.AppendChild(child).expect("Appending failed");
+        }
+    }
+
+    /// Styles a generic XML document without any style of its own with the
+    /// built-in XML viewer stylesheet, so that its tree is readable. Documents
+    /// rooted in a namespace that has a rendering of its own are left alone.
+    fn apply_xml_viewer_stylesheet(&self) {
+        let root = match self.document.GetDocumentElement() {
+            Some(root) => root,
+            None => return,
+        };
+        if *root.namespace() == ns!(html) ||
+            *root.namespace() == ns!(svg) ||
+            *root.namespace() == ns!(mathml)
+        {
+            return;
+        }
+        let has_style = self.document.stylesheet_count() > 0 ||
+            root.upcast::<Node>()
+                .traverse_preorder(ShadowIncluding::No)
+                .any(|node| node.is::<HTMLStyleElement>() || node.is::<HTMLLinkElement>());
+        if has_style {
+            return;
+        }
+
+        let style = Element::create(
+            QualName::new(None, ns!(html), local_name!("style")),
+            None,
+            &self.document,
+            ElementCreator::ScriptCreated,
+            CustomElementCreationMode::Synchronous,
+            None,
+        );
+        style
+            .upcast::<Node>()
+            .SetTextContent(Some(DOMString::from(resources::read_string(
+                Resource::XMLViewerCSS,
+            ))));
+        root.upcast::<Node>()
+            .AppendChild(style.upcast())
+            .expect("Appending failed");
+    }
+
+    /// Replaces the document with a page synthesized from `html`, such as an
+    /// error page of the embedder, and parses it to completion. The input
+    /// received so far is dropped, and so is any further network input, and
+    /// a script the parser is blocked on is never run.
+    ///
+    /// The page is parsed by a new HTML tokenizer, so hooks installed on this
+    /// parser beforehand have to be installed again to apply to it. If this
+    /// is called while tokenizing, e.g. from a script run by the parser, the
+    /// tokenizer can't be replaced yet, and the page is loaded from a task.
+    pub fn load_synthesized_document(&self, html: String) {
+        if self.aborted.get() || self.finished.get() {
+            warn!(
+                "Ignoring a synthesized document for {}, which isn't being parsed anymore",
+                self.document.url()
+            );
+            return;
+        }
+
+        let mut tokenizer = match self.tokenizer.try_borrow_mut() {
+            Ok(tokenizer) => tokenizer,
+            Err(_) => {
+                let parser = Trusted::new(self);
+                let window = self.document.window();
+                let (source, canceller) = window
+                    .task_manager()
+                    .dom_manipulation_task_source_with_canceller();
+                let _ = source.queue_with_canceller(
+                    task!(load_synthesized_document: move || {
+                        parser.root().load_synthesized_document(html);
+                    }),
+                    &canceller,
+                );
+                return;
+            },
+        };
+        *self.script_input.borrow_mut() = BufferQueue::new();
+        *self.network_input.borrow_mut() = BufferQueue::new();
+        *self.prefetch_input.borrow_mut() = BufferQueue::new();
+        self.buffered_input_size.set(Default::default());
+        self.parse_budget.set(ParseBudget::from_prefs());
+        *self.network_decoder.borrow_mut() = None;
+        *self.xml_declaration_buffer.borrow_mut() = None;
+        *self.encoding_sniffing_buffer.borrow_mut() = None;
+        self.use_xml_viewer.set(false);
+        self.first_script_prepare_time.set(None);
+        self.bytes_received.set(0);
+        self.chars_tokenized.set(0);
+        self.at_start_of_input.set(true);
+
+        tokenizer.end();
+        *tokenizer = Tokenizer::Html(self::html::Tokenizer::new(
+            &self.document,
+            self.url(),
+            None,
+            ParsingAlgorithm::Normal,
+        ));
+        drop(tokenizer);
+
+        // The script of the old page the parser is blocked on, if any, is
+        // dropped, so that it doesn't run against the new one once loaded.
+        self.document.forget_pending_parsing_blocking_script();
+        self.suspended.set(false);
+        Node::replace_all(None, self.document.upcast());
+
+        self.document.set_current_parser(Some(self));
+        self.last_chunk_received.set(true);
+        self.push_synthesized_document(html);
+    }
+
+    /// Marks the document as synthesized and feeds it `html`.
+    fn push_synthesized_document(&self, html: String) {
+        self.synthesized_document.set(true);
+        self.push_synthesized_input(html);
+    }
+
+    /// Feeds more of a synthesized document being received.
+    fn push_synthesized_input(&self, html: String) {
+        self.push_string_input_chunk(html);
+        if !self.suspended.get() {
+            self.parse_sync();
+        }
     }
 
     // https://html.spec.whatwg.org/multipage/#active-parser
@@ -422,6 +1601,127 @@ impl ServoParser {
         self.script_nesting_level() > 0 && !self.aborted.get()
     }
 
+    /// Installs a hook to observe the attributes of the elements created from
+    /// now on, see [`RawAttributesObserver`].
+    pub fn set_raw_attributes_observer(&self, observer: Option<RawAttributesObserver>) {
+        self.tokenizer
+            .borrow_mut()
+            .set_raw_attributes_observer(observer);
+    }
+
+    /// Installs a hook to block elements created from now on, see
+    /// [`ElementBlocker`].
+    pub fn set_element_blocker(&self, blocker: Option<ElementBlocker>) {
+        self.tokenizer.borrow_mut().set_element_blocker(blocker);
+    }
+
+    /// Sets whether the scripts parsed from now on are skipped rather than
+    /// prepared, so that the parser never waits for them, which is off by
+    /// default. This is for extracting the DOM of a page quickly, e.g. for
+    /// headless snapshots, and isn't how a page is meant to load: none of
+    /// these scripts run, not even `async` or `defer` ones, so their effects
+    /// on the document, and the `document.write()` calls they would make, are
+    /// missing. They are marked as already started, so they don't run when
+    /// moved elsewhere either.
+    pub fn set_script_blocking_disabled(&self, disabled: bool) {
+        self.tokenizer
+            .borrow_mut()
+            .set_script_blocking_disabled(disabled);
+    }
+
+    /// Installs a hook to observe the doctype of the document, see
+    /// [`DoctypeObserver`].
+    pub fn set_doctype_observer(&self, observer: Option<DoctypeObserver>) {
+        self.tokenizer.borrow_mut().set_doctype_observer(observer);
+    }
+
+    /// Installs a hook to know when the head of the document has been parsed,
+    /// see [`HeadCompleteObserver`]. It is called at most once.
+    pub fn set_head_complete_observer(&self, observer: Option<HeadCompleteObserver>) {
+        self.tokenizer
+            .borrow_mut()
+            .set_head_complete_observer(observer);
+    }
+
+    /// Installs a hook to know when the children of the body of the document
+    /// have been parsed, see [`BodyChildCompleteObserver`].
+    pub fn set_body_child_complete_observer(&self, observer: Option<BodyChildCompleteObserver>) {
+        self.tokenizer
+            .borrow_mut()
+            .set_body_child_complete_observer(observer);
+    }
+
+    /// Installs a hook to trace the microtask checkpoints this parser
+    /// performs, see [`MicrotaskCheckpointObserver`].
+    pub fn set_microtask_checkpoint_observer(&self, observer: Option<MicrotaskCheckpointObserver>) {
+        self.microtask_checkpoint_observer.set(observer);
+    }
+
+    /// Installs a hook to trace the synchronous creation of custom elements
+    /// by this parser, see [`CustomElementCreationObserver`].
+    pub fn set_custom_element_creation_observer(
+        &self,
+        observer: Option<CustomElementCreationObserver>,
+    ) {
+        self.custom_element_creation_observer.set(observer);
+    }
+
+    /// Sets a callback to run once this parser finished parsing, after the
+    /// document was told that its load finished. It isn't run if the parser
+    /// is aborted instead.
+    pub fn set_finish_callback(&self, callback: Option<ParserCompletionCallback>) {
+        *self.finish_callback.borrow_mut() = callback;
+    }
+
+    /// Sets a callback to run once this parser is aborted or halted. It isn't
+    /// run if the parser finishes instead.
+    pub fn set_abort_callback(&self, callback: Option<ParserCompletionCallback>) {
+        *self.abort_callback.borrow_mut() = callback;
+    }
+
+    fn perform_a_microtask_checkpoint(&self, site: MicrotaskCheckpointSite) {
+        if let Some(observer) = self.microtask_checkpoint_observer.get() {
+            observer(site);
+        }
+        self.document
+            .window()
+            .upcast::<GlobalScope>()
+            .perform_a_microtask_checkpoint();
+    }
+
+    /// Sets whether the values of the `<input>` elements created from now on
+    /// are sanitized once their attributes are set, which is the default.
+    /// Documents only parsed for their structure, which are never rendered
+    /// nor interacted with, can skip it, and keep the values as written.
+    pub fn set_input_value_sanitization(&self, enabled: bool) {
+        self.tokenizer
+            .borrow_mut()
+            .set_input_value_sanitization(enabled);
+    }
+
+    /// Starts extracting the text inserted from now on, see [`TextExtractor`].
+    pub fn enable_text_extraction(&self) {
+        self.tokenizer.borrow_mut().enable_text_extraction();
+    }
+
+    /// The text extracted so far, if text extraction was enabled.
+    pub fn extracted_text(&self) -> Option<String> {
+        self.tokenizer.borrow().extracted_text()
+    }
+
+    /// The deprecated constructs found so far, see [`DeprecationWarning`].
+    pub fn deprecation_warnings(&self) -> Vec<DeprecationWarning> {
+        self.tokenizer.borrow().deprecation_warnings().to_vec()
+    }
+
+    /// Installs a hook to inspect and transform the comments encountered from
+    /// now on, see [`CommentTransformer`].
+    pub fn set_comment_transformer(&self, transformer: Option<CommentTransformer>) {
+        self.tokenizer
+            .borrow_mut()
+            .set_comment_transformer(transformer);
+    }
+
     #[allow(crown::unrooted_must_root)]
     fn new_inherited(
         document: &Document,
@@ -432,6 +1732,7 @@ impl ServoParser {
         ServoParser {
             reflector: Reflector::new(),
             document: Dom::from_ref(document),
+            url: tokenizer.url().clone(),
             bom_sniff: DomRefCell::new(Some(Vec::with_capacity(3))),
             network_decoder: DomRefCell::new(Some(NetworkDecoder::new(document.encoding()))),
             network_input: DomRefCell::new(BufferQueue::new()),
@@ -441,9 +1742,29 @@ impl ServoParser {
             suspended: Default::default(),
             script_nesting_level: Default::default(),
             aborted: Default::default(),
+            finished: Default::default(),
             script_created_parser: kind == ParserKind::ScriptCreated,
-            prefetch_tokenizer: DomRefCell::new(prefetch::Tokenizer::new(document)),
+            prefetch_tokenizer: Default::default(),
             prefetch_input: DomRefCell::new(BufferQueue::new()),
+            prefetch_dropped: Cell::new(false),
+            xml_declaration_buffer: Default::default(),
+            encoding_sniffing_buffer: Default::default(),
+            encoding_source: Cell::new(EncodingSource::Initial),
+            script_counters: Default::default(),
+            first_script_prepare_time: Default::default(),
+            use_xml_viewer: Default::default(),
+            bytes_received: Default::default(),
+            chars_tokenized: Default::default(),
+            at_start_of_input: Cell::new(true),
+            buffered_input_size: Default::default(),
+            parse_budget: Cell::new(ParseBudget::from_prefs()),
+            nodes_inserted_since_reflow: Default::default(),
+            synthesized_document: Default::default(),
+            tokenizer_end_pending: Default::default(),
+            microtask_checkpoint_observer: Default::default(),
+            custom_element_creation_observer: Default::default(),
+            finish_callback: Default::default(),
+            abort_callback: Default::default(),
         }
     }
 
@@ -465,10 +1786,12 @@ impl ServoParser {
         )
     }
 
-    fn push_tendril_input_chunk(&self, chunk: StrTendril) {
+    fn push_tendril_input_chunk(&self, mut chunk: StrTendril) {
+        self.strip_bom_at_start_of_input(&mut chunk);
         if chunk.is_empty() {
             return;
         }
+        self.update_parse_budget(|budget| budget.add_bytes(chunk.len()));
         // Per https://github.com/whatwg/html/issues/1495
         // stylesheets should not be loaded for documents
         // without browsing contexts.
@@ -476,24 +1799,128 @@ impl ServoParser {
         // suggests that no content should be preloaded in such a case.
         // We're conservative, and only prefetch for documents
         // with browsing contexts.
-        if self.document.browsing_context().is_some() {
+        if self.prefetches() {
             // Push the chunk into the prefetch input stream,
             // which is tokenized eagerly, to scan for resources
             // to prefetch. If the user script uses `document.write()`
             // to overwrite the network input, this prefetching may
             // have been wasted, but in most cases it won't.
-            let mut prefetch_input = self.prefetch_input.borrow_mut();
-            prefetch_input.push_back(chunk.clone());
-            self.prefetch_tokenizer
-                .borrow_mut()
-                .feed(&mut *prefetch_input);
+            self.update_buffered_input_size(|size| size.push(chunk.len()));
+            self.prefetch_input.borrow_mut().push_back(chunk.clone());
+            self.feed_prefetch_tokenizer();
         }
         // Push the chunk into the network input stream,
         // which is tokenized lazily.
+        self.update_buffered_input_size(|size| size.push(chunk.len()));
         self.network_input.borrow_mut().push_back(chunk);
     }
 
+    /// Tells the prefetcher that the document itself requested `url`, which
+    /// is then no longer cancelled if the parser is aborted, as the load of
+    /// the document may be waiting on the prefetched response.
+    pub fn adopt_prefetch(&self, url: &ServoUrl) {
+        if let Ok(mut tokenizer) = self.prefetch_tokenizer.try_borrow_mut() {
+            if let Some(ref mut tokenizer) = *tokenizer {
+                tokenizer.adopt_url(url);
+            }
+        }
+    }
+
+    /// Keeps the prefetcher from fetching `urls`, which were fetched already.
+    fn skip_prefetch_urls(&self, urls: HashSet<ServoUrl>) {
+        if urls.is_empty() || !self.prefetches() {
+            return;
+        }
+        self.with_prefetch_tokenizer(|tokenizer| tokenizer.skip_urls(urls));
+    }
+
+    /// Fetches the resources preloaded by the `Link` headers of the response,
+    /// before any of its body is parsed.
+    fn preload_link_headers(&self, links: &[prefetch::LinkHeaderValue]) {
+        // As with the resources found in the markup, nothing is preloaded
+        // for documents without browsing contexts.
+        if links.is_empty() || !self.prefetches() {
+            return;
+        }
+        self.with_prefetch_tokenizer(|tokenizer| tokenizer.preload_link_headers(links));
+    }
+
+    /// Feeds the prefetch input to the prefetch tokenizer, unless the parser
+    /// is blocked on a script and `dom.servoparser.pause_prefetch_while_suspended`
+    /// is set, in which case the input is kept, in order, until the parser
+    /// resumes.
+    fn feed_prefetch_tokenizer(&self) {
+        if !self.prefetches() {
+            return;
+        }
+        let mut prefetch_input = self.prefetch_input.borrow_mut();
+        if prefetch_input.is_empty() {
+            return;
+        }
+        let suspended = self.suspended.get();
+        let pauses = pref!(dom.servoparser.pause_prefetch_while_suspended.enabled);
+        let fed = self.with_prefetch_tokenizer(|tokenizer| {
+            feed_prefetch_input(tokenizer, &mut prefetch_input, suspended, pauses)
+        });
+        drop(prefetch_input);
+        if fed {
+            self.measure_buffered_input();
+        }
+    }
+
+    /// Scans the content parsed so far for resources to prefetch, for parsers
+    /// whose document got a browsing context after some of its content was
+    /// parsed, which nothing was prefetched for. The resources the document
+    /// already loaded, as told by its resource timing entries, and those the
+    /// prefetch tokenizer already requested, aren't fetched again.
+    pub fn rerun_prefetch(&self) {
+        if self.aborted.get() || !self.prefetches() {
+            return;
+        }
+        let root = match self.document.GetDocumentElement() {
+            Some(root) => root,
+            None => return,
+        };
+        let content = match root.serialize(TraversalScope::IncludeNode, false) {
+            Ok(content) => content,
+            Err(_) => return,
+        };
+        let document_url = self.document.url();
+        let loaded_urls = self
+            .document
+            .global()
+            .performance()
+            .GetEntriesByType(DOMString::from("resource"))
+            .iter()
+            .filter_map(|entry| ServoUrl::parse_with_base(Some(&document_url), entry.name()).ok())
+            .collect::<Vec<_>>();
+
+        let mut rescan = prefetch::Tokenizer::new(&self.document);
+        rescan.skip_urls(loaded_urls);
+        if let Some(ref tokenizer) = *self.prefetch_tokenizer.borrow() {
+            rescan.skip_urls(tokenizer.fetched_urls().iter().cloned());
+        }
+        let mut input = BufferQueue::new();
+        input.push_back(StrTendril::from(String::from(content)));
+        rescan.rescan(&mut input);
+        rescan.fetch_next_navigation_resources();
+
+        // The input still to come is scanned by the prefetch tokenizer, which
+        // mustn't fetch what was just fetched again.
+        let fetched_urls = rescan.fetched_urls().clone();
+        self.with_prefetch_tokenizer(|tokenizer| tokenizer.skip_urls(fetched_urls));
+    }
+
+    fn with_prefetch_tokenizer<R>(&self, f: impl FnOnce(&mut prefetch::Tokenizer) -> R) -> R {
+        debug_assert!(self.prefetches());
+        let mut tokenizer = self.prefetch_tokenizer.borrow_mut();
+        f(tokenizer.get_or_insert_with(|| prefetch::Tokenizer::new(&self.document)))
+    }
+
     fn push_bytes_input_chunk(&self, chunk: Vec<u8>) {
+        self.bytes_received
+            .set(self.bytes_received.get() + chunk.len());
+
         // BOM sniff. This is needed because NetworkDecoder will switch the
         // encoding based on the BOM, but it won't change
         // `self.document.encoding` in the process.
@@ -504,6 +1931,7 @@ impl ServoParser {
                     partial_bom.extend(chunk.iter().take(3 - partial_bom.len()).copied());
                     if let Some((encoding, _)) = Encoding::for_bom(&partial_bom) {
                         self.document.set_encoding(encoding);
+                        self.encoding_source.set(EncodingSource::Bom);
                     }
                     drop(bom_sniff);
                     *self.bom_sniff.borrow_mut() = None;
@@ -513,6 +1941,16 @@ impl ServoParser {
             }
         }
 
+        let chunk = match self.buffer_for_xml_declaration(chunk) {
+            Some(chunk) => chunk,
+            None => return,
+        };
+
+        let chunk = match self.buffer_for_encoding_sniffing(chunk) {
+            Some(chunk) => chunk,
+            None => return,
+        };
+
         // For byte input, we convert it to text using the network decoder.
         let chunk = self
             .network_decoder
@@ -523,6 +1961,214 @@ impl ServoParser {
         self.push_tendril_input_chunk(chunk);
     }
 
+    /// Buffers `chunk` while the input could still start with an XML
+    /// declaration that doesn't end yet, and returns the input to decode once
+    /// the declaration ends, can't be one, exceeds `MAX_XML_DECLARATION_LEN`,
+    /// or all of the input was received. The network decoder is then switched
+    /// to the encoding declared, if any. This must be done before anything is
+    /// decoded, and a BOM takes precedence.
+    fn buffer_for_xml_declaration(&self, chunk: Vec<u8>) -> Option<Vec<u8>> {
+        let mut buffer = self.xml_declaration_buffer.borrow_mut();
+        let bytes = match *buffer {
+            Some(ref mut bytes) => bytes,
+            None => return Some(chunk),
+        };
+        bytes.extend_from_slice(&chunk);
+        if !self.last_chunk_received.get() &&
+            bytes.len() < MAX_XML_DECLARATION_LEN &&
+            self::xml::is_partial_xml_declaration(bytes)
+        {
+            return None;
+        }
+        let bytes = buffer.take().unwrap();
+        drop(buffer);
+        let declared = self::xml::xml_declaration_encoding(&bytes)
+            .filter(|_| Encoding::for_bom(&bytes).is_none());
+        if let Some(encoding) = declared {
+            debug!("Using XML declaration encoding {}", encoding.name());
+            self.set_network_decoder_encoding(encoding, EncodingSource::XmlDeclaration);
+        }
+        Some(bytes)
+    }
+
+    /// Starts holding the network input back until enough of it is known to
+    /// sniff its encoding from its content, if
+    /// `dom.servoparser.encoding_sniffing_buffer_size` is set. This is for
+    /// input whose encoding isn't given by the transport layer, and may still
+    /// be changed by a BOM.
+    fn start_encoding_sniffing(&self) {
+        if pref!(dom.servoparser.encoding_sniffing_buffer_size) > 0 &&
+            self.bytes_received.get() == 0
+        {
+            *self.encoding_sniffing_buffer.borrow_mut() = Some(vec![]);
+        }
+    }
+
+    /// Buffers `chunk` while the encoding of the input is being sniffed, and
+    /// returns the input to decode once enough of it was buffered, or all of
+    /// it was received. The network decoder is then switched to the encoding
+    /// detected, if any.
+    fn buffer_for_encoding_sniffing(&self, chunk: Vec<u8>) -> Option<Vec<u8>> {
+        let mut buffer = self.encoding_sniffing_buffer.borrow_mut();
+        let bytes = match *buffer {
+            Some(ref mut bytes) => bytes,
+            None => return Some(chunk),
+        };
+        bytes.extend_from_slice(&chunk);
+        let size = pref!(dom.servoparser.encoding_sniffing_buffer_size);
+        let last = self.last_chunk_received.get();
+        if !last && (bytes.len() as i64) < size {
+            return None;
+        }
+        let bytes = buffer.take().unwrap();
+        drop(buffer);
+        if Encoding::for_bom(&bytes).is_none() {
+            let tld = self
+                .url
+                .domain()
+                .and_then(|domain| domain.rsplit('.').next());
+            if let Some(encoding) = sniff_content_encoding(&bytes, tld, last) {
+                debug!("Using sniffed encoding {}", encoding.name());
+                self.set_network_decoder_encoding(encoding, EncodingSource::Sniffed);
+            }
+        }
+        Some(bytes)
+    }
+
+    /// Changes the encoding of the network input while parsing, e.g. for a
+    /// late `<meta charset>`. This only succeeds if the bytes decoded so far
+    /// were retained, see `dom.servoparser.encoding_change_buffer_size`, and
+    /// decode to the same input with `encoding`, in which case the rest of the
+    /// input is decoded with it. Otherwise the document has to be parsed again
+    /// with the new encoding, see `redecode_network_input`.
+    pub fn change_encoding(&self, encoding: &'static Encoding) -> bool {
+        let mut network_decoder = self.network_decoder.borrow_mut();
+        match *network_decoder {
+            Some(ref mut decoder) if decoder.change_encoding(encoding) => {
+                self.document.set_encoding(decoder.encoding());
+                self.encoding_source.set(EncodingSource::Meta);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Changes the encoding of the network input to the one declared by a
+    /// `<meta>` the parser inserted, as long as the current encoding is only
+    /// tentative. Returns whether the input is parsed again from the start.
+    /// <https://html.spec.whatwg.org/multipage/#changing-the-encoding-while-parsing>
+    fn handle_encoding_declaration(&self, encoding: &'static Encoding) -> bool {
+        // Only a tentative encoding is changed.
+        // https://html.spec.whatwg.org/multipage/#parsing-main-inhead
+        match self.encoding_source.get() {
+            EncodingSource::Initial | EncodingSource::Sniffed => {},
+            _ => return false,
+        }
+        if self.synthesized_document.get() {
+            return false;
+        }
+
+        // Steps 1-2.
+        let encoding = if encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE {
+            encoding_rs::UTF_8
+        } else if encoding == encoding_rs::X_USER_DEFINED {
+            encoding_rs::WINDOWS_1252
+        } else {
+            encoding
+        };
+
+        // Steps 3-4.
+        if self.change_encoding(encoding) {
+            return false;
+        }
+
+        // Step 5, parsing the input again instead of navigating to it again.
+        if self.redecode_network_input(encoding) {
+            return true;
+        }
+        debug!(
+            "Ignoring the declared encoding {}, as the input can't be decoded again",
+            encoding.name()
+        );
+        false
+    }
+
+    /// Parses the network input received so far again from the start, decoded
+    /// with `encoding`, with a new tokenizer set up like the current one. This
+    /// is only possible while all of the input is retained, see
+    /// `dom.servoparser.encoding_change_buffer_size`, and before any script
+    /// ran, as scripts may have seen the content as decoded so far. Only the
+    /// synchronous HTML tokenizer can be restarted.
+    fn redecode_network_input(&self, encoding: &'static Encoding) -> bool {
+        if self.first_script_prepare_time.get().is_some() || !self.script_input.borrow().is_empty()
+        {
+            return false;
+        }
+        // The retained bytes must be the whole input, which isn't the case for
+        // input given as strings, or bytes held back for encoding sniffing.
+        let retained_bytes = match *self.network_decoder.borrow() {
+            Some(ref decoder) => match decoder.retained_bytes() {
+                Some(retained_bytes)
+                    if !retained_bytes.is_empty() &&
+                        retained_bytes.len() == self.bytes_received.get() =>
+                {
+                    retained_bytes.to_vec()
+                },
+                _ => return false,
+            },
+            None => return false,
+        };
+        let tokenizer = match *self.tokenizer.borrow() {
+            Tokenizer::Html(ref tokenizer) => tokenizer.restarted(),
+            _ => return false,
+        };
+        debug!(
+            "Decoding {} bytes of input again with {}",
+            retained_bytes.len(),
+            encoding.name()
+        );
+
+        // Nothing parsed so far is kept, the old tokenizer isn't even ended so
+        // that none of its elements are popped.
+        *self.tokenizer.borrow_mut() = Tokenizer::Html(tokenizer);
+        Node::replace_all(None, self.document.upcast());
+
+        let mut decoder = NetworkDecoder::new(encoding);
+        let input = decoder.decode(retained_bytes);
+        *self.network_decoder.borrow_mut() = Some(decoder);
+        self.document.set_encoding(encoding);
+        self.encoding_source.set(EncodingSource::Meta);
+
+        let mut network_input = BufferQueue::new();
+        self.chars_tokenized.set(0);
+        self.buffered_input_size.set(Default::default());
+        if !input.is_empty() {
+            self.update_buffered_input_size(|size| size.push(input.len()));
+            network_input.push_back(input);
+        }
+        *self.network_input.borrow_mut() = network_input;
+        true
+    }
+
+    /// What determined the encoding of the network input, so far.
+    pub fn encoding_source(&self) -> EncodingSource {
+        self.encoding_source.get()
+    }
+
+    /// Decodes the network input that is still to come with `encoding`, as
+    /// determined by `source`. This has no effect on input that has already
+    /// been decoded.
+    fn set_network_decoder_encoding(&self, encoding: &'static Encoding, source: EncodingSource) {
+        let mut network_decoder = self.network_decoder.borrow_mut();
+        if let Some(ref mut decoder) = *network_decoder {
+            self.encoding_source.set(source);
+            if decoder.encoding() != encoding {
+                self.document.set_encoding(encoding);
+                *decoder = NetworkDecoder::new(encoding);
+            }
+        }
+    }
+
     fn push_string_input_chunk(&self, chunk: String) {
         // If the input is a string, we don't have a BOM.
         if self.bom_sniff.borrow().is_some() {
@@ -560,23 +2206,49 @@ impl ServoParser {
         // This parser will continue to parse while there is either pending input or
         // the parser remains unsuspended.
 
-        if self.last_chunk_received.get() {
+        let last_chunk_received = self.last_chunk_received.get();
+        if last_chunk_received {
+            if self.xml_declaration_buffer.borrow().is_some() {
+                if let Some(bytes) = self.buffer_for_xml_declaration(vec![]) {
+                    let mut decoder = self.network_decoder.borrow_mut();
+                    let chunk = decoder.as_mut().unwrap().decode(bytes);
+                    drop(decoder);
+                    self.push_tendril_input_chunk(chunk);
+                }
+            }
+            if self.encoding_sniffing_buffer.borrow().is_some() {
+                if let Some(bytes) = self.buffer_for_encoding_sniffing(vec![]) {
+                    let mut decoder = self.network_decoder.borrow_mut();
+                    let chunk = decoder.as_mut().unwrap().decode(bytes);
+                    drop(decoder);
+                    self.push_tendril_input_chunk(chunk);
+                }
+            }
             if let Some(decoder) = self.network_decoder.borrow_mut().take() {
                 let chunk = decoder.finish();
                 if !chunk.is_empty() {
+                    self.update_buffered_input_size(|size| size.push(chunk.len()));
                     self.network_input.borrow_mut().push_back(chunk);
                 }
             }
         }
-        self.tokenize(|tokenizer| tokenizer.feed(&mut *self.network_input.borrow_mut()));
+        self.tokenize(|| self.network_input.borrow_mut());
+        self.measure_buffered_input();
 
-        if self.suspended.get() {
+        // An aborted parser has already ended its tokenizer, and must not be
+        // finished again.
+        if self.suspended.get() || self.aborted.get() {
             return;
         }
 
         assert!(self.network_input.borrow().is_empty());
 
         if self.last_chunk_received.get() {
+            // A script closed the parser while it was tokenizing, so the end
+            // of the network input is yet to be flushed out of the decoder.
+            if !last_chunk_received {
+                return self.do_parse_sync();
+            }
             self.finish();
         }
     }
@@ -591,37 +2263,111 @@ impl ServoParser {
 
     fn parse_bytes_chunk(&self, input: Vec<u8>) {
         self.document.set_current_parser(Some(self));
-        self.push_bytes_input_chunk(input);
-        if !self.suspended.get() {
-            self.parse_sync();
+
+        // Decoding and tokenizing a very large chunk at once would keep the
+        // script thread busy for a long time, so such chunks are parsed in
+        // bounded pieces, as if they had been received separately.
+        let max_chunk_size = pref!(dom.servoparser.max_chunk_size);
+        if max_chunk_size <= 0 || input.len() <= max_chunk_size as usize {
+            self.push_bytes_input_chunk(input);
+            if !self.suspended.get() {
+                self.parse_sync();
+            }
+            return;
+        }
+        // The pieces are large enough to hold a BOM and an XML declaration,
+        // which are only looked for at the start of the input.
+        let max_chunk_size = (max_chunk_size as usize).max(MIN_SUB_CHUNK_SIZE);
+        for chunk in input.chunks(max_chunk_size) {
+            if self.aborted.get() {
+                return;
+            }
+            self.push_bytes_input_chunk(chunk.to_vec());
+            if !self.suspended.get() {
+                self.parse_sync();
+            }
         }
     }
 
-    fn tokenize<F>(&self, mut feed: F)
+    /// Feeds the queue `input` borrows to the tokenizer, running the scripts
+    /// it stops at, until it's empty or the parser is suspended. The queue is
+    /// only borrowed while it's fed, as the scripts may write to the parser.
+    fn tokenize<F, I>(&self, mut input: F)
     where
-        F: FnMut(&mut Tokenizer) -> TokenizerResult<DomRoot<HTMLScriptElement>>,
+        F: FnMut() -> I,
+        I: DerefMut<Target = BufferQueue>,
     {
         loop {
             assert!(!self.suspended.get());
             assert!(!self.aborted.get());
 
-            self.document.reflow_if_reflow_timer_expired();
-            let script = match feed(&mut *self.tokenizer.borrow_mut()) {
-                TokenizerResult::Done => return,
-                TokenizerResult::Script(script) => script,
+            self.reflow_if_needed();
+            let result = {
+                let mut input = input();
+                let mut chunks = vec![];
+                for_each_pending_input(&mut input, |chunk| chunks.push(chunk.clone()));
+                let result = self.tokenizer.borrow_mut().feed(&mut input);
+                let mut remaining = 0;
+                for_each_pending_input(&mut input, |chunk| remaining += chunk.len());
+                self.chars_tokenized
+                    .set(self.chars_tokenized.get() + consumed_chars(&chunks, remaining));
+                result
             };
-
-            // https://html.spec.whatwg.org/multipage/#parsing-main-incdata
-            // branch "An end tag whose tag name is "script"
-            // The spec says to perform the microtask checkpoint before
-            // setting the insertion mode back from Text, but this is not
+            if self.aborted.get() {
+                // The script that aborted the parser replaced the content of
+                // the document, so what was tokenized is dropped.
+                if self.tokenizer_end_pending.replace(false) {
+                    self.tokenizer.borrow_mut().end();
+                    self.run_completion_callback(&self.abort_callback);
+                }
+                return;
+            }
+            let inserted_nodes = self.tokenizer.borrow_mut().take_inserted_node_count();
+            self.nodes_inserted_since_reflow
+                .set(self.nodes_inserted_since_reflow.get() + inserted_nodes);
+            self.update_parse_budget(|budget| budget.add_nodes(inserted_nodes));
+            if let Some(exceeded) = self.parse_budget.get().exceeded() {
+                self.abort_on_exceeded_parse_budget(exceeded);
+                return;
+            }
+            let strict_parse_error = self.tokenizer.borrow_mut().take_strict_parse_error();
+            if let Some(error) = strict_parse_error {
+                self.abort_on_strict_parse_error(error);
+                return;
+            }
+            let declared_encoding = self.tokenizer.borrow_mut().take_declared_encoding();
+            if let Some(encoding) = declared_encoding {
+                if self.handle_encoding_declaration(encoding) {
+                    continue;
+                }
+            }
+            let script = match result {
+                TokenizerResult::Done => return,
+                TokenizerResult::Script(script) => script,
+            };
+
+            // https://html.spec.whatwg.org/multipage/#parsing-main-incdata
+            // branch "An end tag whose tag name is "script"
+            // The spec says to perform the microtask checkpoint before
+            // setting the insertion mode back from Text, but this is not
             // possible with the way servo and html5ever currently
             // relate to each other, and hopefully it is not observable.
             if is_execution_stack_empty() {
-                self.document
-                    .window()
-                    .upcast::<GlobalScope>()
-                    .perform_a_microtask_checkpoint();
+                self.perform_a_microtask_checkpoint(MicrotaskCheckpointSite::ScriptEndTag);
+                // The script nesting level is 0 during the checkpoint, so a
+                // microtask may have opened the document, which aborts this
+                // parser and replaces it with a script-created one. The
+                // script was removed from the document with the rest of its
+                // content, and isn't prepared.
+                if self.aborted.get() {
+                    return;
+                }
+            }
+
+            if self.first_script_prepare_time.get().is_none() {
+                let now = time::get_time();
+                let now_ms = now.sec * 1000 + now.nsec as i64 / 1000000;
+                self.first_script_prepare_time.set(Some(now_ms as u64));
             }
 
             let script_nesting_level = self.script_nesting_level.get();
@@ -629,8 +2375,21 @@ impl ServoParser {
             self.script_nesting_level.set(script_nesting_level + 1);
             script.prepare();
             self.script_nesting_level.set(script_nesting_level);
+            self.document.update_render_blocking();
+
+            let is_parser_blocking = self.document.has_pending_parsing_blocking_script();
+            self.update_script_counters(|counters| {
+                if script_nesting_level > 0 {
+                    counters.nested_prepared += 1;
+                } else {
+                    counters.prepared += 1;
+                }
+                if is_parser_blocking {
+                    counters.parser_blocking += 1;
+                }
+            });
 
-            if self.document.has_pending_parsing_blocking_script() {
+            if is_parser_blocking {
                 self.suspended.set(true);
                 return;
             }
@@ -640,6 +2399,34 @@ impl ServoParser {
         }
     }
 
+    /// Reflows once the reflow timer expired, or, if
+    /// `dom.servoparser.reflow_node_threshold` is positive, once at least that
+    /// many nodes were inserted since the parser last made the document
+    /// reflow, so that reflows follow the progress of the content. Documents
+    /// without a browsing context, like those of fragment parsers, aren't
+    /// rendered and never reflow.
+    fn reflow_if_needed(&self) {
+        let threshold = pref!(dom.servoparser.reflow_node_threshold);
+        if threshold > 0 &&
+            self.nodes_inserted_since_reflow.get() >= threshold as usize &&
+            self.document.browsing_context().is_some()
+        {
+            self.nodes_inserted_since_reflow.set(0);
+            self.document.reflow_for_parsed_content();
+        } else if self.document.reflow_if_reflow_timer_expired() {
+            self.nodes_inserted_since_reflow.set(0);
+        }
+    }
+
+    fn update_script_counters<F>(&self, update: F)
+    where
+        F: FnOnce(&mut ParserScriptCounters),
+    {
+        let mut counters = self.script_counters.get();
+        update(&mut counters);
+        self.script_counters.set(counters);
+    }
+
     // https://html.spec.whatwg.org/multipage/#the-end
     fn finish(&self) {
         assert!(!self.suspended.get());
@@ -653,13 +2440,328 @@ impl ServoParser {
             .set_ready_state(DocumentReadyState::Interactive);
 
         // Step 2.
+        self.finished.set(true);
         self.tokenizer.borrow_mut().end();
         self.document.set_current_parser(None);
 
+        if self.use_xml_viewer.get() {
+            self.apply_xml_viewer_stylesheet();
+        }
+
+        if let Some(ref mut tokenizer) = *self.prefetch_tokenizer.borrow_mut() {
+            tokenizer.fetch_next_navigation_resources();
+        }
+
         // Steps 3-12 are in another castle, namely finish_load.
-        let url = self.tokenizer.borrow().url().clone();
+        let url = self.url();
         self.document.finish_load(LoadType::PageSource(url));
+
+        self.abort_callback.borrow_mut().take();
+        self.run_completion_callback(&self.finish_callback);
+    }
+}
+
+/// The input of a fragment parsed by `parse_html_fragment_in_chunks` that is
+/// still to be parsed.
+struct FragmentChunks<F> {
+    parser: Trusted<ServoParser>,
+    input: VecDeque<String>,
+    on_nodes: F,
+}
+
+impl<F> FragmentChunks<F>
+where
+    F: FnMut(Vec<DomRoot<Node>>) -> bool + Send + 'static,
+{
+    /// Parses the next chunk of input, passes on the nodes known to be
+    /// complete, and queues a task to parse the rest of the input, if any and
+    /// if the nodes were taken.
+    fn parse_next(mut self) {
+        let parser = self.parser.root();
+        let last_chunk = self.input.len() <= 1;
+        if last_chunk {
+            parser.last_chunk_received.set(true);
+        }
+        match self.input.pop_front() {
+            Some(chunk) => parser.parse_string_chunk(chunk),
+            None => parser.parse_sync(),
+        }
+
+        let root_element = parser
+            .document
+            .GetDocumentElement()
+            .expect("no document element");
+        let mut nodes: Vec<_> = root_element.upcast::<Node>().children().collect();
+        if !last_chunk {
+            nodes.pop();
+        }
+        for node in &nodes {
+            node.remove_self();
+        }
+        if !nodes.is_empty() && !(self.on_nodes)(nodes) {
+            return;
+        }
+        if last_chunk {
+            return;
+        }
+
+        let window = parser.document.window();
+        let (source, canceller) = window
+            .task_manager()
+            .dom_manipulation_task_source_with_canceller();
+        let _ = source.queue_with_canceller(
+            task!(parse_fragment_chunk: move || {
+                self.parse_next();
+            }),
+            &canceller,
+        );
+    }
+}
+
+/// Whether `local_name` is that of an obsolete HTML element, which authors
+/// must not use anymore.
+/// <https://html.spec.whatwg.org/multipage/#non-conforming-features>
+pub fn is_obsolete_element(local_name: &str) -> bool {
+    matches!(
+        local_name,
+        "acronym" |
+            "applet" |
+            "basefont" |
+            "bgsound" |
+            "big" |
+            "blink" |
+            "center" |
+            "dir" |
+            "font" |
+            "frame" |
+            "frameset" |
+            "isindex" |
+            "keygen" |
+            "listing" |
+            "marquee" |
+            "menuitem" |
+            "multicol" |
+            "nextid" |
+            "nobr" |
+            "noembed" |
+            "noframes" |
+            "plaintext" |
+            "rb" |
+            "rtc" |
+            "spacer" |
+            "strike" |
+            "tt" |
+            "xmp"
+    )
+}
+
+/// Whether a doctype with the given name and identifiers, in an HTML document,
+/// is a legacy one rather than `<!DOCTYPE html>`, or its legacy-compatible
+/// form with the `about:legacy-compat` system identifier.
+/// <https://html.spec.whatwg.org/multipage/#the-doctype>
+pub fn is_legacy_doctype(name: &str, public_id: &str, system_id: &str) -> bool {
+    !name.eq_ignore_ascii_case("html") ||
+        !public_id.is_empty() ||
+        !(system_id.is_empty() || system_id == "about:legacy-compat")
+}
+
+/// Whether a doctype with the given public and system identifiers is the one of
+/// an XHTML document while the document is parsed as HTML, or the one of an
+/// HTML 4 document while it's parsed as XML. The `<!DOCTYPE html>` of HTML5
+/// has no identifiers, and suits both.
+pub fn doctype_mismatches_document(
+    is_html_document: bool,
+    public_id: &str,
+    system_id: &str,
+) -> bool {
+    let starts_with_ignore_ascii_case = |id: &str, prefix: &str| {
+        id.len() >= prefix.len() &&
+            id.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+    };
+    if is_html_document {
+        starts_with_ignore_ascii_case(public_id, "-//W3C//DTD XHTML") ||
+            starts_with_ignore_ascii_case(system_id, "http://www.w3.org/TR/xhtml")
+    } else {
+        starts_with_ignore_ascii_case(public_id, "-//W3C//DTD HTML") ||
+            starts_with_ignore_ascii_case(system_id, "http://www.w3.org/TR/html4")
+    }
+}
+
+/// Whether an element with the given name and attributes is a `<link>` to an
+/// enabled, non-alternate stylesheet.
+fn is_stylesheet_link(name: &QualName, attrs: &[ElementAttribute]) -> bool {
+    if name.ns != ns!(html) || name.local != local_name!("link") {
+        return false;
+    }
+    let attr = |local_name: LocalName| {
+        attrs
+            .iter()
+            .find(|attr| attr.name.ns == ns!() && attr.name.local == local_name)
+    };
+    if attr(local_name!("disabled")).is_some() {
+        return false;
+    }
+    let rel = match attr(local_name!("rel")) {
+        Some(rel) => &*rel.value,
+        None => return false,
+    };
+    let has_rel = |value: &str| {
+        rel.split_ascii_whitespace()
+            .any(|token| token.eq_ignore_ascii_case(value))
+    };
+    has_rel("stylesheet") && !has_rel("alternate")
+}
+
+/// Whether an element with the given name and attributes is explicitly
+/// render-blocking, i.e. is a `<script>`, a `<style>` or a stylesheet
+/// `<link>` with a `render` token in its `blocking` attribute. Other links
+/// don't load anything that would unblock rendering.
+/// <https://html.spec.whatwg.org/multipage/#potentially-render-blocking>
+pub fn is_render_blocking_element(name: &QualName, attrs: &[ElementAttribute]) -> bool {
+    if name.ns != ns!(html) {
+        return false;
+    }
+    match name.local {
+        local_name!("script") | local_name!("style") => {},
+        local_name!("link") if is_stylesheet_link(name, attrs) => {},
+        _ => return false,
+    }
+    attrs
+        .iter()
+        .find(|attr| attr.name.ns == ns!() && attr.name.local == local_name!("blocking"))
+        .map_or(false, |blocking| {
+            blocking
+                .value
+                .split_ascii_whitespace()
+                .any(|token| token.eq_ignore_ascii_case("render"))
+        })
+}
+
+/// The maximum number of attributes the parser gives an element, see
+/// `dom.servoparser.max_attributes_per_element`. Attributes beyond it are
+/// dropped, which keeps input with a huge number of attributes on an element
+/// from making the parser spend quadratic time on them.
+fn max_attributes_per_element() -> usize {
+    match pref!(dom.servoparser.max_attributes_per_element) {
+        max if max > 0 => max as usize,
+        _ => DEFAULT_MAX_ATTRIBUTES_PER_ELEMENT,
+    }
+}
+
+/// Drops the attributes of `attrs` that would give an element which has
+/// `existing` attributes already more than `max` attributes, and returns how
+/// many were dropped.
+pub fn cap_attributes<T>(attrs: &mut Vec<T>, existing: usize, max: usize) -> usize {
+    let allowed = max.saturating_sub(existing);
+    let dropped = attrs.len().saturating_sub(allowed);
+    attrs.truncate(allowed);
+    dropped
+}
+
+/// The number of characters a tokenizer consumed from the input `chunks`,
+/// given that `remaining` bytes of input were left after it was fed. The
+/// tokenizer consumes input from the front, and stops at the end of a script.
+pub fn consumed_chars(chunks: &[StrTendril], remaining: usize) -> usize {
+    let total: usize = chunks.iter().map(|chunk| chunk.len()).sum();
+    let mut consumed = total.saturating_sub(remaining);
+    let mut chars = 0;
+    for chunk in chunks {
+        if consumed == 0 {
+            break;
+        }
+        let len = consumed.min(chunk.len());
+        chars += chunk.char_indices().take_while(|&(i, _)| i < len).count();
+        consumed -= len;
+    }
+    chars
+}
+
+/// Calls `f` with each chunk of input of `queue`, in order. `BufferQueue` can
+/// only be looked into by taking the chunks out, so they're put back after.
+fn for_each_pending_input<F>(queue: &mut BufferQueue, mut f: F)
+where
+    F: FnMut(&StrTendril),
+{
+    let mut chunks = vec![];
+    while let Some(chunk) = queue.pop_front() {
+        f(&chunk);
+        chunks.push(chunk);
+    }
+    for chunk in chunks {
+        queue.push_back(chunk);
+    }
+}
+
+/// Feeds `input` to the prefetch `tokenizer`, unless the parser is `suspended`
+/// and prefetching `pauses_while_suspended`, in which case the input is kept
+/// as is, to be fed in order once the parser resumes. Returns whether the
+/// input was fed.
+pub fn feed_prefetch_input(
+    tokenizer: &mut prefetch::Tokenizer,
+    input: &mut BufferQueue,
+    suspended: bool,
+    pauses_while_suspended: bool,
+) -> bool {
+    if suspended && pauses_while_suspended {
+        return false;
+    }
+    tokenizer.feed(input);
+    true
+}
+
+/// Moves the input written by scripts while the parser was blocked from
+/// `script_input` to the front of `network_input`, ahead of the input received
+/// in the meantime, and in the order it was written. Only the chunks of
+/// `script_input` are moved, which are usually far fewer than those of the
+/// network input that piled up while a script was loading.
+pub fn prepend_script_input(script_input: &mut BufferQueue, network_input: &mut BufferQueue) {
+    let mut chunks = vec![];
+    while let Some(chunk) = script_input.pop_front() {
+        chunks.push(chunk);
+    }
+    for chunk in chunks.into_iter().rev() {
+        network_input.push_front(chunk);
+    }
+}
+
+/// Copies the input in `queue` to chunks that are no larger than their
+/// content. Chunks split by the tokenizer, or taken from larger buffers, keep
+/// the whole buffer they came from alive otherwise.
+pub fn compact_input(queue: &mut BufferQueue) {
+    let mut compacted = BufferQueue::new();
+    while let Some(chunk) = queue.pop_front() {
+        compacted.push_back(StrTendril::from_slice(&chunk));
+    }
+    *queue = compacted;
+}
+
+/// The size in bytes of the input in `queue`, or `None` if it is being
+/// tokenized.
+fn pending_input_len(queue: &DomRefCell<BufferQueue>) -> Option<usize> {
+    let mut queue = queue.try_borrow_mut().ok()?;
+    let mut len = 0;
+    for_each_pending_input(&mut queue, |chunk| len += chunk.len());
+    Some(len)
+}
+
+/// Splits `input` in pieces of `size` bytes, or a few more to not split a
+/// character. A `size` of 0 leaves `input` whole.
+fn split_at_char_boundaries(mut input: String, size: usize) -> VecDeque<String> {
+    let mut chunks = VecDeque::new();
+    if size == 0 {
+        chunks.push_back(input);
+        return chunks;
+    }
+    while input.len() > size {
+        let mut end = size;
+        while !input.is_char_boundary(end) {
+            end += 1;
+        }
+        let rest = input.split_off(end);
+        chunks.push_back(mem::replace(&mut input, rest));
     }
+    chunks.push_back(input);
+    chunks
 }
 
 struct FragmentParsingResult<I>
@@ -734,22 +2836,206 @@ impl Tokenizer {
         }
     }
 
+    fn set_quirks_mode_override(&mut self, mode: ServoQuirksMode) {
+        match *self {
+            Tokenizer::Html(ref mut tokenizer) => tokenizer.set_quirks_mode_override(mode),
+            Tokenizer::AsyncHtml(ref mut tokenizer) => tokenizer.set_quirks_mode_override(mode),
+            // XML documents are never in quirks mode, so there is nothing to
+            // override.
+            Tokenizer::Xml(_) => {},
+        }
+    }
+
+    fn set_comment_transformer(&mut self, transformer: Option<CommentTransformer>) {
+        match *self {
+            Tokenizer::Html(ref mut tokenizer) => tokenizer.set_comment_transformer(transformer),
+            Tokenizer::AsyncHtml(ref mut tokenizer) => {
+                tokenizer.set_comment_transformer(transformer)
+            },
+            Tokenizer::Xml(ref mut tokenizer) => tokenizer.set_comment_transformer(transformer),
+        }
+    }
+
+    fn set_raw_attributes_observer(&mut self, observer: Option<RawAttributesObserver>) {
+        match *self {
+            Tokenizer::Html(ref mut tokenizer) => tokenizer.set_raw_attributes_observer(observer),
+            Tokenizer::AsyncHtml(ref mut tokenizer) => {
+                tokenizer.set_raw_attributes_observer(observer)
+            },
+            Tokenizer::Xml(ref mut tokenizer) => tokenizer.set_raw_attributes_observer(observer),
+        }
+    }
+
+    fn set_element_blocker(&mut self, blocker: Option<ElementBlocker>) {
+        match *self {
+            Tokenizer::Html(ref mut tokenizer) => tokenizer.set_element_blocker(blocker),
+            Tokenizer::AsyncHtml(ref mut tokenizer) => tokenizer.set_element_blocker(blocker),
+            Tokenizer::Xml(ref mut tokenizer) => tokenizer.set_element_blocker(blocker),
+        }
+    }
+
+    fn set_script_blocking_disabled(&mut self, disabled: bool) {
+        match *self {
+            Tokenizer::Html(ref mut tokenizer) => tokenizer.set_script_blocking_disabled(disabled),
+            Tokenizer::AsyncHtml(ref mut tokenizer) => {
+                tokenizer.set_script_blocking_disabled(disabled)
+            },
+            Tokenizer::Xml(ref mut tokenizer) => tokenizer.set_script_blocking_disabled(disabled),
+        }
+    }
+
+    fn set_doctype_observer(&mut self, observer: Option<DoctypeObserver>) {
+        match *self {
+            Tokenizer::Html(ref mut tokenizer) => tokenizer.set_doctype_observer(observer),
+            Tokenizer::AsyncHtml(ref mut tokenizer) => tokenizer.set_doctype_observer(observer),
+            Tokenizer::Xml(ref mut tokenizer) => tokenizer.set_doctype_observer(observer),
+        }
+    }
+
+    fn set_head_complete_observer(&mut self, observer: Option<HeadCompleteObserver>) {
+        match *self {
+            Tokenizer::Html(ref mut tokenizer) => tokenizer.set_head_complete_observer(observer),
+            Tokenizer::AsyncHtml(ref mut tokenizer) => {
+                tokenizer.set_head_complete_observer(observer)
+            },
+            Tokenizer::Xml(ref mut tokenizer) => tokenizer.set_head_complete_observer(observer),
+        }
+    }
+
+    fn set_body_child_complete_observer(&mut self, observer: Option<BodyChildCompleteObserver>) {
+        match *self {
+            Tokenizer::Html(ref mut tokenizer) => {
+                tokenizer.set_body_child_complete_observer(observer)
+            },
+            Tokenizer::AsyncHtml(ref mut tokenizer) => {
+                tokenizer.set_body_child_complete_observer(observer)
+            },
+            Tokenizer::Xml(ref mut tokenizer) => {
+                tokenizer.set_body_child_complete_observer(observer)
+            },
+        }
+    }
+
+    fn set_input_value_sanitization(&mut self, enabled: bool) {
+        match *self {
+            Tokenizer::Html(ref mut tokenizer) => tokenizer.set_input_value_sanitization(enabled),
+            Tokenizer::AsyncHtml(ref mut tokenizer) => {
+                tokenizer.set_input_value_sanitization(enabled)
+            },
+            Tokenizer::Xml(ref mut tokenizer) => tokenizer.set_input_value_sanitization(enabled),
+        }
+    }
+
+    fn insert_script_input(&mut self, input: &str) {
+        match *self {
+            Tokenizer::Html(ref mut tokenizer) => tokenizer.insert_script_input(input),
+            Tokenizer::AsyncHtml(ref mut tokenizer) => tokenizer.insert_script_input(input),
+            // XML documents can't be written to.
+            Tokenizer::Xml(_) => {},
+        }
+    }
+
+    fn enable_text_extraction(&mut self) {
+        match *self {
+            Tokenizer::Html(ref mut tokenizer) => tokenizer.enable_text_extraction(),
+            Tokenizer::AsyncHtml(ref mut tokenizer) => tokenizer.enable_text_extraction(),
+            Tokenizer::Xml(ref mut tokenizer) => tokenizer.enable_text_extraction(),
+        }
+    }
+
+    fn extracted_text(&self) -> Option<String> {
+        match *self {
+            Tokenizer::Html(ref tokenizer) => tokenizer.extracted_text(),
+            Tokenizer::AsyncHtml(ref tokenizer) => tokenizer.extracted_text(),
+            Tokenizer::Xml(ref tokenizer) => tokenizer.extracted_text(),
+        }
+    }
+
+    fn warn_deprecated(&mut self, feature: DeprecatedFeature) {
+        match *self {
+            Tokenizer::Html(ref mut tokenizer) => tokenizer.warn_deprecated(feature),
+            Tokenizer::AsyncHtml(ref mut tokenizer) => tokenizer.warn_deprecated(feature),
+            Tokenizer::Xml(ref mut tokenizer) => tokenizer.warn_deprecated(feature),
+        }
+    }
+
+    fn deprecation_warnings(&self) -> &[DeprecationWarning] {
+        match *self {
+            Tokenizer::Html(ref tokenizer) => tokenizer.deprecation_warnings(),
+            Tokenizer::AsyncHtml(ref tokenizer) => tokenizer.deprecation_warnings(),
+            Tokenizer::Xml(ref tokenizer) => tokenizer.deprecation_warnings(),
+        }
+    }
+
+    fn kind(&self) -> TokenizerKind {
+        match *self {
+            Tokenizer::Html(_) => TokenizerKind::Html,
+            Tokenizer::AsyncHtml(_) => TokenizerKind::AsyncHtml,
+            Tokenizer::Xml(_) => TokenizerKind::Xml,
+        }
+    }
+
+    fn element_counters(&self) -> ParserElementCounters {
+        match *self {
+            Tokenizer::Html(ref tokenizer) => tokenizer.element_counters(),
+            Tokenizer::AsyncHtml(ref tokenizer) => tokenizer.element_counters(),
+            Tokenizer::Xml(ref tokenizer) => tokenizer.element_counters(),
+        }
+    }
+
+    fn take_inserted_node_count(&mut self) -> usize {
+        match *self {
+            Tokenizer::Html(ref mut tokenizer) => tokenizer.take_inserted_node_count(),
+            Tokenizer::AsyncHtml(ref mut tokenizer) => tokenizer.take_inserted_node_count(),
+            Tokenizer::Xml(ref mut tokenizer) => tokenizer.take_inserted_node_count(),
+        }
+    }
+
+    fn take_strict_parse_error(&mut self) -> Option<StrictParseError> {
+        match *self {
+            Tokenizer::Html(ref mut tokenizer) => tokenizer.take_strict_parse_error(),
+            Tokenizer::AsyncHtml(ref mut tokenizer) => tokenizer.take_strict_parse_error(),
+            Tokenizer::Xml(ref mut tokenizer) => tokenizer.take_strict_parse_error(),
+        }
+    }
+
+    fn take_declared_encoding(&mut self) -> Option<&'static Encoding> {
+        match *self {
+            Tokenizer::Html(ref mut tokenizer) => tokenizer.take_declared_encoding(),
+            Tokenizer::AsyncHtml(ref mut tokenizer) => tokenizer.take_declared_encoding(),
+            // XML documents declare their encoding in their XML declaration,
+            // not in `<meta>` elements.
+            Tokenizer::Xml(_) => None,
+        }
+    }
+
     fn profiler_category(&self) -> ProfilerCategory {
         match *self {
-            Tokenizer::Html(_) => ProfilerCategory::ScriptParseHTML,
-            Tokenizer::AsyncHtml(_) => ProfilerCategory::ScriptParseHTML,
+            Tokenizer::Html(ref tokenizer) => html_profiler_category(tokenizer.parsing_algorithm()),
+            Tokenizer::AsyncHtml(ref tokenizer) => {
+                html_profiler_category(tokenizer.parsing_algorithm())
+            },
             Tokenizer::Xml(_) => ProfilerCategory::ScriptParseXML,
         }
     }
 }
 
+fn html_profiler_category(parsing_algorithm: ParsingAlgorithm) -> ProfilerCategory {
+    match parsing_algorithm {
+        ParsingAlgorithm::Normal => ProfilerCategory::ScriptParseHTML,
+        ParsingAlgorithm::Fragment => ProfilerCategory::ScriptParseHTMLFragment,
+    }
+}
+
 /// The context required for asynchronously fetching a document
 /// and parsing it progressively.
 pub struct ParserContext {
     /// The parser that initiated the request.
     parser: Option<Trusted<ServoParser>>,
-    /// Is this a synthesized document
-    is_synthesized_document: bool,
+    /// Is this a successful `text/html` response
+    is_successful_html_response: bool,
+    /// Has any part of the response body been passed to the parser
+    has_processed_chunk: bool,
     /// The pipeline associated with this document.
     id: PipelineId,
     /// The URL for this document.
@@ -758,64 +3044,346 @@ pub struct ParserContext {
     resource_timing: ResourceFetchTiming,
     /// pushed entry index
     pushed_entry_index: Option<usize>,
+    /// Set when the load has been superseded, possibly from another thread,
+    /// to drop the rest of the response without waiting for the parser to be
+    /// aborted.
+    cancelled: Arc<AtomicBool>,
+    /// The origin of the document that navigated to this one, for top-level
+    /// navigations initiated by script.
+    initiator_origin: Option<ImmutableOrigin>,
+    /// The HTTP status code of the response, once it is known.
+    status_code: Option<u16>,
+    /// Whether the response forbids sniffing its content type.
+    /// <https://fetch.spec.whatwg.org/#x-content-type-options-header>
+    nosniff: bool,
+    /// The content type of the icon this document is, and its bytes received
+    /// so far, when it is shown by the icon viewer.
+    icon: Option<(Mime, Vec<u8>)>,
+    /// The content type of the font this document is, and the bytes received
+    /// so far, until there are enough of them to tell whether it is one.
+    font: Option<(Mime, Vec<u8>)>,
+    /// What is shown for a response whose content type can't be rendered.
+    unknown_content_type: UnknownContentTypeHandling,
+    /// The source viewer, when it numbers lines.
+    source: Option<SourceViewer>,
+    /// The resource threads the resources preloaded by early hints are
+    /// fetched on, if they are acted on.
+    resource_threads: Option<ResourceThreads>,
+    /// The URLs of the resources preloaded by early hints, which the
+    /// document doesn't prefetch again.
+    early_hint_urls: HashSet<ServoUrl>,
 }
 
 impl ParserContext {
     pub fn new(id: PipelineId, url: ServoUrl) -> ParserContext {
         ParserContext {
             parser: None,
-            is_synthesized_document: false,
+            is_successful_html_response: false,
+            has_processed_chunk: false,
             id: id,
             url: url,
             resource_timing: ResourceFetchTiming::new(ResourceTimingType::Navigation),
             pushed_entry_index: None,
+            cancelled: Default::default(),
+            initiator_origin: None,
+            status_code: None,
+            nosniff: false,
+            icon: None,
+            font: None,
+            unknown_content_type: UnknownContentTypeHandling::from_pref_value(&pref!(
+                dom.servoparser.unknown_content_type
+            )),
+            resource_threads: None,
+            early_hint_urls: HashSet::new(),
+            source: None,
         }
     }
-}
-
-impl FetchResponseListener for ParserContext {
-    fn process_request_body(&mut self) {}
 
-    fn process_request_eof(&mut self) {}
+    /// Shows the source of the response in a `<pre>` with the given class,
+    /// as it is received, with numbered lines if
+    /// `dom.servoparser.source_line_numbers.enabled` is set.
+    fn push_source_viewer(
+        &mut self,
+        parser: &ServoParser,
+        class: Option<&'static str>,
+        encoding: Option<&'static Encoding>,
+    ) {
+        if pref!(dom.servoparser.source_line_numbers.enabled) {
+            let encoding = encoding.unwrap_or_else(|| parser.document.encoding());
+            self.source = Some(SourceViewer::new(encoding));
+            parser.push_synthesized_document(SourceViewer::header(class));
+            return;
+        }
+        if let Some(encoding) = encoding {
+            parser.set_network_decoder_encoding(encoding, EncodingSource::Format);
+        }
+        // The `<pre>` is pushed as text that doesn't go through BOM sniffing,
+        // which must still see the first bytes of the response.
+        let pre = match class {
+            Some(class) => format!("<pre class=\"{}\">\n", class),
+            None => "<pre>\n".to_owned(),
+        };
+        parser.push_tendril_input_chunk(pre.into());
+        parser.parse_sync();
+        parser.tokenizer.borrow_mut().set_plaintext_state();
+    }
 
-    fn process_response(&mut self, meta_result: Result<FetchMetadata, NetworkError>) {
-        let (metadata, error) = match meta_result {
-            Ok(meta) => (
-                Some(match meta {
-                    FetchMetadata::Unfiltered(m) => m,
-                    FetchMetadata::Filtered { unsafe_, .. } => unsafe_,
-                }),
-                None,
-            ),
-            Err(error) => (
-                // Check variant without moving
-                match &error {
-                    NetworkError::SslValidation(..) |
-                    NetworkError::Internal(..) |
-                    NetworkError::Crash(..) => {
-                        let mut meta = Metadata::default(self.url.clone());
-                        let mime: Option<Mime> = "text/html".parse().ok();
-                        meta.set_content_type(mime.as_ref());
-                        Some(meta)
-                    },
-                    _ => None,
-                },
-                Some(error),
-            ),
+    /// Shows the image this document is in the image viewer.
+    fn push_image_viewer(&self, parser: &ServoParser, content_type: &Mime) {
+        let page = resources::read_string(Resource::ImageViewerHTML);
+        let page = substitute_html(&page, "${src}", self.url.as_str());
+        parser.push_synthesized_document(page);
+
+        // Use the image element provided by the viewer markup, if any,
+        // and append one to the body otherwise. The image source is only
+        // set here if the markup didn't already set it through `${src}`.
+        let doc = &parser.document;
+        let img = doc
+            .upcast::<Node>()
+            .traverse_preorder(ShadowIncluding::No)
+            .find_map(DomRoot::downcast::<HTMLImageElement>);
+        let img = match img {
+            Some(img) => img,
+            None => {
+                let doc_body = DomRoot::upcast::<Node>(doc.GetBody().unwrap());
+                let img = HTMLImageElement::new(local_name!("img"), None, doc, None);
+                doc_body
+                    .AppendChild(img.upcast())
+                    .expect("Appending failed");
+                img
+            },
         };
-        let content_type: Option<Mime> = metadata
-            .clone()
-            .and_then(|meta| meta.content_type)
-            .map(Serde::into_inner)
-            .map(Into::into);
+        // The image can't be loaded before the next task, so its title
+        // is always updated once it is.
+        if pref!(dom.servoparser.image_viewer_details.enabled) {
+            img.set_image_viewer_mime_type(DOMString::from(content_type.essence_str()));
+        }
+        if !img.upcast::<Element>().has_attribute(&local_name!("src")) {
+            img.SetSrc(USVString(self.url.to_string()));
+        }
+    }
 
-        // https://www.w3.org/TR/CSP/#initialize-document-csp
-        // TODO: Implement step 1 (local scheme special case)
-        let csp_list = metadata.as_ref().and_then(|m| {
-            let h = m.headers.as_ref()?;
-            let mut csp = h.get_all("content-security-policy").iter();
-            // This silently ignores the CSP if it contains invalid Unicode.
-            // We should probably report an error somewhere.
+    /// Shows what `unknown_content_type` asks for a response whose content
+    /// type, `mime_type/subtype`, can't be rendered.
+    fn push_unknown_content_type(&mut self, parser: &ServoParser, mime_type: &str, subtype: &str) {
+        match self.unknown_content_type {
+            UnknownContentTypeHandling::Warning => {
+                // Show warning page for unknown mime types.
+                parser.push_synthesized_document(unknown_content_type_page(mime_type, subtype));
+            },
+            UnknownContentTypeHandling::PlainText => {
+                // Show the source of the response the same way as that of
+                // `text/plain` responses.
+                self.push_source_viewer(parser, None, None);
+            },
+            UnknownContentTypeHandling::Download => {
+                // The embedder fetches the resource again itself, so the
+                // body of this response is dropped like that of any other
+                // synthesized document.
+                let content_type = format!("{}/{}", mime_type, subtype);
+                parser
+                    .document
+                    .window()
+                    .send_to_embedder(EmbedderMsg::Download(
+                        self.url.clone(),
+                        content_type.clone(),
+                    ));
+                let page = format!(
+                    "<html><body><p>{} ({}) is offered for download.</p></body></html>",
+                    escape_html_text(self.url.as_str()),
+                    escape_html_text(&content_type)
+                );
+                parser.push_synthesized_document(page);
+            },
+            UnknownContentTypeHandling::CustomHtml => {
+                let page = resources::read_string(Resource::UnknownContentTypeHTML);
+                let page = substitute_html(&page, "${type}", &format!("{}/{}", mime_type, subtype));
+                let page = substitute_html(&page, "${url}", self.url.as_str());
+                parser.push_synthesized_document(page);
+            },
+        }
+    }
+
+    /// Shows the font this document is on the specimen page, if `bytes`, the
+    /// start of the response, are those of a font file. A response that isn't
+    /// a font is shown like any other of an unknown content type.
+    fn push_font_viewer(&mut self, parser: &ServoParser, content_type: &Mime, bytes: Vec<u8>) {
+        if is_font_file(&bytes) {
+            parser.push_synthesized_document(font_specimen_page(&self.url));
+            return;
+        }
+        self.push_unknown_content_type(
+            parser,
+            content_type.type_().as_str(),
+            content_type.subtype().as_str(),
+        );
+        if let Some(ref mut source) = self.source {
+            parser.push_synthesized_input(source.push(&bytes));
+        } else if !parser.synthesized_document.get() {
+            parser.parse_bytes_chunk(bytes);
+        }
+    }
+
+    /// Sets the origin of the document that started this top-level
+    /// navigation, against which the security of the response is checked.
+    pub fn set_initiator_origin(&mut self, origin: ImmutableOrigin) {
+        self.initiator_origin = Some(origin);
+    }
+
+    /// Sets the resource threads to fetch the resources preloaded by early
+    /// hints on. Early hints are ignored until this is set.
+    pub fn set_resource_threads(&mut self, resource_threads: ResourceThreads) {
+        self.resource_threads = Some(resource_threads);
+    }
+
+    /// Fetches the resources preloaded by the `Link` headers of a `103 Early
+    /// Hints` response, which come before the final response, and so before
+    /// there is a document to prefetch them for. The prefetcher of the
+    /// document doesn't fetch them again. Early hints coming after the final
+    /// response are ignored.
+    /// <https://html.spec.whatwg.org/multipage/#early-hints>
+    pub fn process_early_hints(&mut self, headers: &HeaderMap) {
+        if self.is_cancelled() || self.parser.is_some() || self.status_code.is_some() {
+            return;
+        }
+        let resource_threads = match self.resource_threads {
+            Some(ref resource_threads) => resource_threads,
+            None => return,
+        };
+        let links: Vec<_> = headers
+            .get_all("link")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(prefetch::parse_link_header)
+            .collect();
+        for request in prefetch::early_hint_requests(&links, &self.url, self.id) {
+            if !self.early_hint_urls.insert(request.url.clone()) {
+                continue;
+            }
+            let _ = resource_threads.send(CoreResourceMsg::Fetch(
+                request,
+                FetchChannels::Prefetch(None, None),
+            ));
+        }
+    }
+
+    /// Sets what is shown for the response if its content type can't be
+    /// rendered, overriding the `dom.servoparser.unknown_content_type` pref.
+    pub fn set_unknown_content_type_handling(&mut self, handling: UnknownContentTypeHandling) {
+        self.unknown_content_type = handling;
+    }
+
+    /// The HTTP status code of the response, if it has been received and has
+    /// one.
+    pub fn status_code(&self) -> Option<u16> {
+        self.status_code
+    }
+
+    /// Returns the flag which cancels this load once set.
+    pub fn cancellation_flag(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+impl FetchResponseListener for ParserContext {
+    fn process_request_body(&mut self) {}
+
+    fn process_request_eof(&mut self) {}
+
+    fn process_response(&mut self, meta_result: Result<FetchMetadata, NetworkError>) {
+        if self.is_cancelled() {
+            return;
+        }
+        let (mut metadata, error) = match meta_result {
+            Ok(meta) => (
+                Some(match meta {
+                    FetchMetadata::Unfiltered(m) => m,
+                    FetchMetadata::Filtered { unsafe_, .. } => unsafe_,
+                }),
+                None,
+            ),
+            Err(error) => (
+                // Check variant without moving
+                match &error {
+                    NetworkError::SslValidation(..) |
+                    NetworkError::Internal(..) |
+                    NetworkError::Crash(..) => {
+                        let mut meta = Metadata::default(self.url.clone());
+                        let mime: Option<Mime> = "text/html".parse().ok();
+                        meta.set_content_type(mime.as_ref());
+                        Some(meta)
+                    },
+                    _ => None,
+                },
+                Some(error),
+            ),
+        };
+        let x_content_type_options = metadata
+            .as_ref()
+            .and_then(|meta| meta.headers.as_ref())
+            .map(|headers| {
+                let values = headers.get_all("x-content-type-options").iter();
+                let values: Vec<_> = values.filter_map(|value| value.to_str().ok()).collect();
+                values.join(",")
+            });
+        let nosniff = x_content_type_options.as_deref().map_or(false, is_nosniff);
+        self.nosniff = nosniff;
+        // The content type of a response that forbids sniffing isn't guessed.
+        if let Some(meta) = metadata.as_mut().filter(|_| !nosniff) {
+            let content_type: Option<Mime> = meta
+                .content_type
+                .clone()
+                .map(Serde::into_inner)
+                .map(Into::into);
+            if let Some(mime) = file_extension_content_type(&meta.final_url, content_type.as_ref())
+            {
+                // Set it before the document is created, so that it's parsed
+                // by the tokenizer for its content type.
+                meta.set_content_type(Some(&mime));
+            }
+        }
+        let content_type: Option<Mime> = metadata
+            .clone()
+            .and_then(|meta| meta.content_type)
+            .map(Serde::into_inner)
+            .map(Into::into);
+        let status_code = metadata
+            .as_ref()
+            .and_then(|meta| meta.status.as_ref())
+            .map(|&(code, _)| code);
+        let final_url = metadata.as_ref().map(|meta| meta.final_url.clone());
+        self.status_code = status_code;
+        let content_location = metadata.as_ref().and_then(|meta| {
+            let value = meta.headers.as_ref()?.get("content-location")?;
+            content_location_url(value.to_str().ok()?, &meta.final_url)
+        });
+        let retry_after = metadata
+            .as_ref()
+            .and_then(|meta| meta.headers.as_ref())
+            .and_then(|headers| headers.get("retry-after"))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| parse_retry_after(value, SystemTime::now()));
+        let clear_site_data = metadata
+            .as_ref()
+            .and_then(|meta| meta.headers.as_ref())
+            .map(|headers| {
+                let values = headers.get_all("clear-site-data").iter();
+                let values: Vec<_> = values.filter_map(|value| value.to_str().ok()).collect();
+                parse_clear_site_data(&values.join(","))
+            })
+            .unwrap_or_default();
+
+        // https://www.w3.org/TR/CSP/#initialize-document-csp
+        // Step 1 is implemented once the document is known, below.
+        let csp_list = metadata.as_ref().and_then(|m| {
+            let h = m.headers.as_ref()?;
+            let mut csp = h.get_all("content-security-policy").iter();
+            // This silently ignores the CSP if it contains invalid Unicode.
+            // We should probably report an error somewhere.
             let c = csp.next().and_then(|c| c.to_str().ok())?;
             let mut csp_list = CspList::parse(
                 c,
@@ -833,9 +3401,29 @@ impl FetchResponseListener for ParserContext {
             Some(csp_list)
         });
 
+        let link_headers: Vec<prefetch::LinkHeaderValue> = metadata
+            .as_ref()
+            .and_then(|m| m.headers.as_ref())
+            .map(|h| {
+                h.get_all("link")
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .flat_map(prefetch::parse_link_header)
+                    .collect()
+            })
+            .unwrap_or_default();
+
         let parser = match ScriptThread::page_headers_available(&self.id, metadata) {
             Some(parser) => parser,
-            None => return,
+            None => {
+                // No document is made from this response, because the
+                // navigation was aborted, or its pipeline was closed in the
+                // meantime. Drop the rest of the response, and don't submit a
+                // resource timing entry without a document to submit it to.
+                debug!("No document for the response to {}", self.url);
+                self.cancelled.store(true, Ordering::Relaxed);
+                return;
+            },
         };
         if parser.aborted.get() {
             return;
@@ -843,104 +3431,200 @@ impl FetchResponseListener for ParserContext {
 
         let _realm = enter_realm(&*parser.document);
 
+        // Only secure responses may clear the data of their site, which is
+        // done before the document can load anything that is cleared.
+        // https://w3c.github.io/webappsec-clear-site-data/#clear-response
+        if let (Some(final_url), None) = (&final_url, &error) {
+            if final_url.is_potentially_trustworthy() {
+                clear_site_data.clear(&parser.document.global(), final_url);
+            }
+        }
+
+        // Step 1 of https://www.w3.org/TR/CSP/#initialize-document-csp:
+        // documents with a local scheme, which have no headers of their own,
+        // inherit the policies of the document that created them instead.
+        let csp_list = if self.url.is_local_scheme() {
+            parser
+                .document
+                .browsing_context()
+                .and_then(|window_proxy| window_proxy.creator())
+                .and_then(|creator| creator.document())
+                .and_then(|creator| creator.global().get_csp_list())
+        } else {
+            csp_list
+        };
         parser.document.set_csp_list(csp_list);
+        // Set before anything is prefetched, so that the prefetch tokenizer
+        // resolves against it too.
+        if error.is_none() {
+            parser.document.set_content_location(content_location);
+        }
+        parser.skip_prefetch_urls(mem::take(&mut self.early_hint_urls));
+        parser.preload_link_headers(&link_headers);
         self.parser = Some(Trusted::new(&*parser));
         self.submit_resource_timing();
 
+        // https://httpwg.org/specs/rfc9110.html#status.503
+        // Say when the service is expected to be available again, if known.
+        if let (Some(503), Some(retry_after), None) = (status_code, retry_after, &error) {
+            let page = resources::read_string(Resource::ServiceUnavailableHTML);
+            let page = substitute_html(&page, "${url}", self.url.as_str());
+            let page = page.replace("${delay}", &retry_after.as_secs().to_string());
+            parser.push_synthesized_document(page);
+            return;
+        }
+
+        // Warn about a secure page navigating to an insecure one, rather than
+        // showing the insecure one as if nothing happened.
+        if let (Some(origin), Some(final_url), None) = (&self.initiator_origin, &final_url, &error)
+        {
+            if pref!(dom.servoparser.mixed_content_warning.enabled) &&
+                is_mixed_content_navigation(origin, final_url)
+            {
+                parser.push_synthesized_document(mixed_content_page(final_url.as_str()));
+                return;
+            }
+        }
+
         let content_type = match content_type {
             Some(ref content_type) => content_type,
             None => {
                 // No content-type header.
                 // Merge with #4212 when fixed.
+                if !is_parsed_as_html(None, self.nosniff) {
+                    parser.push_synthesized_document(no_content_type_page(self.url.as_str()));
+                }
                 return;
             },
         };
 
+        // The input must be decoded with the charset of the response, which
+        // for `data:` URLs is given by the URL itself, before any of it is
+        // decoded. The document was created with the same content type, but
+        // make sure the decoder doesn't depend on how it got its encoding.
+        // https://html.spec.whatwg.org/multipage/#encoding-sniffing-algorithm
+        if let Some(encoding) = content_type_encoding(content_type) {
+            parser.set_network_decoder_encoding(encoding, EncodingSource::TransportLayer);
+        }
+
         match (
             content_type.type_(),
             content_type.subtype(),
             content_type.suffix(),
         ) {
-            (mime::IMAGE, _, _) => {
-                self.is_synthesized_document = true;
-                let page = "<html><body></body></html>".into();
-                parser.push_string_input_chunk(page);
-                parser.parse_sync();
-
-                let doc = &parser.document;
-                let doc_body = DomRoot::upcast::<Node>(doc.GetBody().unwrap());
-                let img = HTMLImageElement::new(local_name!("img"), None, doc, None);
-                img.SetSrc(USVString(self.url.to_string()));
-                doc_body
-                    .AppendChild(&DomRoot::upcast::<Node>(img))
-                    .expect("Appending failed");
+            (mime::IMAGE, _, _) if !is_supported_image_mime_type(content_type) => {
+                // An image that can't be decoded would only show as a broken
+                // image, so say why there is nothing to show instead.
+                let page = unsupported_image_page(content_type.essence_str());
+                parser.push_synthesized_document(page);
             },
+            (mime::IMAGE, subtype, _)
+                if is_icon_subtype(subtype.as_str()) &&
+                    pref!(dom.servoparser.icon_viewer.enabled) =>
+            {
+                // The page tells about the images in the icon, which are only
+                // known once all of it has been received.
+                self.icon = Some((content_type.clone(), vec![]));
+            },
+            (mime::IMAGE, _, _) => self.push_image_viewer(&parser, content_type),
             (mime::TEXT, mime::PLAIN, _) => {
                 // https://html.spec.whatwg.org/multipage/#read-text
-                let page = "<pre>\n".into();
-                parser.push_string_input_chunk(page);
-                parser.parse_sync();
-                parser.tokenizer.borrow_mut().set_plaintext_state();
+                self.push_source_viewer(&parser, None, None);
+            },
+            (mime::TEXT, subtype, _) if subtype == "vtt" => {
+                // Show the source of WebVTT caption files, which are always
+                // UTF-8, the same way as plain text. Cues aren't rendered.
+                // https://w3c.github.io/webvtt/#file-structure
+                self.push_source_viewer(&parser, Some("webvtt"), Some(encoding_rs::UTF_8));
             },
             (mime::TEXT, mime::HTML, _) => match error {
                 Some(NetworkError::SslValidation(reason, bytes)) => {
-                    self.is_synthesized_document = true;
                     let page = resources::read_string(Resource::BadCertHTML);
-                    let page = page.replace("${reason}", &reason);
+                    let page = substitute_html(&page, "${reason}", &reason);
                     let encoded_bytes = general_purpose::STANDARD_NO_PAD.encode(&bytes);
                     let page = page.replace("${bytes}", encoded_bytes.as_str());
                     let page =
                         page.replace("${secret}", &net_traits::PRIVILEGED_SECRET.to_string());
-                    parser.push_string_input_chunk(page);
-                    parser.parse_sync();
+                    parser.push_synthesized_document(page);
                 },
                 Some(NetworkError::Internal(reason)) => {
-                    self.is_synthesized_document = true;
                     let page = resources::read_string(Resource::NetErrorHTML);
-                    let page = page.replace("${reason}", &reason);
-                    parser.push_string_input_chunk(page);
-                    parser.parse_sync();
+                    let page = substitute_html(&page, "${reason}", &reason);
+                    parser.push_synthesized_document(page);
                 },
                 Some(NetworkError::Crash(details)) => {
-                    self.is_synthesized_document = true;
                     let page = resources::read_string(Resource::CrashHTML);
-                    let page = page.replace("${details}", &details);
-                    parser.push_string_input_chunk(page);
-                    parser.parse_sync();
+                    let page = substitute_html(&page, "${details}", &details);
+                    parser.push_synthesized_document(page);
                 },
                 Some(_) => {},
-                None => {},
+                None => {
+                    self.is_successful_html_response = status_code == Some(200);
+                    if content_type_encoding(content_type).is_none() {
+                        parser.start_encoding_sniffing();
+                    }
+                },
+            },
+            (mime::TEXT, mime::XML, _) | (mime::APPLICATION, mime::XML, _) => {
+                // The document is parsed by the XML tokenizer, see
+                // `ScriptThread::load`. Unless it brings style of its own,
+                // show its tree once it is parsed.
+                parser.use_xml_viewer.set(true);
             },
-            (mime::TEXT, mime::XML, _) |
-            (mime::APPLICATION, mime::XML, _) |
             (mime::APPLICATION, mime::JSON, _) => {},
+            (mime::APPLICATION, mime::PDF, _) if pref!(dom.servoparser.pdf_viewer.enabled) => {
+                // The embedder fetches the PDF again to show it in its viewer,
+                // as for downloads. Without this pref, PDFs are shown like any
+                // other unknown content type.
+                parser
+                    .document
+                    .window()
+                    .send_to_embedder(EmbedderMsg::OpenPdf(self.url.clone()));
+                parser.push_synthesized_document(pdf_viewer_page(&self.url));
+            },
             (mime::APPLICATION, subtype, Some(mime::XML)) if subtype == "xhtml" => {},
+            (font, _, _) if font == "font" && pref!(dom.servoparser.font_viewer.enabled) => {
+                // Whether there is a font to show is only known once the first
+                // bytes of the response are received.
+                self.font = Some((content_type.clone(), vec![]));
+            },
             (mime_type, subtype, _) => {
-                // Show warning page for unknown mime types.
-                let page = format!(
-                    "<html><body><p>Unknown content type ({}/{}).</p></body></html>",
-                    mime_type.as_str(),
-                    subtype.as_str()
-                );
-                self.is_synthesized_document = true;
-                parser.push_string_input_chunk(page);
-                parser.parse_sync();
+                self.push_unknown_content_type(&parser, mime_type.as_str(), subtype.as_str())
             },
         }
     }
 
     fn process_response_chunk(&mut self, payload: Vec<u8>) {
-        if self.is_synthesized_document {
+        if self.is_cancelled() {
             return;
         }
         let parser = match self.parser.as_ref() {
             Some(parser) => parser.root(),
             None => return,
         };
-        if parser.aborted.get() {
+        if parser.aborted.get() || parser.synthesized_document.get() {
             return;
         }
         let _realm = enter_realm(&*parser);
+        if let Some((_, ref mut bytes)) = self.icon {
+            bytes.extend_from_slice(&payload);
+            return;
+        }
+        if let Some((_, ref mut bytes)) = self.font {
+            bytes.extend_from_slice(&payload);
+            if bytes.len() >= FONT_SIGNATURE_LEN {
+                let (content_type, bytes) = self.font.take().unwrap();
+                self.push_font_viewer(&parser, &content_type, bytes);
+            }
+            return;
+        }
+        if let Some(ref mut source) = self.source {
+            parser.push_synthesized_input(source.push(&payload));
+            return;
+        }
+        if !payload.is_empty() {
+            self.has_processed_chunk = true;
+        }
         parser.parse_bytes_chunk(payload);
     }
 
@@ -948,6 +3632,9 @@ impl FetchResponseListener for ParserContext {
     // submit_resource_timing in this function
     // Resource listeners are called via net_traits::Action::process, which handles submission for them
     fn process_response_eof(&mut self, status: Result<ResourceFetchTiming, NetworkError>) {
+        if self.is_cancelled() {
+            return;
+        }
         let parser = match self.parser.as_ref() {
             Some(parser) => parser.root(),
             None => return,
@@ -958,9 +3645,37 @@ impl FetchResponseListener for ParserContext {
 
         let _realm = enter_realm(&*parser);
 
+        // Icons that can't be read, possibly as their response was cut short,
+        // are shown like any other image.
+        if let Some((content_type, bytes)) = self.icon.take() {
+            match icon_entries(&bytes) {
+                Some(entries) => {
+                    let page = icon_viewer_page(&self.url, &entries);
+                    parser.push_synthesized_document(page);
+                },
+                None => self.push_image_viewer(&parser, &content_type),
+            }
+        }
+        // So are fonts shorter than their signature.
+        if let Some((content_type, bytes)) = self.font.take() {
+            self.push_font_viewer(&parser, &content_type, bytes);
+        }
+        if let Some(mut source) = self.source.take() {
+            parser.push_synthesized_input(source.finish());
+        }
+
         match status {
             // are we throwing this away or can we use it?
-            Ok(_) => (),
+            Ok(_) => {
+                // Show a placeholder page for successful but empty documents.
+                if self.is_successful_html_response &&
+                    !self.has_processed_chunk &&
+                    pref!(dom.servoparser.empty_document_placeholder.enabled)
+                {
+                    let page = resources::read_string(Resource::EmptyDocumentHTML);
+                    parser.push_synthesized_document(page);
+                }
+            },
             // TODO(Savago): we should send a notification to callers #5463.
             Err(err) => debug!("Failed to load page URL {}, error: {:?}", self.url, err),
         }
@@ -969,9 +3684,13 @@ impl FetchResponseListener for ParserContext {
             .document
             .set_redirect_count(self.resource_timing.redirect_count);
 
-        parser.last_chunk_received.set(true);
-        if !parser.suspended.get() {
-            parser.parse_sync();
+        // A document synthesized with `load_synthesized_document` already had
+        // its last chunk.
+        if !parser.last_chunk_received.get() {
+            parser.last_chunk_received.set(true);
+            if !parser.suspended.get() {
+                parser.parse_sync();
+            }
         }
 
         //TODO only update if this is the current document resource
@@ -1071,19 +3790,314 @@ pub struct Sink {
     current_line: u64,
     script: MutNullableDom<HTMLScriptElement>,
     parsing_algorithm: ParsingAlgorithm,
+    /// A quirks mode forced upon the document, which the parser must not change.
+    #[ignore_malloc_size_of = "Defined in style"]
+    #[no_trace]
+    quirks_mode_override: Option<ServoQuirksMode>,
+    #[ignore_malloc_size_of = "Function pointer"]
+    #[no_trace]
+    comment_transformer: Option<CommentTransformer>,
+    #[ignore_malloc_size_of = "Function pointer"]
+    #[no_trace]
+    raw_attributes_observer: Option<RawAttributesObserver>,
+    #[ignore_malloc_size_of = "Function pointer"]
+    #[no_trace]
+    element_blocker: Option<ElementBlocker>,
+    /// The elements blocked so far.
+    blocked_elements: Vec<Dom<Node>>,
+    /// The first parse error, when parsing a document in strict mode.
+    strict_parse_error: Option<StrictParseError>,
+    /// The parse errors found so far, if they are being collected.
+    parse_errors: Option<Vec<ParseErrorRecord>>,
+    #[ignore_malloc_size_of = "Function pointer"]
+    #[no_trace]
+    doctype_observer: Option<DoctypeObserver>,
+    element_counters: ParserElementCounters,
+    /// The number of nodes inserted since the tokenizer was last asked.
+    inserted_nodes: usize,
+    /// Whether to sanitize the values of `<input>` elements.
+    sanitize_input_values: bool,
+    /// See `max_attributes_per_element`.
+    max_attributes: usize,
+    /// The declarative shadow roots whose `<template>` is still open.
+    declarative_shadow_roots: Vec<DeclarativeShadowRoot>,
+    /// Whether the scripts parsed may run. If not, they are marked as already
+    /// started as soon as they are created, and never prepared.
+    scripting_enabled: bool,
+    /// Whether scripts never block the parser, see
+    /// `ServoParser::set_script_blocking_disabled`.
+    script_blocking_disabled: bool,
+    #[ignore_malloc_size_of = "Function pointer"]
+    #[no_trace]
+    head_complete_observer: Option<HeadCompleteObserver>,
+    /// Whether the head has been popped already.
+    head_complete: bool,
+    #[ignore_malloc_size_of = "Function pointer"]
+    #[no_trace]
+    body_child_complete_observer: Option<BodyChildCompleteObserver>,
+    /// The text inserted so far, if it is being extracted.
+    text_extractor: Option<TextExtractor>,
+    line_tracker: LineTracker,
+    deprecation_warnings: Vec<DeprecationWarning>,
+    /// The encoding declared by the first `<meta>` declaring one, until the
+    /// parser takes it, see `ServoParser::handle_encoding_declaration`.
+    #[no_trace]
+    declared_encoding: Option<&'static Encoding>,
+    /// Whether a `<meta>` declared an encoding already. Only the first
+    /// declaration counts.
+    encoding_declared: bool,
+}
+
+/// Accumulates the text the parser inserts, as plain text for accessibility
+/// and indexing. Runs of whitespace are collapsed to a single space, and
+/// whitespace at the start and end is dropped. The text of `<script>`,
+/// `<style>` and `<template>` elements isn't part of it.
+#[derive(Default, JSTraceable, MallocSizeOf)]
+pub struct TextExtractor {
+    text: String,
+    /// Whether whitespace was found since the last character of `text`.
+    pending_space: bool,
+}
+
+impl TextExtractor {
+    pub fn push(&mut self, text: &str) {
+        for c in text.chars() {
+            if c.is_ascii_whitespace() {
+                self.pending_space = !self.text.is_empty();
+                continue;
+            }
+            if self.pending_space {
+                self.text.push(' ');
+                self.pending_space = false;
+            }
+            self.text.push(c);
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn extract_inserted_text(&mut self, parent: &Node, child: &NodeOrText<Dom<Node>>) {
+        if let NodeOrText::AppendText(ref text) = *child {
+            if !is_excluded_from_text(parent) {
+                self.push(text);
+            }
+        }
+    }
+}
+
+/// Whether the text inserted into `parent` is left out of the extracted text,
+/// which is the case in `<script>`, `<style>` and `<template>` elements, and so
+/// in the contents of templates, which aren't in the tree of their template.
+fn is_excluded_from_text(parent: &Node) -> bool {
+    parent
+        .inclusive_ancestors(ShadowIncluding::Yes)
+        .any(|node| match node.downcast::<Element>() {
+            Some(elem) => matches!(
+                *elem.local_name(),
+                local_name!("script") | local_name!("style") | local_name!("template")
+            ),
+            None => node.is::<DocumentFragment>() && !node.is::<ShadowRoot>(),
+        })
+}
+
+/// Maps the lines the tokenizer counts, over both the network input and the
+/// input inserted by `document.write()`, to lines of the network input, which
+/// are the lines of the source of the document.
+///
+/// The input a script writes is inserted right after the `</script>` end tag
+/// that made it run, so everything parsed from it, up to and including the
+/// rest of the line the end tag is on, gets the line of that end tag. The
+/// lines after it are counted as if nothing was written. The inputs written
+/// by the scripts that run before the parser gets back to the network input,
+/// be they nested or parser-blocking, all go to that same line.
+#[derive(Clone, Copy, Debug, JSTraceable, MallocSizeOf, PartialEq)]
+pub struct LineTracker {
+    /// The line the tokenizer is currently at.
+    tokenizer_line: u64,
+    /// The number of newlines in the script input parsed before the current
+    /// script lines.
+    script_newlines: u64,
+    /// The tokenizer line at which script input was last inserted, and the
+    /// number of newlines inserted there.
+    script_lines: Option<(u64, u64)>,
+}
+
+impl Default for LineTracker {
+    fn default() -> Self {
+        LineTracker {
+            tokenizer_line: 1,
+            script_newlines: 0,
+            script_lines: None,
+        }
+    }
+}
+
+impl LineTracker {
+    /// Records the line the tokenizer is at, and returns the line of the
+    /// network input it maps to.
+    pub fn set_tokenizer_line(&mut self, tokenizer_line: u64) -> u64 {
+        self.tokenizer_line = tokenizer_line;
+        self.line()
+    }
+
+    /// The line of the network input the tokenizer is at.
+    pub fn line(&self) -> u64 {
+        let line = self.tokenizer_line.saturating_sub(self.script_newlines);
+        match self.script_lines {
+            Some((start, newlines)) if self.tokenizer_line >= start => {
+                let start = start.saturating_sub(self.script_newlines);
+                start.max(line.saturating_sub(newlines))
+            },
+            _ => line,
+        }
+    }
+
+    /// Records `input` being inserted by a script at the line the tokenizer
+    /// is at.
+    pub fn insert_script_input(&mut self, input: &str) {
+        self.insert_script_newlines(count_newlines(input));
+    }
+
+    /// Records script input with `newlines` newlines being inserted at the
+    /// line the tokenizer is at.
+    pub fn insert_script_newlines(&mut self, newlines: u64) {
+        match self.script_lines {
+            // The tokenizer is still in the script input inserted before.
+            Some((start, inserted)) if self.tokenizer_line <= start.saturating_add(inserted) => {
+                self.script_lines = Some((start, inserted.saturating_add(newlines)));
+            },
+            _ => {
+                if let Some((_, inserted)) = self.script_lines {
+                    self.script_newlines = self.script_newlines.saturating_add(inserted);
+                }
+                self.script_lines = Some((self.tokenizer_line, newlines));
+            },
+        }
+    }
+}
+
+/// The number of lines the tokenizer counts in `input`, where CRLF pairs and
+/// lone CRs each end a line, like LFs.
+fn count_newlines(input: &str) -> u64 {
+    let bytes = input.as_bytes();
+    let newlines = bytes
+        .iter()
+        .enumerate()
+        .filter(|&(i, &byte)| byte == b'\n' || (byte == b'\r' && bytes.get(i + 1) != Some(&b'\n')));
+    newlines.count() as u64
+}
+
+/// A `<template shadowrootmode>` being parsed, whose children go into the
+/// shadow root attached to its parent rather than into its contents.
+#[derive(JSTraceable, MallocSizeOf)]
+#[crown::unrooted_must_root_lint::must_root]
+struct DeclarativeShadowRoot {
+    template: Dom<Node>,
+    shadow_root: Dom<ShadowRoot>,
 }
 
 impl Sink {
+    /// Sets this sink up like `sink`, for parsing its document again from the
+    /// start. Only the configuration is carried over, not the state of the
+    /// parse, but text is extracted and parse errors are collected again if
+    /// they were.
+    fn configure_like(&mut self, sink: &Sink) {
+        self.quirks_mode_override = sink.quirks_mode_override;
+        self.comment_transformer = sink.comment_transformer;
+        self.raw_attributes_observer = sink.raw_attributes_observer;
+        self.element_blocker = sink.element_blocker;
+        self.doctype_observer = sink.doctype_observer;
+        self.sanitize_input_values = sink.sanitize_input_values;
+        self.script_blocking_disabled = sink.script_blocking_disabled;
+        self.head_complete_observer = sink.head_complete_observer;
+        self.body_child_complete_observer = sink.body_child_complete_observer;
+        if sink.text_extractor.is_some() {
+            self.text_extractor = Some(Default::default());
+        }
+        if sink.parse_errors.is_some() {
+            self.parse_errors = Some(vec![]);
+        }
+    }
+
     fn same_tree(&self, x: &Dom<Node>, y: &Dom<Node>) -> bool {
-        let x = x.downcast::<Element>().expect("Element node expected");
-        let y = y.downcast::<Element>().expect("Element node expected");
+        in_same_tree(x, y)
+    }
+
+    fn declarative_shadow_root(&self, template: &Dom<Node>) -> Option<DomRoot<ShadowRoot>> {
+        self.declarative_shadow_roots
+            .iter()
+            .find(|declarative| declarative.template == *template)
+            .map(|declarative| DomRoot::from_ref(&*declarative.shadow_root))
+    }
 
-        x.is_in_same_home_subtree(y)
+    /// Attaches a shadow root to the parent of `template`, if it is a
+    /// declarative shadow root, i.e. its `shadowrootmode` is `open` or
+    /// `closed`. Otherwise, or if the parent can't host a shadow root, the
+    /// template stays a plain one.
+    ///
+    /// Declarative shadow roots are only parsed in HTML documents, not in
+    /// fragments nor XML documents, and not by the async HTML tokenizer.
+    /// <https://html.spec.whatwg.org/multipage/#parsing-main-inhead>
+    fn attach_declarative_shadow_root(
+        &mut self,
+        template: &Dom<Node>,
+    ) -> Option<DomRoot<ShadowRoot>> {
+        if self.parsing_algorithm != ParsingAlgorithm::Normal ||
+            !self.document.is_html_document() ||
+            !pref!(dom.shadowdom.enabled)
+        {
+            return None;
+        }
+        let mode = template
+            .downcast::<Element>()?
+            .get_attribute(&ns!(), &LocalName::from("shadowrootmode"))?;
+        if !mode.value().eq_ignore_ascii_case("open") &&
+            !mode.value().eq_ignore_ascii_case("closed")
+        {
+            return None;
+        }
+        let mode = if mode.value().eq_ignore_ascii_case("open") {
+            ShadowRootMode::Open
+        } else {
+            ShadowRootMode::Closed
+        };
+        let template_element = template.downcast::<Element>()?;
+        let clonable = template_element.has_attribute(&LocalName::from("shadowrootclonable"));
+        let delegates_focus =
+            template_element.has_attribute(&LocalName::from("shadowrootdelegatesfocus"));
+        let host = template.GetParentElement()?;
+        let shadow_root = match host.attach_shadow(IsUserAgentWidget::No) {
+            Ok(shadow_root) => shadow_root,
+            Err(_) => {
+                debug!("Parsing <template shadowrootmode> as a plain template");
+                return None;
+            },
+        };
+        shadow_root.set_declarative(mode, clonable, delegates_focus);
+        self.declarative_shadow_roots.push(DeclarativeShadowRoot {
+            template: template.clone(),
+            shadow_root: Dom::from_ref(&*shadow_root),
+        });
+        Some(shadow_root)
     }
 
     fn has_parent_node(&self, node: &Dom<Node>) -> bool {
         node.GetParentNode().is_some()
     }
+
+    /// Drops the attributes beyond the maximum number of attributes of an
+    /// element with `existing` attributes, with a parse error.
+    fn cap_attributes(&mut self, attrs: &mut Vec<Attribute>, existing: usize) {
+        let dropped = cap_attributes(attrs, existing, self.max_attributes);
+        if dropped > 0 {
+            self.parse_error(Cow::Owned(format!(
+                "Dropped {} attributes beyond the maximum of {} of an element",
+                dropped, self.max_attributes
+            )));
+        }
+    }
 }
 
 #[allow(crown::unrooted_must_root)] // FIXME: really?
@@ -1100,6 +4114,14 @@ impl TreeSink for Sink {
     }
 
     fn get_template_contents(&mut self, target: &Dom<Node>) -> Dom<Node> {
+        // The children of a declarative shadow root's template go into the
+        // shadow root, which is attached once the first one is inserted.
+        let shadow_root = self
+            .declarative_shadow_root(target)
+            .or_else(|| self.attach_declarative_shadow_root(target));
+        if let Some(shadow_root) = shadow_root {
+            return Dom::from_ref(shadow_root.upcast());
+        }
         let template = target
             .downcast::<HTMLTemplateElement>()
             .expect("tried to get template contents of non-HTMLTemplateElement in HTML parsing");
@@ -1123,9 +4145,34 @@ impl TreeSink for Sink {
     fn create_element(
         &mut self,
         name: QualName,
-        attrs: Vec<Attribute>,
+        mut attrs: Vec<Attribute>,
         _flags: ElementFlags,
     ) -> Dom<Node> {
+        self.cap_attributes(&mut attrs, 0);
+        if name.ns == ns!(html) && is_obsolete_element(&name.local) {
+            let feature = DeprecatedFeature::ObsoleteElement(name.local.to_string());
+            warn_deprecated(&mut self.deprecation_warnings, self.current_line, feature);
+        }
+        if is_blocked_element(self.element_blocker, &name, &attrs) {
+            let element = create_element_for_token(
+                name,
+                vec![],
+                &*self.document,
+                ElementCreator::ParserCreated(self.current_line),
+                self.parsing_algorithm,
+                &mut self.element_counters,
+                self.sanitize_input_values,
+            );
+            if let Some(script) = element.downcast::<HTMLScriptElement>() {
+                script.set_already_started(true);
+            }
+            let element = Dom::from_ref(element.upcast());
+            self.blocked_elements.push(element.clone());
+            return element;
+        }
+        let raw_attrs = self
+            .raw_attributes_observer
+            .map(|observer| (observer, attrs.clone()));
         let attrs = attrs
             .into_iter()
             .map(|attr| ElementAttribute::new(attr.name, DOMString::from(String::from(attr.value))))
@@ -1136,12 +4183,29 @@ impl TreeSink for Sink {
             &*self.document,
             ElementCreator::ParserCreated(self.current_line),
             self.parsing_algorithm,
+            &mut self.element_counters,
+            self.sanitize_input_values,
         );
+        if let Some((observer, raw_attrs)) = raw_attrs {
+            observer(&element, &raw_attrs);
+        }
+        if !self.scripting_enabled {
+            if let Some(script) = element.downcast::<HTMLScriptElement>() {
+                script.set_already_started(true);
+            }
+        }
         Dom::from_ref(element.upcast())
     }
 
     fn create_comment(&mut self, text: StrTendril) -> Dom<Node> {
-        let comment = Comment::new(DOMString::from(String::from(text)), &*self.document, None);
+        let text = match self.comment_transformer {
+            None => String::from(text),
+            Some(transform) => match transform(&text) {
+                Some(text) => text,
+                None => return Dom::from_ref(dropped_comment(&self.document).upcast()),
+            },
+        };
+        let comment = Comment::new(DOMString::from(text), &*self.document, None);
         Dom::from_ref(comment.upcast())
     }
 
@@ -1155,6 +4219,10 @@ impl TreeSink for Sink {
         Dom::from_ref(pi.upcast())
     }
 
+    // The tree builder doesn't associate controls with a form attribute, they
+    // get their form owner when inserted, or once an element with the id they
+    // refer to is, through the form id listeners of the document (see
+    // `FormControl::register_if_necessary`). The form can come after them.
     fn associate_with_form(
         &mut self,
         target: &Dom<Node>,
@@ -1190,14 +4258,37 @@ impl TreeSink for Sink {
             .GetParentNode()
             .expect("append_before_sibling called on node without parent");
 
+        let new_node = unless_blocked(new_node, &self.blocked_elements, &self.document);
+        if let Some(ref mut text_extractor) = self.text_extractor {
+            text_extractor.extract_inserted_text(&parent, &new_node);
+        }
         insert(&parent, Some(&*sibling), new_node, self.parsing_algorithm);
+        self.inserted_nodes += 1;
     }
 
     fn parse_error(&mut self, msg: Cow<'static, str>) {
         debug!("Parse error: {}", msg);
+        if let Some(ref mut parse_errors) = self.parse_errors {
+            parse_errors.push(ParseErrorRecord {
+                line: self.current_line,
+                message: msg.to_string(),
+            });
+        }
+        if self.parsing_algorithm == ParsingAlgorithm::Normal &&
+            self.strict_parse_error.is_none() &&
+            pref!(dom.servoparser.strict.enabled)
+        {
+            self.strict_parse_error = Some(StrictParseError {
+                line: self.current_line,
+                message: msg.into_owned(),
+            });
+        }
     }
 
     fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        if self.quirks_mode_override.is_some() {
+            return;
+        }
         let mode = match mode {
             QuirksMode::Quirks => ServoQuirksMode::Quirks,
             QuirksMode::LimitedQuirks => ServoQuirksMode::LimitedQuirks,
@@ -1207,7 +4298,12 @@ impl TreeSink for Sink {
     }
 
     fn append(&mut self, parent: &Dom<Node>, child: NodeOrText<Dom<Node>>) {
+        let child = unless_blocked(child, &self.blocked_elements, &self.document);
+        if let Some(ref mut text_extractor) = self.text_extractor {
+            text_extractor.extract_inserted_text(parent, &child);
+        }
         insert(&parent, None, child, self.parsing_algorithm);
+        self.inserted_nodes += 1;
     }
 
     fn append_based_on_parent_node(
@@ -1229,7 +4325,17 @@ impl TreeSink for Sink {
         public_id: StrTendril,
         system_id: StrTendril,
     ) {
+        if let Some(observer) = self.doctype_observer {
+            observer(&name, &public_id, &system_id);
+        }
         let doc = &*self.document;
+        if doctype_mismatches_document(doc.is_html_document(), &public_id, &system_id) {
+            doc.set_doctype_mismatch(true);
+        }
+        if doc.is_html_document() && is_legacy_doctype(&name, &public_id, &system_id) {
+            let feature = DeprecatedFeature::LegacyDoctype;
+            warn_deprecated(&mut self.deprecation_warnings, self.current_line, feature);
+        }
         let doctype = DocumentType::new(
             DOMString::from(String::from(name)),
             Some(DOMString::from(String::from(public_id))),
@@ -1241,10 +4347,14 @@ impl TreeSink for Sink {
             .expect("Appending failed");
     }
 
-    fn add_attrs_if_missing(&mut self, target: &Dom<Node>, attrs: Vec<Attribute>) {
+    fn add_attrs_if_missing(&mut self, target: &Dom<Node>, mut attrs: Vec<Attribute>) {
         let elem = target
             .downcast::<Element>()
             .expect("tried to set attrs on non-Element in HTML parsing");
+        self.cap_attributes(&mut attrs, elem.attrs().len());
+        if let Some(observer) = self.raw_attributes_observer {
+            observer(elem, &attrs);
+        }
         for attr in attrs {
             elem.set_attribute_from_parser(
                 attr.name,
@@ -1266,6 +4376,15 @@ impl TreeSink for Sink {
     }
 
     fn complete_script(&mut self, node: &Dom<Node>) -> NextParserState {
+        if !self.scripting_enabled || self.blocked_elements.contains(node) {
+            return NextParserState::Continue;
+        }
+        if self.script_blocking_disabled {
+            if let Some(script) = node.downcast::<HTMLScriptElement>() {
+                script.set_already_started(true);
+            }
+            return NextParserState::Continue;
+        }
         if let Some(script) = node.downcast() {
             self.script.set(Some(script));
             NextParserState::Suspend
@@ -1292,22 +4411,528 @@ impl TreeSink for Sink {
     }
 
     fn set_current_line(&mut self, line_number: u64) {
-        self.current_line = line_number;
+        self.current_line = self.line_tracker.set_tokenizer_line(line_number);
     }
 
     fn pop(&mut self, node: &Dom<Node>) {
+        if !self.head_complete {
+            if let Some(head) = as_head_element(node) {
+                self.head_complete = true;
+                if let Some(observer) = self.head_complete_observer {
+                    observer(head);
+                }
+            }
+        }
+        if let Some(observer) = self.body_child_complete_observer {
+            if let Some(child) = as_body_child(&self.document, node) {
+                observer(child);
+            }
+        }
+        if self.parsing_algorithm == ParsingAlgorithm::Normal && node.is_in_doc() {
+            if let Some(schemes) = as_color_scheme_meta(node) {
+                self.document.declare_supported_color_schemes(schemes);
+            }
+            if !self.encoding_declared && self.document.is_html_document() {
+                self.declared_encoding = as_encoding_declaration(node);
+                self.encoding_declared = self.declared_encoding.is_some();
+            }
+        }
+
+        // An empty declarative shadow root's template only gets its shadow
+        // root now. Either way, the template itself isn't part of the
+        // document, but html5ever has already inserted it, so remove it.
+        if let Some(template) = node.downcast::<HTMLTemplateElement>() {
+            if template
+                .Content()
+                .upcast::<Node>()
+                .GetFirstChild()
+                .is_none() &&
+                self.declarative_shadow_root(node).is_none()
+            {
+                self.attach_declarative_shadow_root(node);
+            }
+            let index = self
+                .declarative_shadow_roots
+                .iter()
+                .position(|declarative| declarative.template == *node);
+            if let Some(index) = index {
+                self.declarative_shadow_roots.remove(index);
+                self.remove_from_parent(node);
+            }
+        }
+
         let node = DomRoot::from_ref(&**node);
         vtable_for(&node).pop();
     }
 }
 
+/// The node standing in for a comment dropped by a [`CommentTransformer`]. The
+/// tree builder still gets a handle to append, but appending an empty document
+/// fragment doesn't insert anything.
+fn dropped_comment(document: &Document) -> DomRoot<DocumentFragment> {
+    DocumentFragment::new(document)
+}
+
+/// The data a `Clear-Site-Data` header asks to clear for the site of a
+/// response. The `"executionContexts"` type, which would reload the documents
+/// of the origin, isn't supported.
+/// <https://w3c.github.io/webappsec-clear-site-data/>
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ClearSiteData {
+    /// The responses cached for the origin.
+    pub cache: bool,
+    /// The cookies of the registrable domain.
+    pub cookies: bool,
+    /// The local and session storage of the origin.
+    pub storage: bool,
+}
+
+impl ClearSiteData {
+    /// Clears the data of the site of `url`. This doesn't wait for the data
+    /// to be cleared, later messages to the resource threads are only handled
+    /// once it is.
+    fn clear(&self, global: &GlobalScope, url: &ServoUrl) {
+        let resource_threads = global.resource_threads();
+        if self.cache {
+            let msg = CoreResourceMsg::ClearCacheForOrigin(url.origin());
+            if let Err(error) = resource_threads.send(msg) {
+                warn!("Failed to clear the cache of {}: {:?}", url, error);
+            }
+        }
+        // https://w3c.github.io/webappsec-clear-site-data/#clear-cookies
+        // Cookies are cleared for the whole registrable domain.
+        if let Some(host) = url.host_str().filter(|_| self.cookies) {
+            let domain = reg_suffix(host).to_owned();
+            let msg = CoreResourceMsg::DeleteCookiesForRegistrableDomain(domain);
+            if let Err(error) = resource_threads.send(msg) {
+                warn!("Failed to clear the cookies of {}: {:?}", url, error);
+            }
+        }
+        if self.storage {
+            let msg = StorageThreadMsg::ClearOrigin(url.clone());
+            if let Err(error) = resource_threads.send(msg) {
+                warn!("Failed to clear the storage of {}: {:?}", url, error);
+            }
+        }
+    }
+}
+
+/// Parses the value of a `Clear-Site-Data` header, a list of quoted types.
+/// Unknown types are ignored, and `"*"` stands for all of them.
+/// <https://w3c.github.io/webappsec-clear-site-data/#parsing>
+pub fn parse_clear_site_data(value: &str) -> ClearSiteData {
+    let mut data = ClearSiteData::default();
+    for item in value.split(',') {
+        let item = item.trim_matches(|c| c == ' ' || c == '\t');
+        let item = match item
+            .strip_prefix('"')
+            .and_then(|item| item.strip_suffix('"'))
+        {
+            Some(item) => item,
+            None => continue,
+        };
+        match item {
+            "cache" => data.cache = true,
+            "cookies" => data.cookies = true,
+            "storage" => data.storage = true,
+            "*" => {
+                data.cache = true;
+                data.cookies = true;
+                data.storage = true;
+            },
+            _ => {},
+        }
+    }
+    data
+}
+
+/// What `ServoParser::close` does.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CloseAction {
+    /// Nothing, as the parser is already done with its input.
+    Ignore,
+    /// Mark the end of the input, but leave parsing the rest of it to
+    /// whatever resumes the parser: resuming it once its pending
+    /// parsing-blocking script has run, or continuing to tokenize once the
+    /// script of its own that closed it is done.
+    Defer,
+    /// Mark the end of the input, then parse the rest of it and finish.
+    Parse,
+}
+
+/// What closing a script-created parser does, given whether it has already
+/// been aborted or finished, whether it is blocked on a script, and how many
+/// of its scripts are running. Parsing while one of its scripts runs would
+/// finish the parser under the feet of the tokenizer running the script,
+/// which then finishes it a second time.
+pub fn close_action(stopped: bool, suspended: bool, script_nesting_level: usize) -> CloseAction {
+    if stopped {
+        CloseAction::Ignore
+    } else if suspended || script_nesting_level > 0 {
+        CloseAction::Defer
+    } else {
+        CloseAction::Parse
+    }
+}
+
+/// Shows the source of a response with numbered lines as it's received, see
+/// `dom.servoparser.source_line_numbers.enabled`. Each line is put in a
+/// `<span>` numbered by the stylesheet, escaped so that none of the source is
+/// parsed as markup, and the page has a checkbox to soft-wrap long lines.
+pub struct SourceViewer {
+    decoder: Decoder,
+    /// The decoded start of the line that isn't terminated yet.
+    partial_line: String,
+    /// The number of lines shown so far.
+    lines: usize,
+}
+
+impl SourceViewer {
+    /// A viewer for a source in `encoding`, unless it starts with a BOM.
+    pub fn new(encoding: &'static Encoding) -> Self {
+        Self {
+            decoder: encoding.new_decoder(),
+            partial_line: String::new(),
+            lines: 0,
+        }
+    }
+
+    /// The start of the page, up to the `<pre>` with the given class the
+    /// lines are shown in.
+    pub fn header(class: Option<&str>) -> String {
+        let class = match class {
+            Some(class) => format!("source {}", escape_html_text(class)),
+            None => "source".to_owned(),
+        };
+        format!(
+            "<html><head><style>\
+             pre.source {{ counter-reset: line; white-space: pre; }}\
+             #wrap:checked ~ pre.source {{ white-space: pre-wrap; }}\
+             .line::before {{ counter-increment: line; content: counter(line); \
+             display: inline-block; min-width: 3em; margin-right: 1em; text-align: right; \
+             color: gray; }}\
+             </style></head><body>\
+             <input type=\"checkbox\" id=\"wrap\"><label for=\"wrap\">Wrap long lines</label>\
+             <pre class=\"{}\">\n",
+            class
+        )
+    }
+
+    /// Decodes more of the source, and returns the lines it terminates.
+    pub fn push(&mut self, bytes: &[u8]) -> String {
+        self.decode(bytes, false);
+        self.take_lines()
+    }
+
+    /// Decodes the end of the source, and returns the lines left and the end
+    /// of the page.
+    pub fn finish(&mut self) -> String {
+        self.decode(&[], true);
+        let mut markup = self.take_lines();
+        // An empty source is still shown as one empty line.
+        if !self.partial_line.is_empty() || self.lines == 0 {
+            let line = mem::take(&mut self.partial_line);
+            self.push_line(&mut markup, &line);
+        }
+        markup.push_str("</pre></body></html>");
+        markup
+    }
+
+    fn decode(&mut self, bytes: &[u8], last: bool) {
+        let max_length = self
+            .decoder
+            .max_utf8_buffer_length(bytes.len())
+            .expect("Source chunk too large to decode");
+        self.partial_line.reserve(max_length);
+        let (_, read, _) = self
+            .decoder
+            .decode_to_string(bytes, &mut self.partial_line, last);
+        debug_assert_eq!(read, bytes.len());
+    }
+
+    /// Returns the lines terminated by now, keeping the partial line after
+    /// them.
+    fn take_lines(&mut self) -> String {
+        let end = match self.partial_line.rfind('\n') {
+            Some(index) => index + 1,
+            None => return String::new(),
+        };
+        let text: String = self.partial_line.drain(..end).collect();
+        let mut markup = String::new();
+        for line in text.split_terminator('\n') {
+            self.push_line(&mut markup, line);
+        }
+        markup
+    }
+
+    fn push_line(&mut self, markup: &mut String, line: &str) {
+        self.lines += 1;
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        markup.push_str(&format!(
+            "<span class=\"line\" id=\"L{}\">{}</span>\n",
+            self.lines,
+            escape_html_text(line)
+        ));
+    }
+}
+
+/// The page shown for a response whose content type can't be rendered, when
+/// `dom.servoparser.unknown_content_type` doesn't ask for anything else.
+pub fn unknown_content_type_page(mime_type: &str, subtype: &str) -> String {
+    format!(
+        "<html><body><p>Unknown content type ({}/{}).</p></body></html>",
+        escape_html_text(mime_type),
+        escape_html_text(subtype)
+    )
+}
+
+/// The page shown for an image whose content type isn't supported, as it
+/// would only show as a broken image.
+pub fn unsupported_image_page(content_type: &str) -> String {
+    format!(
+        "<html><body><p>Images of type {} are not supported.</p></body></html>",
+        escape_html_text(content_type)
+    )
+}
+
+/// The page shown instead of the insecure page at `url`, that a secure page
+/// navigated to, when `dom.servoparser.mixed_content_warning.enabled` is set.
+pub fn mixed_content_page(url: &str) -> String {
+    format!(
+        "<html><body><p>{} was not shown because a secure page navigated to it \
+         over an insecure connection.</p></body></html>",
+        escape_html_text(url)
+    )
+}
+
+/// The page shown for the response at `url` without a content type, when it
+/// forbids sniffing one.
+pub fn no_content_type_page(url: &str) -> String {
+    format!(
+        "<html><body><p>{} has no content type, and the server forbids guessing \
+         it.</p></body></html>",
+        escape_html_text(url)
+    )
+}
+
+/// Replaces `placeholder` in the template of a synthesized page with `value`,
+/// escaped so that it can't inject markup into the page. This is for text
+/// and quoted attribute values, not for values in scripts or stylesheets.
+pub fn substitute_html(template: &str, placeholder: &str, value: &str) -> String {
+    template.replace(placeholder, &escape_html_text(value))
+}
+
+/// Escapes text to be put in an element or a quoted attribute value.
+fn escape_html_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The length of the signature a font file starts with, see [`is_font_file`].
+const FONT_SIGNATURE_LEN: usize = 4;
+
+/// Whether `bytes` start like a TrueType, OpenType, WOFF or WOFF2 file, or a
+/// collection of fonts.
+/// <https://learn.microsoft.com/en-us/typography/opentype/spec/otff#organization-of-an-opentype-font>
+/// <https://www.w3.org/TR/WOFF2/#woff20Header>
+pub fn is_font_file(bytes: &[u8]) -> bool {
+    let signature = match bytes.get(..FONT_SIGNATURE_LEN) {
+        Some(signature) => signature,
+        None => return false,
+    };
+    matches!(
+        signature,
+        [0, 1, 0, 0] | b"OTTO" | b"true" | b"typ1" | b"ttcf" | b"wOFF" | b"wOF2"
+    )
+}
+
+/// The page left in place of the PDF at `url`, once the embedder was asked
+/// to show it in a PDF viewer.
+pub fn pdf_viewer_page(url: &ServoUrl) -> String {
+    format!(
+        "<html><head><title>{0}</title></head><body>\
+         <p>{0} is opened in a PDF viewer.</p></body></html>",
+        escape_html_text(url.as_str())
+    )
+}
+
+/// The page showing the font at `url`, loaded with `@font-face`, with a
+/// pangram at several sizes. If the font can't be loaded after all, the
+/// text is shown in the default font.
+pub fn font_specimen_page(url: &ServoUrl) -> String {
+    // The URL goes in a stylesheet, where character references aren't
+    // decoded, so markup characters are percent-encoded instead.
+    let css_url = url
+        .as_str()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('&', "%26")
+        .replace('<', "%3C")
+        .replace('>', "%3E");
+    let sizes: String = [12, 18, 24, 36, 48, 72]
+        .iter()
+        .map(|size| {
+            format!(
+                "<p style=\"font-size: {}px\">The quick brown fox jumps over the lazy dog.</p>",
+                size
+            )
+        })
+        .collect();
+    format!(
+        "<html><head><title>{}</title><style>\
+         @font-face {{ font-family: \"specimen\"; src: url(\"{}\"); }}\
+         body {{ font-family: \"specimen\"; }}\
+         p {{ margin: 0.25em 0; white-space: nowrap; }}\
+         </style></head><body>\
+         <p style=\"font-size: 36px\">ABCDEFGHIJKLMNOPQRSTUVWXYZ<br>abcdefghijklmnopqrstuvwxyz<br>\
+         0123456789</p>{}</body></html>",
+        escape_html_text(url.as_str()),
+        css_url,
+        sizes
+    )
+}
+
+/// Whether `subtype` is that of an icon, as favicons usually are.
+fn is_icon_subtype(subtype: &str) -> bool {
+    subtype.eq_ignore_ascii_case("x-icon") || subtype.eq_ignore_ascii_case("vnd.microsoft.icon")
+}
+
+/// An image in an ICO file, as listed in its directory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IconEntry {
+    pub width: u32,
+    pub height: u32,
+    pub bits_per_pixel: u16,
+}
+
+/// The images listed in the directory of the ICO file `bytes`, largest first,
+/// or `None` if it isn't an ICO file, lists no image, or is cut short.
+/// <https://en.wikipedia.org/wiki/ICO_(file_format)>
+pub fn icon_entries(bytes: &[u8]) -> Option<Vec<IconEntry>> {
+    let u16_at = |offset: usize| -> Option<u16> {
+        let bytes = bytes.get(offset..offset + 2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    };
+    // The directory starts with a reserved zero, and the type of the file,
+    // which is 1 for icons.
+    if u16_at(0)? != 0 || u16_at(2)? != 1 {
+        return None;
+    }
+    let count = u16_at(4)? as usize;
+    if count == 0 {
+        return None;
+    }
+    let mut entries = (0..count)
+        .map(|index| {
+            let offset = 6 + index * 16;
+            let entry = bytes.get(offset..offset + 16)?;
+            // A width or height of 0 stands for 256 pixels.
+            let pixels = |byte: u8| if byte == 0 { 256 } else { byte as u32 };
+            Some(IconEntry {
+                width: pixels(entry[0]),
+                height: pixels(entry[1]),
+                bits_per_pixel: u16::from_le_bytes([entry[6], entry[7]]),
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.width * entry.height));
+    Some(entries)
+}
+
+/// The page showing the icon at `url`, whose image decoder picks its largest
+/// image, along with the list of all its images.
+fn icon_viewer_page(url: &ServoUrl, entries: &[IconEntry]) -> String {
+    let rows: String = entries
+        .iter()
+        .map(|entry| {
+            format!(
+                "<tr><td>{}\u{d7}{}</td><td>{}</td></tr>",
+                entry.width, entry.height, entry.bits_per_pixel
+            )
+        })
+        .collect();
+    format!(
+        "<html><body><img src=\"{}\"><table><tr><th>Size</th><th>Bits per pixel</th></tr>{}\
+         </table></body></html>",
+        escape_html_text(url.as_str()),
+        rows
+    )
+}
+
+/// Whether `blocker` blocks the element named `name` with the attributes
+/// `attrs`. The elements the parser relies on to build the document, `<html>`,
+/// `<head>`, `<body>` and `<template>`, are never blocked.
+pub fn is_blocked_element(
+    blocker: Option<ElementBlocker>,
+    name: &QualName,
+    attrs: &[Attribute],
+) -> bool {
+    let blocker = match blocker {
+        Some(blocker) => blocker,
+        None => return false,
+    };
+    let is_structural = name.ns == ns!(html) &&
+        matches!(
+            name.local,
+            local_name!("html") |
+                local_name!("head") |
+                local_name!("body") |
+                local_name!("template")
+        );
+    !is_structural && blocker(name, attrs)
+}
+
+/// The node to insert instead of `child`, which is a comment standing in for
+/// it if it is one of the `blocked_elements`, and `child` itself otherwise.
+fn unless_blocked(
+    child: NodeOrText<Dom<Node>>,
+    blocked_elements: &[Dom<Node>],
+    document: &Document,
+) -> NodeOrText<Dom<Node>> {
+    match child {
+        NodeOrText::AppendNode(ref node) if blocked_elements.contains(node) => {
+            let name = node.downcast::<Element>().unwrap().local_name();
+            let text = DOMString::from(format!("blocked <{}>", name));
+            let comment = Comment::new(text, document, None);
+            NodeOrText::AppendNode(Dom::from_ref(comment.upcast()))
+        },
+        child => child,
+    }
+}
+
 /// <https://html.spec.whatwg.org/multipage/#create-an-element-for-the-token>
+/// Calls the [`CustomElementCreationObserver`] of the current parser of
+/// `document`, if any.
+fn observe_custom_element_creation(
+    document: &Document,
+    local_name: &LocalName,
+    phase: CustomElementCreationPhase,
+) {
+    let observer = document
+        .get_current_parser()
+        .and_then(|parser| parser.custom_element_creation_observer.get());
+    if let Some(observer) = observer {
+        observer(local_name, phase);
+    }
+}
+
 fn create_element_for_token(
     name: QualName,
     attrs: Vec<ElementAttribute>,
     document: &Document,
     creator: ElementCreator,
     parsing_algorithm: ParsingAlgorithm,
+    counters: &mut ParserElementCounters,
+    sanitize_input_values: bool,
 ) -> DomRoot<Element> {
     // Step 3.
     let is = attrs
@@ -1328,11 +4953,16 @@ fn create_element_for_token(
         document.increment_throw_on_dynamic_markup_insertion_counter();
         // Step 6.2
         if is_execution_stack_empty() {
-            document
-                .window()
-                .upcast::<GlobalScope>()
-                .perform_a_microtask_checkpoint();
+            match document.get_current_parser() {
+                Some(parser) => parser
+                    .perform_a_microtask_checkpoint(MicrotaskCheckpointSite::CustomElementCreation),
+                None => document
+                    .window()
+                    .upcast::<GlobalScope>()
+                    .perform_a_microtask_checkpoint(),
+            }
         }
+        observe_custom_element_creation(document, &name.local, CustomElementCreationPhase::Start);
         // Step 6.3
         ScriptThread::push_new_element_queue()
     }
@@ -1344,6 +4974,25 @@ fn create_element_for_token(
         CustomElementCreationMode::Asynchronous
     };
 
+    counters.created += 1;
+    if will_execute_script {
+        counters.custom_synchronous += 1;
+    } else if definition.is_some() {
+        counters.custom_asynchronous += 1;
+    }
+    if parsing_algorithm == ParsingAlgorithm::Normal &&
+        is_stylesheet_link(&name, &attrs) &&
+        document.GetBody().is_none()
+    {
+        counters.render_blocking_stylesheets += 1;
+    }
+    // https://html.spec.whatwg.org/multipage/#allows-adding-render-blocking-elements
+    let blocks_rendering = parsing_algorithm == ParsingAlgorithm::Normal &&
+        document.is_html_document() &&
+        document.GetBody().is_none() &&
+        is_render_blocking_element(&name, &attrs);
+
+    let local_name = name.local.clone();
     let element = Element::create(name, is, document, creator, creation_mode, None);
 
     // https://html.spec.whatwg.org/multipage#the-input-element:value-sanitization-algorithm-3
@@ -1366,13 +5015,22 @@ fn create_element_for_token(
     // _now_ we can sanitize (and we sanitize now even if the "value"
     // attribute isn't present!)
     if let Some(input) = maybe_input {
-        input.enable_sanitization();
+        if sanitize_input_values {
+            input.enable_sanitization();
+        } else {
+            input.enable_sanitization_keeping_value();
+        }
+    }
+
+    if blocks_rendering {
+        document.block_rendering(&element);
     }
 
     // Step 9.
     if will_execute_script {
         // Steps 9.1 - 9.2.
         ScriptThread::pop_current_element_queue();
+        observe_custom_element_creation(document, &local_name, CustomElementCreationPhase::End);
         // Step 9.3.
         document.decrement_throw_on_dynamic_markup_insertion_counter();
     }
@@ -1386,21 +5044,111 @@ fn create_element_for_token(
     element
 }
 
+/// Decodes the bytes of the network input, which may come in chunks of any
+/// size, into text for the tokenizer.
+///
+/// The text is always valid UTF-8, as a `StrTendril` must be: malformed input
+/// is replaced with U+FFFD, including lone UTF-16 surrogates, which encoding_rs
+/// never decodes to anything else. A surrogate pair split between chunks is
+/// still decoded to its character, and a high surrogate the input ends with to
+/// U+FFFD, once finished.
 #[derive(JSTraceable, MallocSizeOf)]
-struct NetworkDecoder {
+pub struct NetworkDecoder {
     #[ignore_malloc_size_of = "Defined in tendril"]
     #[custom_trace]
     decoder: LossyDecoder<NetworkSink>,
+    #[no_trace]
+    encoding: &'static Encoding,
+    /// All the bytes decoded so far, as long as there are no more of them than
+    /// `dom.servoparser.encoding_change_buffer_size`. These tell whether the
+    /// encoding can still be changed without changing the decoded input.
+    retained_bytes: Option<Vec<u8>>,
+    /// The last bytes decoded, which may not make a whole character yet, see
+    /// `undecoded_suffix_len`.
+    tail: Vec<u8>,
+    /// The number of bytes decoded so far.
+    bytes_decoded: usize,
 }
 
 impl NetworkDecoder {
-    fn new(encoding: &'static Encoding) -> Self {
+    pub fn new(encoding: &'static Encoding) -> Self {
+        let retain_bytes = pref!(dom.servoparser.encoding_change_buffer_size) > 0;
         Self {
             decoder: LossyDecoder::new_encoding_rs(encoding, Default::default()),
+            encoding,
+            retained_bytes: if retain_bytes { Some(vec![]) } else { None },
+            tail: vec![],
+            bytes_decoded: 0,
+        }
+    }
+
+    /// A decoder that goes on decoding from a checkpoint, at which the bytes
+    /// decoded last were `undecoded_bytes`, see `undecoded_bytes`. The bytes
+    /// decoded before aren't known, so the encoding can't be changed anymore.
+    pub fn restore(encoding: &'static Encoding, undecoded_bytes: &[u8]) -> Self {
+        let mut decoder = NetworkDecoder::new(encoding);
+        decoder.retained_bytes = None;
+        let decoded = decoder.decode(undecoded_bytes.to_vec());
+        debug_assert!(decoded.is_empty());
+        decoder
+    }
+
+    /// The bytes decoded last that the decoder holds on to because they don't
+    /// make a whole character yet, or `None` if that can't be told for the
+    /// encoding.
+    pub fn undecoded_bytes(&self) -> Option<&[u8]> {
+        let len = undecoded_suffix_len(self.encoding, &self.tail, self.bytes_decoded)?;
+        Some(&self.tail[self.tail.len() - len..])
+    }
+
+    fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    /// All the bytes decoded so far, if they were retained, see
+    /// `dom.servoparser.encoding_change_buffer_size`.
+    pub fn retained_bytes(&self) -> Option<&[u8]> {
+        self.retained_bytes.as_deref()
+    }
+
+    /// Switches to decoding the bytes to come with `encoding`, if the bytes
+    /// decoded so far decode to the same input with it. This is only known if
+    /// all of these bytes were retained.
+    /// <https://html.spec.whatwg.org/multipage/#changing-the-encoding-while-parsing>
+    fn change_encoding(&mut self, encoding: &'static Encoding) -> bool {
+        if encoding == self.encoding {
+            return true;
+        }
+        let retained_bytes = match self.retained_bytes.take() {
+            Some(retained_bytes) => retained_bytes,
+            None => return false,
+        };
+        if !decodes_identically(&retained_bytes, self.encoding, encoding) {
+            self.retained_bytes = Some(retained_bytes);
+            return false;
         }
+        let mut decoder = NetworkDecoder::new(encoding);
+        decoder.retained_bytes = decoder.retained_bytes.map(|_| retained_bytes);
+        decoder.tail = mem::take(&mut self.tail);
+        decoder.bytes_decoded = self.bytes_decoded;
+        *self = decoder;
+        true
     }
 
-    fn decode(&mut self, chunk: Vec<u8>) -> StrTendril {
+    pub fn decode(&mut self, chunk: Vec<u8>) -> StrTendril {
+        if let Some(ref mut retained_bytes) = self.retained_bytes {
+            let limit = pref!(dom.servoparser.encoding_change_buffer_size);
+            if (retained_bytes.len() + chunk.len()) as i64 > limit {
+                self.retained_bytes = None;
+            } else {
+                retained_bytes.extend_from_slice(&chunk);
+            }
+        }
+        self.bytes_decoded += chunk.len();
+        self.tail
+            .extend_from_slice(&chunk[chunk.len().saturating_sub(MAX_UNDECODED_LEN)..]);
+        let excess = self.tail.len().saturating_sub(MAX_UNDECODED_LEN);
+        self.tail.drain(..excess);
         self.decoder.process(ByteTendril::from(&*chunk));
         mem::replace(
             &mut self.decoder.inner_sink_mut().output,
@@ -1408,11 +5156,464 @@ impl NetworkDecoder {
         )
     }
 
-    fn finish(self) -> StrTendril {
+    pub fn finish(self) -> StrTendril {
         self.decoder.finish()
     }
 }
 
+/// The most bytes a decoder holds on to without decoding them, as those of a
+/// UTF-8 sequence missing its last byte, or a UTF-16 surrogate pair missing
+/// its last byte.
+const MAX_UNDECODED_LEN: usize = 3;
+
+/// How many of the bytes at the end of `input_len` bytes of input, the last
+/// ones of which are `tail`, a decoder for `encoding` holds on to because they
+/// don't make a whole character yet. This is `None` for the legacy multi-byte
+/// encodings, whose decoders aren't looked into.
+pub fn undecoded_suffix_len(
+    encoding: &'static Encoding,
+    tail: &[u8],
+    input_len: usize,
+) -> Option<usize> {
+    if encoding == encoding_rs::UTF_8 {
+        return Some(utf8_undecoded_suffix_len(tail));
+    }
+    if encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE {
+        let odd = input_len % 2;
+        let units = &tail[..tail.len() - odd.min(tail.len())];
+        let last_unit = match units {
+            [.., a, b] if encoding == encoding_rs::UTF_16LE => Some(u16::from_le_bytes([*a, *b])),
+            [.., a, b] => Some(u16::from_be_bytes([*a, *b])),
+            _ => None,
+        };
+        let high_surrogate = matches!(last_unit, Some(0xD800..=0xDBFF));
+        return Some(odd + if high_surrogate { 2 } else { 0 });
+    }
+    if encoding.is_single_byte() || encoding == encoding_rs::REPLACEMENT {
+        return Some(0);
+    }
+    None
+}
+
+/// How many of the bytes at the end of `tail` are the start of a UTF-8
+/// sequence that is still valid but not whole yet.
+fn utf8_undecoded_suffix_len(tail: &[u8]) -> usize {
+    for len in 1..=tail.len().min(MAX_UNDECODED_LEN) {
+        let sequence = &tail[tail.len() - len..];
+        let lead = sequence[0];
+        if (0x80..=0xBF).contains(&lead) {
+            continue;
+        }
+        let (sequence_len, second) = match lead {
+            0xC2..=0xDF => (2, 0x80..=0xBF),
+            0xE0 => (3, 0xA0..=0xBF),
+            0xED => (3, 0x80..=0x9F),
+            0xE1..=0xEF => (3, 0x80..=0xBF),
+            0xF0 => (4, 0x90..=0xBF),
+            0xF4 => (4, 0x80..=0x8F),
+            0xF1..=0xF3 => (4, 0x80..=0xBF),
+            _ => return 0,
+        };
+        let valid = sequence.get(1).map_or(true, |byte| second.contains(byte));
+        return if len < sequence_len && valid { len } else { 0 };
+    }
+    0
+}
+
+/// Detects the encoding of `bytes`, the start of the input or all of it if
+/// `last`, from their content, for a document from a domain with the given
+/// top-level domain. This is `None` unless the detector is confident, which it
+/// never is for ASCII input.
+pub fn sniff_content_encoding(
+    bytes: &[u8],
+    tld: Option<&str>,
+    last: bool,
+) -> Option<&'static Encoding> {
+    if bytes.is_ascii() {
+        return None;
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, last);
+    let (encoding, confident) = detector.guess_assess(tld.map(str::as_bytes), true);
+    if confident {
+        Some(encoding)
+    } else {
+        None
+    }
+}
+
+/// Parses the value of a `Retry-After` header into the delay it asks for from
+/// `now`. A date in the past is no delay.
+/// <https://httpwg.org/specs/rfc9110.html#field.retry-after>
+pub fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return value.parse().ok().map(Duration::from_secs);
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    let date = SystemTime::from(date);
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+/// Whether a navigation from a document of the `initiator` origin to `url`
+/// downgrades from a secure connection to an insecure one.
+pub fn is_mixed_content_navigation(initiator: &ImmutableOrigin, url: &ServoUrl) -> bool {
+    initiator.scheme() == Some("https") && !url.is_potentially_trustworthy()
+}
+
+/// Returns `node` as an element if it is an HTML `<head>` element.
+fn as_head_element(node: &Node) -> Option<&Element> {
+    node.downcast::<Element>()
+        .filter(|elem| *elem.namespace() == ns!(html) && *elem.local_name() == local_name!("head"))
+}
+
+/// The element that `node` is if it is a child of the `<body>` of `document`,
+/// which the tree builder inserted the same whether the document had a
+/// `<body>` start tag or not. The `<body>` of a document with a `<frameset>`
+/// has no children.
+fn as_body_child<'a>(document: &Document, node: &'a Node) -> Option<&'a Element> {
+    let body = document.GetBody()?;
+    if *body.upcast::<Element>().local_name() != local_name!("body") {
+        return None;
+    }
+    let parent = node.GetParentNode()?;
+    if &*parent != body.upcast::<Node>() {
+        return None;
+    }
+    node.downcast::<Element>()
+}
+
+/// The color schemes declared by `node`, if it's a `<meta name="color-scheme">`
+/// whose content is valid.
+/// <https://html.spec.whatwg.org/multipage/#meta-color-scheme>
+fn as_color_scheme_meta(node: &Node) -> Option<SupportedColorSchemes> {
+    let elem = node.downcast::<Element>()?;
+    if *elem.namespace() != ns!(html) || *elem.local_name() != local_name!("meta") {
+        return None;
+    }
+    let name = elem.get_attribute(&ns!(), &local_name!("name"))?;
+    if !name
+        .value()
+        .trim_matches(HTML_SPACE_CHARACTERS)
+        .eq_ignore_ascii_case("color-scheme")
+    {
+        return None;
+    }
+    let content = elem.get_attribute(&ns!(), &local_name!("content"))?;
+    parse_color_scheme(&content.value())
+}
+
+/// The encoding declared by `node`, if it's a `<meta>` whose `charset` names
+/// one, or else a `<meta http-equiv="content-type">` whose `content` does.
+/// <https://html.spec.whatwg.org/multipage/#parsing-main-inhead>
+fn as_encoding_declaration(node: &Node) -> Option<&'static Encoding> {
+    let elem = node.downcast::<Element>()?;
+    if *elem.namespace() != ns!(html) || *elem.local_name() != local_name!("meta") {
+        return None;
+    }
+    let charset = elem
+        .get_attribute(&ns!(), &local_name!("charset"))
+        .and_then(|charset| Encoding::for_label(charset.value().as_bytes()));
+    if charset.is_some() {
+        return charset;
+    }
+    let http_equiv = elem.get_attribute(&ns!(), &local_name!("http-equiv"))?;
+    if !http_equiv
+        .value()
+        .trim_matches(HTML_SPACE_CHARACTERS)
+        .eq_ignore_ascii_case("content-type")
+    {
+        return None;
+    }
+    let content = elem.get_attribute(&ns!(), &local_name!("content"))?;
+    meta_content_encoding(&content.value())
+}
+
+/// Extracts the encoding named by the `content` of a
+/// `<meta http-equiv="content-type">`, such as `text/html; charset=utf-8`.
+/// <https://html.spec.whatwg.org/multipage/#algorithm-for-extracting-a-character-encoding-from-a-meta-element>
+pub fn meta_content_encoding(content: &str) -> Option<&'static Encoding> {
+    let content = content.as_bytes();
+    let skip_whitespace = |mut position: usize| {
+        while content
+            .get(position)
+            .map_or(false, |byte| byte.is_ascii_whitespace())
+        {
+            position += 1;
+        }
+        position
+    };
+    let mut position = 0;
+    loop {
+        // Steps 2-3.
+        let charset = content[position..]
+            .windows(b"charset".len())
+            .position(|window| window.eq_ignore_ascii_case(b"charset"))?;
+        position += charset + b"charset".len();
+        let after_charset = skip_whitespace(position);
+
+        // Step 4.
+        if content.get(after_charset) != Some(&b'=') {
+            continue;
+        }
+
+        // Step 5.
+        let value_start = skip_whitespace(after_charset + 1);
+
+        // Step 6.
+        return match content.get(value_start)? {
+            &quote @ (b'"' | b'\'') => {
+                let value = &content[value_start + 1..];
+                let value_len = value.iter().position(|&byte| byte == quote)?;
+                Encoding::for_label(&value[..value_len])
+            },
+            _ => {
+                let value = &content[value_start..];
+                let value_len = value
+                    .iter()
+                    .position(|&byte| byte.is_ascii_whitespace() || byte == b';')
+                    .unwrap_or(value.len());
+                Encoding::for_label(&value[..value_len])
+            },
+        };
+    }
+}
+
+/// Parses the value of a `<meta name="color-scheme">`, which is that of the
+/// CSS `color-scheme` property, or returns `None` if it isn't valid.
+/// <https://drafts.csswg.org/css-color-adjust/#color-scheme-prop>
+pub fn parse_color_scheme(content: &str) -> Option<SupportedColorSchemes> {
+    let tokens: Vec<&str> = content.split_ascii_whitespace().collect();
+    let mut color_schemes = SupportedColorSchemes::default();
+    for (i, token) in tokens.iter().enumerate() {
+        match &*token.to_ascii_lowercase() {
+            "normal" if tokens.len() == 1 => return Some(color_schemes),
+            "only" if !color_schemes.only && (i == 0 || i == tokens.len() - 1) => {
+                color_schemes.only = true
+            },
+            "normal" | "only" | "default" | "initial" | "inherit" | "unset" | "revert" |
+            "revert-layer" => return None,
+            scheme @ "light" | scheme @ "dark" => color_schemes.schemes.push(scheme.to_owned()),
+            _ if is_css_identifier(token) => color_schemes.schemes.push((*token).to_owned()),
+            _ => return None,
+        }
+    }
+    if color_schemes.schemes.is_empty() {
+        return None;
+    }
+    Some(color_schemes)
+}
+
+/// Whether `token` is a CSS identifier without escapes.
+/// <https://drafts.csswg.org/css-syntax/#ident-token-diagram>
+fn is_css_identifier(token: &str) -> bool {
+    let start = token.strip_prefix('-').unwrap_or(token);
+    match start.chars().next() {
+        Some(c) if c.is_ascii_digit() => false,
+        Some(_) => token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || !c.is_ascii()),
+        None => false,
+    }
+}
+
+/// Whether `x` and `y` are in the same tree, i.e. have the same root, which
+/// is never across a shadow boundary: an element in a shadow tree is never in
+/// the same tree as an element of the tree of its host, nor of another shadow
+/// tree. Comparing root nodes rather than root elements also puts elements
+/// without a common element ancestor in the same tree when they share a root,
+/// e.g. children of the same shadow root.
+/// <https://dom.spec.whatwg.org/#concept-tree-root>
+pub(crate) fn in_same_tree(x: &Node, y: &Node) -> bool {
+    let root = |node: &Node| node.inclusive_ancestors(ShadowIncluding::No).last();
+    root(x) == root(y)
+}
+
+/// Returns `input` without the U+FEFF BYTE ORDER MARK it starts with, if any.
+/// Only one is dropped, any other is part of the content.
+pub fn strip_leading_bom(input: &str) -> &str {
+    input.strip_prefix('\u{FEFF}').unwrap_or(input)
+}
+
+/// Drops the U+FEFF BYTE ORDER MARK from the start of `chunk` if it starts
+/// the input, as told by `at_start`, which is cleared by the first chunk that
+/// isn't empty, so that only one BOM is ever dropped, even if it's the whole
+/// chunk.
+pub fn strip_bom_at_start_of_input(chunk: &mut StrTendril, at_start: &mut bool) {
+    if !*at_start || chunk.is_empty() {
+        return;
+    }
+    *at_start = false;
+    let bom_len = chunk.len() - strip_leading_bom(chunk).len();
+    chunk.pop_front(bom_len as u32);
+}
+
+/// Whether `bytes` decode to the same text with both encodings, in which case
+/// input decoded with one of them may continue to be decoded with the other.
+pub fn decodes_identically(bytes: &[u8], from: &'static Encoding, to: &'static Encoding) -> bool {
+    if from == to {
+        return true;
+    }
+    let (from_text, from_had_errors) = from.decode_without_bom_handling(bytes);
+    let (to_text, to_had_errors) = to.decode_without_bom_handling(bytes);
+    !from_had_errors && !to_had_errors && from_text == to_text
+}
+
+/// Whether an HTML document of `size_hint` bytes, if known, is tokenized off
+/// the main thread. Nothing is unless the
+/// `dom.servoparser.async_html_tokenizer.enabled` pref, passed as `enabled`,
+/// is set. With a positive `size_threshold`, documents of known size are then
+/// only tokenized off the main thread from that size on, where it pays off.
+pub fn uses_async_html_tokenizer(
+    enabled: bool,
+    size_threshold: i64,
+    size_hint: Option<u64>,
+) -> bool {
+    enabled &&
+        (size_threshold <= 0 || size_hint.map_or(true, |size| size >= size_threshold as u64))
+}
+
+/// The size of the chunks in which the `innerHTML` and `insertAdjacentHTML`
+/// setters parse a fragment of `size` bytes, if they parse it in chunks at all.
+/// Nothing is unless the `dom.servoparser.chunked_fragment_parsing.enabled`
+/// pref, passed as `enabled`, is set, and a positive `chunk_size` is given.
+/// Only fragments larger than one chunk, and of at least `size_threshold`
+/// bytes if that's positive, are then parsed in chunks.
+pub fn fragment_chunk_size(
+    enabled: bool,
+    chunk_size: i64,
+    size_threshold: i64,
+    size: usize,
+) -> Option<usize> {
+    if !enabled || chunk_size <= 0 {
+        return None;
+    }
+    let chunk_size = chunk_size as usize;
+    if size <= chunk_size || (size_threshold > 0 && size < size_threshold as usize) {
+        return None;
+    }
+    Some(chunk_size)
+}
+
+/// Resolves the value of a `Content-Location` header against the URL of the
+/// response, returning `None` for an empty or invalid value. Only http(s) URLs
+/// of the same origin as the response are accepted, so that the header can't
+/// make relative URLs resolve to another site, or to `data:` or `javascript:`
+/// URLs.
+/// <https://httpwg.org/specs/rfc9110.html#field.content-location>
+pub fn content_location_url(value: &str, response_url: &ServoUrl) -> Option<ServoUrl> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    let url = response_url.join(value).ok()?;
+    if !matches!(url.scheme(), "http" | "https") || url.origin() != response_url.origin() {
+        return None;
+    }
+    Some(url)
+}
+
+/// Returns the encoding named by the `charset` parameter of `content_type`,
+/// if it has one that is a known label. Labels like `iso-2022-kr` resolve to
+/// the replacement encoding, which decodes the whole input to a single
+/// U+FFFD, so that content in these encodings can't smuggle markup past
+/// filters.
+pub fn content_type_encoding(content_type: &Mime) -> Option<&'static Encoding> {
+    let charset = content_type.get_param(mime::CHARSET)?;
+    Encoding::for_label(charset.as_str().as_bytes())
+}
+
+/// Whether the `X-Content-Type-Options` header of a response, whose values
+/// are combined into `value`, forbids sniffing its content type.
+/// <https://fetch.spec.whatwg.org/#determine-nosniff>
+pub fn is_nosniff(value: &str) -> bool {
+    let first_value = value.split(',').next().unwrap_or_default();
+    first_value
+        .trim_matches(|c| matches!(c, ' ' | '\t'))
+        .eq_ignore_ascii_case("nosniff")
+}
+
+/// What is shown for a response to a navigation whose content type is neither
+/// parsed as a document nor shown by one of the viewers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnknownContentTypeHandling {
+    /// A page saying that the content type is unknown.
+    Warning,
+    /// The body of the response, as text.
+    PlainText,
+    /// A page saying that the response is offered for download, after asking
+    /// the embedder to download it.
+    Download,
+    /// The `unknowncontenttype.html` resource, in which `${type}` and `${url}`
+    /// are replaced by the content type and the URL of the response.
+    CustomHtml,
+}
+
+impl Default for UnknownContentTypeHandling {
+    fn default() -> UnknownContentTypeHandling {
+        UnknownContentTypeHandling::Warning
+    }
+}
+
+impl UnknownContentTypeHandling {
+    /// The handling named by a value of the
+    /// `dom.servoparser.unknown_content_type` pref, which is the warning page
+    /// for any value but `"plaintext"`, `"download"` and `"custom"`.
+    pub fn from_pref_value(value: &str) -> UnknownContentTypeHandling {
+        match value {
+            "plaintext" => UnknownContentTypeHandling::PlainText,
+            "download" => UnknownContentTypeHandling::Download,
+            "custom" => UnknownContentTypeHandling::CustomHtml,
+            _ => UnknownContentTypeHandling::Warning,
+        }
+    }
+}
+
+/// Whether a response to a navigation with the given status is one that
+/// doesn't replace the current document, which is 204 No Content and 205 Reset
+/// Content. No document is created for such a response, whatever its body.
+/// <https://html.spec.whatwg.org/multipage/#process-a-navigate-response>
+pub fn is_no_content_status(status: Option<u16>) -> bool {
+    matches!(status, Some(204) | Some(205))
+}
+
+/// Whether the body of a response to a navigation with the given content type
+/// is parsed as HTML. A response without a content type is assumed to be HTML
+/// unless it forbids sniffing, since that is a guess; any other content type is
+/// only ever shown as what it is.
+pub fn is_parsed_as_html(content_type: Option<&Mime>, nosniff: bool) -> bool {
+    match content_type {
+        Some(content_type) => {
+            content_type.type_() == mime::TEXT && content_type.subtype() == mime::HTML
+        },
+        None => !nosniff,
+    }
+}
+
+/// The content type of a local file whose content type is unknown, from the
+/// extension of its name, for the extensions of HTML and XHTML documents.
+/// Only `file:` URLs are looked at, as guessing the content type of network
+/// responses would allow content to be handled in ways the server didn't want.
+pub fn file_extension_content_type(url: &ServoUrl, content_type: Option<&Mime>) -> Option<Mime> {
+    if url.scheme() != "file" {
+        return None;
+    }
+    if content_type.map_or(false, |content_type| {
+        *content_type != mime::APPLICATION_OCTET_STREAM
+    }) {
+        return None;
+    }
+    let file_name = url.path().rsplit('/').next()?;
+    let (_, extension) = file_name.rsplit_once('.')?;
+    if extension.eq_ignore_ascii_case("xhtml") || extension.eq_ignore_ascii_case("xht") {
+        "application/xhtml+xml".parse().ok()
+    } else if extension.eq_ignore_ascii_case("html") || extension.eq_ignore_ascii_case("htm") {
+        Some(mime::TEXT_HTML)
+    } else {
+        None
+    }
+}
+
 #[derive(Default, JSTraceable)]
 struct NetworkSink {
     #[no_trace]
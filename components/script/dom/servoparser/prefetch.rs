@@ -2,23 +2,39 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use std::collections::{HashMap, HashSet};
+use std::mem;
+
 use html5ever::buffer_queue::BufferQueue;
 use html5ever::tokenizer::states::RawKind;
 use html5ever::tokenizer::{
     Tag, TagKind, Token, TokenSink, TokenSinkResult, Tokenizer as HtmlTokenizer, TokenizerResult,
 };
 use html5ever::{local_name, Attribute, LocalName};
+use http::header::{HeaderName, HeaderValue};
+use ipc_channel::ipc::{self, IpcReceiver, IpcSender};
 use js::jsapi::JSTracer;
-use msg::constellation_msg::PipelineId;
-use net_traits::request::{CorsSettings, CredentialsMode, ParserMetadata, Referrer};
+use msg::constellation_msg::{PipelineId, TEST_PIPELINE_ID};
+use net_traits::pub_domains::reg_suffix;
+use net_traits::request::{
+    CorsSettings, CredentialsMode, Destination, ParserMetadata, Referrer, RequestBuilder,
+    RequestMode,
+};
 use net_traits::{CoreResourceMsg, FetchChannels, IpcSend, ReferrerPolicy, ResourceThreads};
+use serde_json::Value;
+use servo_config::pref;
 use servo_url::{ImmutableOrigin, ServoUrl};
+use url::Host;
 
 use crate::dom::bindings::reflector::DomObject;
 use crate::dom::bindings::trace::{CustomTraceable, JSTraceable};
 use crate::dom::document::{determine_policy_for_token, Document};
-use crate::dom::htmlimageelement::{image_fetch_request, FromPictureOrSrcSet};
+use crate::dom::htmlimageelement::{
+    image_fetch_request, parse_a_srcset_attribute, Descriptor, FromPictureOrSrcSet, ImageSource,
+};
 use crate::dom::htmlscriptelement::script_fetch_request;
+use crate::dom::window::Window;
+use crate::fetch::create_a_potential_cors_request;
 use crate::script_module::ScriptFetchOptions;
 use crate::stylesheet_loader::stylesheet_fetch_request;
 
@@ -43,14 +59,63 @@ impl Tokenizer {
             pipeline_id: document.global().pipeline_id(),
             base_url: None,
             document_url: document.url(),
+            fallback_base_url: document.fallback_base_url(),
             referrer: document.global().get_referrer(),
             referrer_policy: document.get_referrer_policy(),
             resource_threads: document.loader().resource_threads().clone(),
+            viewport: SrcsetViewport::for_window(&document.window()),
             // Initially we set prefetching to false, and only set it
             // true after the first script tag, since that is what will
             // block the main parser.
             prefetching: false,
+            resource_urls: if pref!(dom.servoparser.collect_resource_urls.enabled) {
+                Some(vec![])
+            } else {
+                None
+            },
+            next_navigation_requests: vec![],
+            speculation_rules: None,
+            fetched_urls: HashSet::new(),
+            budget: PrefetchBudget::new(pref!(dom.servoparser.max_prefetch_requests)),
+            pending_fetches: PendingPrefetches::default(),
         };
+        Tokenizer::with_sink(sink)
+    }
+
+    /// A tokenizer for a document at `document_url` that has no browsing
+    /// context, sending at most `max_prefetch_requests` requests to
+    /// `resource_threads`. This is what unit tests use, as they can't create a
+    /// `Document`.
+    pub fn for_url(
+        document_url: ServoUrl,
+        resource_threads: ResourceThreads,
+        max_prefetch_requests: i64,
+    ) -> Self {
+        let sink = PrefetchSink {
+            origin: document_url.origin(),
+            pipeline_id: TEST_PIPELINE_ID,
+            base_url: None,
+            fallback_base_url: document_url.clone(),
+            document_url,
+            referrer: Referrer::NoReferrer,
+            referrer_policy: None,
+            resource_threads,
+            viewport: SrcsetViewport {
+                width: 0.,
+                device_pixel_ratio: 1.,
+            },
+            prefetching: false,
+            resource_urls: None,
+            next_navigation_requests: vec![],
+            speculation_rules: None,
+            fetched_urls: HashSet::new(),
+            budget: PrefetchBudget::new(max_prefetch_requests),
+            pending_fetches: PendingPrefetches::default(),
+        };
+        Tokenizer::with_sink(sink)
+    }
+
+    fn with_sink(sink: PrefetchSink) -> Self {
         let options = Default::default();
         let inner = HtmlTokenizer::new(sink, options);
         Tokenizer { inner }
@@ -59,6 +124,66 @@ impl Tokenizer {
     pub fn feed(&mut self, input: &mut BufferQueue) {
         while let TokenizerResult::Script(PrefetchHandle) = self.inner.feed(input) {}
     }
+
+    /// Fetches the resources preloaded by the given links from the `Link`
+    /// headers of the response.
+    pub fn preload_link_headers(&mut self, links: &[LinkHeaderValue]) {
+        for link in links {
+            self.inner.sink.preload_link_header(link);
+        }
+    }
+
+    /// Fetches the resources linked to with `rel=prefetch` so far, which may be
+    /// needed by the next navigation, into the HTTP cache.
+    pub fn fetch_next_navigation_resources(&mut self) {
+        let sink = &mut self.inner.sink;
+        for request in mem::take(&mut sink.next_navigation_requests) {
+            sink.fetch(request);
+        }
+    }
+
+    pub fn resource_urls(&self) -> &[(ServoUrl, Destination)] {
+        self.inner.sink.resource_urls.as_deref().unwrap_or(&[])
+    }
+
+    /// The URLs this tokenizer requested so far.
+    pub fn fetched_urls(&self) -> &HashSet<ServoUrl> {
+        &self.inner.sink.fetched_urls
+    }
+
+    /// Marks the prefetched `url` as requested by the document, see
+    /// [`PendingPrefetches::adopt`].
+    pub fn adopt_url(&mut self, url: &ServoUrl) {
+        self.inner.sink.pending_fetches.adopt(url);
+    }
+
+    /// Whether the request this tokenizer sent for `url` is still tracked, see
+    /// [`PendingPrefetches::is_tracked`].
+    pub fn is_tracking_fetch(&mut self, url: &ServoUrl) -> bool {
+        self.inner.sink.pending_fetches.is_tracked(url)
+    }
+
+    /// Cancels the requests this tokenizer sent that the document didn't
+    /// request itself.
+    pub fn cancel_pending_fetches(&mut self) {
+        self.inner.sink.pending_fetches.cancel();
+    }
+
+    /// Keeps this tokenizer from requesting any of `urls`, which were already
+    /// loaded.
+    pub fn skip_urls(&mut self, urls: impl IntoIterator<Item = ServoUrl>) {
+        self.inner.sink.fetched_urls.extend(urls);
+    }
+
+    /// Scans content that was already parsed, e.g. because the document had
+    /// no browsing context to prefetch for at the time. The parser is past
+    /// all of it, so its resources are fetched from the start instead of
+    /// after the first script.
+    pub fn rescan(&mut self, input: &mut BufferQueue) {
+        self.inner.sink.prefetching = true;
+        self.feed(input);
+        self.inner.end();
+    }
 }
 
 #[derive(JSTraceable)]
@@ -71,13 +196,40 @@ struct PrefetchSink {
     document_url: ServoUrl,
     #[no_trace]
     base_url: Option<ServoUrl>,
+    /// The URL resolved against without a `base` element, which differs from
+    /// the document URL for `srcdoc` documents and responses with a
+    /// `Content-Location` header.
+    #[no_trace]
+    fallback_base_url: ServoUrl,
     #[no_trace]
     referrer: Referrer,
     #[no_trace]
     referrer_policy: Option<ReferrerPolicy>,
     #[no_trace]
     resource_threads: ResourceThreads,
+    #[no_trace]
+    viewport: SrcsetViewport,
     prefetching: bool,
+    /// The URLs of the resources found so far, if they are being collected.
+    #[no_trace]
+    resource_urls: Option<Vec<(ServoUrl, Destination)>>,
+    /// The requests for `rel=prefetch` links, which are sent once the document
+    /// is parsed.
+    #[no_trace]
+    next_navigation_requests: Vec<RequestBuilder>,
+    /// The text of the `<script type=speculationrules>` being tokenized, if
+    /// any.
+    speculation_rules: Option<String>,
+    /// The URLs requested so far, or already loaded, which aren't requested
+    /// again.
+    #[no_trace]
+    fetched_urls: HashSet<ServoUrl>,
+    /// How many more requests may be sent for this parse.
+    #[no_trace]
+    budget: PrefetchBudget,
+    /// The requests sent that may be cancelled if the parser is aborted.
+    #[no_trace]
+    pending_fetches: PendingPrefetches,
 }
 
 /// The prefetch tokenizer produces trivial results
@@ -92,39 +244,56 @@ impl TokenSink for PrefetchSink {
     ) -> TokenSinkResult<PrefetchHandle> {
         let tag = match token {
             Token::TagToken(ref tag) => tag,
+            Token::CharacterTokens(ref text) => {
+                if let Some(ref mut rules) = self.speculation_rules {
+                    rules.push_str(text);
+                }
+                return TokenSinkResult::Continue;
+            },
             _ => return TokenSinkResult::Continue,
         };
         match (tag.kind, &tag.name) {
-            (TagKind::StartTag, &local_name!("script")) if self.prefetching => {
+            (TagKind::StartTag, &local_name!("script")) if self.is_speculation_rules(tag) => {
+                // These never block the parser, and their `src` is ignored.
+                self.speculation_rules = Some(String::new());
+                TokenSinkResult::RawData(RawKind::ScriptData)
+            },
+            (TagKind::StartTag, &local_name!("script")) if self.is_scanning() => {
                 if let Some(url) = self.get_url(tag, local_name!("src")) {
                     debug!("Prefetch script {}", url);
-                    let cors_setting = self.get_cors_settings(tag, local_name!("crossorigin"));
-                    let integrity_metadata = self
-                        .get_attr(tag, local_name!("integrity"))
-                        .map(|attr| String::from(&attr.value))
-                        .unwrap_or_default();
-                    let request = script_fetch_request(
+                    let crossorigin = self.get_attr(tag, local_name!("crossorigin"));
+                    let integrity = self.get_attr(tag, local_name!("integrity"));
+                    let request = speculative_script_request(
                         url,
-                        cors_setting,
+                        crossorigin.map(|attr| &*attr.value),
+                        integrity.map(|attr| &*attr.value),
                         self.origin.clone(),
                         self.pipeline_id,
-                        ScriptFetchOptions {
-                            referrer: self.referrer.clone(),
-                            referrer_policy: self.referrer_policy,
-                            integrity_metadata,
-                            cryptographic_nonce: String::new(),
-                            credentials_mode: CredentialsMode::CredentialsSameOrigin,
-                            parser_metadata: ParserMetadata::ParserInserted,
-                        },
+                        self.referrer.clone(),
+                        self.referrer_policy,
                     );
-                    let _ = self
-                        .resource_threads
-                        .send(CoreResourceMsg::Fetch(request, FetchChannels::Prefetch));
+                    self.prefetch(request);
                 }
                 TokenSinkResult::RawData(RawKind::ScriptData)
             },
-            (TagKind::StartTag, &local_name!("img")) if self.prefetching => {
-                if let Some(url) = self.get_url(tag, local_name!("src")) {
+            (TagKind::StartTag, &local_name!("img")) if self.is_scanning() => {
+                let from_srcset = self
+                    .get_attr(tag, local_name!("srcset"))
+                    .and_then(|srcset| {
+                        select_source_from_srcset(
+                            &parse_a_srcset_attribute(&srcset.value),
+                            self.viewport,
+                            self.base(),
+                        )
+                    });
+                let (url, from_picture_or_srcset) = match from_srcset {
+                    Some(url) => (Some(url), FromPictureOrSrcSet::Yes),
+                    None => (
+                        self.get_url(tag, local_name!("src")),
+                        FromPictureOrSrcSet::No,
+                    ),
+                };
+                if let Some(url) = url {
                     debug!("Prefetch {} {}", tag.name, url);
                     let request = image_fetch_request(
                         url,
@@ -133,15 +302,13 @@ impl TokenSink for PrefetchSink {
                         self.pipeline_id,
                         self.get_cors_settings(tag, local_name!("crossorigin")),
                         self.get_referrer_policy(tag, local_name!("referrerpolicy")),
-                        FromPictureOrSrcSet::No,
+                        from_picture_or_srcset,
                     );
-                    let _ = self
-                        .resource_threads
-                        .send(CoreResourceMsg::Fetch(request, FetchChannels::Prefetch));
+                    self.prefetch(request);
                 }
                 TokenSinkResult::Continue
             },
-            (TagKind::StartTag, &local_name!("link")) if self.prefetching => {
+            (TagKind::StartTag, &local_name!("link")) if self.is_scanning() => {
                 if let Some(rel) = self.get_attr(tag, local_name!("rel")) {
                     if rel.value.eq_ignore_ascii_case("stylesheet") {
                         if let Some(url) = self.get_url(tag, local_name!("href")) {
@@ -163,9 +330,45 @@ impl TokenSink for PrefetchSink {
                                 referrer_policy,
                                 integrity_metadata,
                             );
-                            let _ = self
-                                .resource_threads
-                                .send(CoreResourceMsg::Fetch(request, FetchChannels::Prefetch));
+                            self.prefetch(request);
+                        }
+                    } else if rel.value.eq_ignore_ascii_case("preload") {
+                        let destination = self
+                            .get_attr(tag, LocalName::from("as"))
+                            .and_then(|attr| preload_destination(&attr.value));
+                        if let (Some(destination), Some(url)) =
+                            (destination, self.get_url(tag, local_name!("href")))
+                        {
+                            debug!("Prefetch {} {} as {:?}", tag.name, url, destination);
+                            let cors_setting = preload_cors_setting(
+                                destination,
+                                self.get_cors_settings(tag, local_name!("crossorigin")),
+                            );
+                            let referrer_policy =
+                                self.get_referrer_policy(tag, local_name!("referrerpolicy"));
+                            let integrity_metadata = self
+                                .get_attr(tag, local_name!("integrity"))
+                                .map(|attr| String::from(&attr.value))
+                                .unwrap_or_default();
+                            let request = self.preload_request(
+                                url,
+                                destination,
+                                cors_setting,
+                                referrer_policy,
+                                integrity_metadata,
+                            );
+                            self.prefetch(request);
+                        }
+                    } else if rel.value.eq_ignore_ascii_case("prefetch") {
+                        if let Some(url) = self.get_url(tag, local_name!("href")) {
+                            debug!("Prefetch {} {} for the next navigation", tag.name, url);
+                            let cors_setting =
+                                self.get_cors_settings(tag, local_name!("crossorigin"));
+                            let referrer_policy =
+                                self.get_referrer_policy(tag, local_name!("referrerpolicy"));
+                            let request =
+                                self.next_navigation_request(url, cors_setting, referrer_policy);
+                            self.prefetch_for_next_navigation(request);
                         }
                     }
                 }
@@ -174,6 +377,14 @@ impl TokenSink for PrefetchSink {
             (TagKind::StartTag, &local_name!("script")) => {
                 TokenSinkResult::RawData(RawKind::ScriptData)
             },
+            (TagKind::EndTag, &local_name!("script")) if self.speculation_rules.is_some() => {
+                let text = self.speculation_rules.take().unwrap_or_default();
+                match parse_speculation_rules(&text, self.base()) {
+                    Some(rules) => self.prefetch_speculation_rules(rules),
+                    None => debug!("Ignoring invalid speculation rules"),
+                }
+                TokenSinkResult::Continue
+            },
             (TagKind::EndTag, &local_name!("script")) => {
                 // After the first script tag, the main parser is blocked, so it's worth prefetching.
                 self.prefetching = true;
@@ -198,10 +409,167 @@ impl PrefetchSink {
         tag.attrs.iter().find(|attr| attr.name.local == name)
     }
 
+    /// Whether `tag` starts a script whose type is `speculationrules`.
+    /// <https://html.spec.whatwg.org/multipage/#prepare-the-script-element>
+    fn is_speculation_rules(&self, tag: &Tag) -> bool {
+        self.get_attr(tag, local_name!("type"))
+            .map_or(false, |attr| {
+                attr.value
+                    .trim_matches(|c: char| c.is_ascii_whitespace())
+                    .eq_ignore_ascii_case("speculationrules")
+            })
+    }
+
+    /// Queues the URLs listed by speculation rules to be fetched once the
+    /// document is parsed, like `rel=prefetch` links. Prerendering isn't
+    /// supported, so the candidates of `prerender` rules are only prefetched.
+    /// Like the rules themselves, which the parser doesn't handle, these are
+    /// queued whether or not the parser got blocked.
+    fn prefetch_speculation_rules(&mut self, rules: SpeculationRules) {
+        for rule in rules.prefetch.into_iter().chain(rules.prerender) {
+            let referrer_policy = rule.referrer_policy.or(self.referrer_policy);
+            for url in rule.urls {
+                debug!("Prefetch {} from speculation rules", url);
+                let request = self.next_navigation_request(url, None, referrer_policy);
+                if let Some(ref mut resource_urls) = self.resource_urls {
+                    resource_urls.push((request.url.clone(), request.destination));
+                }
+                self.next_navigation_requests.push(request);
+            }
+        }
+    }
+
+    /// Whether start tags are worth looking at, either to prefetch the
+    /// resources they refer to or to collect their URLs.
+    fn is_scanning(&self) -> bool {
+        self.prefetching || self.resource_urls.is_some()
+    }
+
+    fn prefetch(&mut self, request: RequestBuilder) {
+        if let Some(ref mut resource_urls) = self.resource_urls {
+            resource_urls.push((request.url.clone(), request.destination));
+        }
+        if self.prefetching {
+            self.fetch(request);
+        }
+    }
+
+    /// Resources for the next navigation are less important than those of the
+    /// current document, so they are only fetched once it is parsed, see
+    /// `Tokenizer::fetch_next_navigation_resources`.
+    fn prefetch_for_next_navigation(&mut self, request: RequestBuilder) {
+        if let Some(ref mut resource_urls) = self.resource_urls {
+            resource_urls.push((request.url.clone(), request.destination));
+        }
+        if self.prefetching {
+            self.next_navigation_requests.push(request);
+        }
+    }
+
+    fn next_navigation_request(
+        &self,
+        url: ServoUrl,
+        cors_setting: Option<CorsSettings>,
+        referrer_policy: Option<ReferrerPolicy>,
+    ) -> RequestBuilder {
+        next_navigation_prefetch_request(
+            url,
+            cors_setting,
+            self.origin.clone(),
+            self.referrer.clone(),
+        )
+        .pipeline_id(Some(self.pipeline_id))
+        .referrer_policy(referrer_policy)
+    }
+
+    /// Sends `request`, unless the budget of prefetch requests for this parse
+    /// is spent, in which case the resource is left for the parser to load.
+    fn fetch(&mut self, request: RequestBuilder) {
+        if self.fetched_urls.contains(&request.url) || !self.budget.spend() {
+            return;
+        }
+        self.fetched_urls.insert(request.url.clone());
+        let (cancel_chan, done_chan) = match self.pending_fetches.track(request.url.clone()) {
+            Some((cancel_chan, done_chan)) => (Some(cancel_chan), Some(done_chan)),
+            None => (None, None),
+        };
+        let request = with_fetch_metadata(request);
+        let _ = self.resource_threads.send(CoreResourceMsg::Fetch(
+            request,
+            FetchChannels::Prefetch(cancel_chan, done_chan),
+        ));
+    }
+
+    fn preload_request(
+        &self,
+        url: ServoUrl,
+        destination: Destination,
+        cors_setting: Option<CorsSettings>,
+        referrer_policy: Option<ReferrerPolicy>,
+        integrity_metadata: String,
+    ) -> RequestBuilder {
+        create_a_potential_cors_request(url, destination, cors_setting, None, self.referrer.clone())
+            .origin(self.origin.clone())
+            .pipeline_id(Some(self.pipeline_id))
+            .referrer_policy(referrer_policy)
+            .integrity_metadata(integrity_metadata)
+    }
+
+    /// Fetches the resource a link from a `Link` header preloads, if any.
+    /// Unlike resources found in the markup, these are fetched regardless of
+    /// whether the parser got blocked, since they are known before any of
+    /// the markup is parsed.
+    fn preload_link_header(&mut self, link: &LinkHeaderValue) {
+        let url = match ServoUrl::parse_with_base(Some(&self.document_url), &link.target) {
+            Ok(url) => url,
+            Err(_) => return,
+        };
+        let cors_setting = link.param("crossorigin").map(parse_cors_settings);
+        let referrer_policy = link
+            .param("referrerpolicy")
+            .and_then(determine_policy_for_token)
+            .or(self.referrer_policy);
+        let integrity_metadata = link.param("integrity").unwrap_or_default().to_owned();
+        let (destination, cors_setting) = if is_link_preload(link) {
+            match link_preload(link) {
+                Some(preload) => preload,
+                None => return,
+            }
+        } else if link.has_rel("prefetch") {
+            debug!("Prefetch {} from Link header for the next navigation", url);
+            let request = self.next_navigation_request(url, cors_setting, referrer_policy);
+            if let Some(ref mut resource_urls) = self.resource_urls {
+                resource_urls.push((request.url.clone(), request.destination));
+            }
+            self.next_navigation_requests.push(request);
+            return;
+        } else {
+            if link.has_rel("preconnect") {
+                debug!("Ignoring Link header preconnect to {}", url);
+            }
+            return;
+        };
+        debug!("Preload {} as {:?} from Link header", url, destination);
+        let request = self.preload_request(
+            url,
+            destination,
+            cors_setting,
+            referrer_policy,
+            integrity_metadata,
+        );
+        if let Some(ref mut resource_urls) = self.resource_urls {
+            resource_urls.push((request.url.clone(), request.destination));
+        }
+        self.fetch(request);
+    }
+
+    fn base(&self) -> &ServoUrl {
+        self.base_url.as_ref().unwrap_or(&self.fallback_base_url)
+    }
+
     fn get_url(&self, tag: &Tag, name: LocalName) -> Option<ServoUrl> {
         let attr = self.get_attr(tag, name)?;
-        let base = self.base_url.as_ref().unwrap_or(&self.document_url);
-        ServoUrl::parse_with_base(Some(base), &attr.value).ok()
+        ServoUrl::parse_with_base(Some(self.base()), &attr.value).ok()
     }
 
     fn get_referrer_policy(&self, tag: &Tag, name: LocalName) -> Option<ReferrerPolicy> {
@@ -211,13 +579,606 @@ impl PrefetchSink {
     }
 
     fn get_cors_settings(&self, tag: &Tag, name: LocalName) -> Option<CorsSettings> {
-        let crossorigin = self.get_attr(tag, name)?;
-        if crossorigin.value.eq_ignore_ascii_case("anonymous") {
-            Some(CorsSettings::Anonymous)
-        } else if crossorigin.value.eq_ignore_ascii_case("use-credentials") {
-            Some(CorsSettings::UseCredentials)
-        } else {
-            None
+        self.get_attr(tag, name)
+            .map(|crossorigin| parse_cors_settings(&crossorigin.value))
+    }
+}
+
+/// Parses the value of a CORS settings attribute, whose missing and invalid
+/// value defaults are both the anonymous state, as `cors_setting_for_element`
+/// does for the elements themselves.
+/// <https://html.spec.whatwg.org/multipage/#cors-settings-attribute>
+fn parse_cors_settings(value: &str) -> CorsSettings {
+    if value.eq_ignore_ascii_case("use-credentials") {
+        CorsSettings::UseCredentials
+    } else {
+        CorsSettings::Anonymous
+    }
+}
+
+/// The request for a speculative fetch of a classic `<script src>`, built from
+/// the values of its `crossorigin` and `integrity` attributes exactly as the
+/// script element itself would once it's inserted, so that the eventual load
+/// matches the prefetched response rather than fetching the script again.
+pub fn speculative_script_request(
+    url: ServoUrl,
+    crossorigin: Option<&str>,
+    integrity: Option<&str>,
+    origin: ImmutableOrigin,
+    pipeline_id: PipelineId,
+    referrer: Referrer,
+    referrer_policy: Option<ReferrerPolicy>,
+) -> RequestBuilder {
+    script_fetch_request(
+        url,
+        crossorigin.map(parse_cors_settings),
+        origin,
+        pipeline_id,
+        ScriptFetchOptions {
+            referrer,
+            referrer_policy,
+            integrity_metadata: integrity.map(String::from).unwrap_or_default(),
+            cryptographic_nonce: String::new(),
+            credentials_mode: CredentialsMode::CredentialsSameOrigin,
+            parser_metadata: ParserMetadata::ParserInserted,
+        },
+    )
+}
+
+/// A link from the value of a `Link` HTTP header.
+/// <https://httpwg.org/specs/rfc8288.html#header>
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinkHeaderValue {
+    /// The target of the link, as written between the angle brackets.
+    pub target: String,
+    /// The parameters of the link in order, with lowercase names, and with
+    /// quoted values unescaped.
+    pub params: Vec<(String, String)>,
+}
+
+impl LinkHeaderValue {
+    /// The value of the first parameter called `name`, the others are ignored.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| &**value)
+    }
+
+    /// Whether `rel` is one of the relation types of the link.
+    pub fn has_rel(&self, rel: &str) -> bool {
+        self.param("rel").map_or(false, |rels| {
+            rels.split_ascii_whitespace()
+                .any(|value| value.eq_ignore_ascii_case(rel))
+        })
+    }
+}
+
+/// Parses the comma-separated links of a `Link` header value. Links that
+/// can't be parsed are skipped.
+pub fn parse_link_header(value: &str) -> Vec<LinkHeaderValue> {
+    let mut links = vec![];
+    let mut input = value;
+    loop {
+        input = input.trim_start_matches(|c: char| c == ',' || is_link_whitespace(c));
+        if input.is_empty() {
+            return links;
+        }
+        match parse_link_value(&mut input) {
+            Some(link) => links.push(link),
+            None => {
+                input = match input.find(',') {
+                    Some(comma) => &input[comma..],
+                    None => "",
+                }
+            },
+        }
+    }
+}
+
+fn is_link_whitespace(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+fn is_link_token(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+fn parse_link_value(input: &mut &str) -> Option<LinkHeaderValue> {
+    let target = input.strip_prefix('<')?;
+    let end = target.find('>')?;
+    *input = &target[end + 1..];
+    let target = target[..end].trim().to_owned();
+    let mut params = vec![];
+    loop {
+        *input = input.trim_start_matches(is_link_whitespace);
+        if input.is_empty() || input.starts_with(',') {
+            return Some(LinkHeaderValue { target, params });
+        }
+        *input = input
+            .strip_prefix(';')?
+            .trim_start_matches(is_link_whitespace);
+        let name_len = input
+            .find(|c: char| !is_link_token(c))
+            .unwrap_or(input.len());
+        if name_len == 0 {
+            continue;
+        }
+        let name = input[..name_len].to_ascii_lowercase();
+        *input = input[name_len..].trim_start_matches(is_link_whitespace);
+        let value = match input.strip_prefix('=') {
+            Some(value) => {
+                *input = value.trim_start_matches(is_link_whitespace);
+                parse_link_param_value(input)?
+            },
+            None => String::new(),
+        };
+        params.push((name, value));
+    }
+}
+
+fn parse_link_param_value(input: &mut &str) -> Option<String> {
+    let quoted = match input.strip_prefix('"') {
+        Some(quoted) => quoted,
+        None => {
+            let len = input
+                .find(|c: char| c == ';' || c == ',' || is_link_whitespace(c))
+                .unwrap_or(input.len());
+            let value = input[..len].to_owned();
+            *input = &input[len..];
+            return Some(value);
+        },
+    };
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                *input = &quoted[i + 1..];
+                return Some(value);
+            },
+            '\\' => value.push(chars.next()?.1),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// The list rules of a speculation rule set.
+/// <https://wicg.github.io/nav-speculation/speculation-rules.html#speculation-rule-set>
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpeculationRules {
+    pub prefetch: Vec<SpeculationRule>,
+    pub prerender: Vec<SpeculationRule>,
+}
+
+/// A rule listing URLs that are likely to be navigated to.
+/// <https://wicg.github.io/nav-speculation/speculation-rules.html#speculation-rule>
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpeculationRule {
+    /// The `http(s)` URLs of the rule, resolved against the base URL of the
+    /// document. Others can't be prefetched, and are dropped.
+    pub urls: Vec<ServoUrl>,
+    pub referrer_policy: Option<ReferrerPolicy>,
+}
+
+/// Parses the JSON text of a `<script type=speculationrules>` into its list
+/// rules, resolving their URLs against `base`. Returns `None` if the text
+/// isn't a JSON object, in which case the whole rule set is ignored. Rules
+/// that can't be parsed are skipped, as are document rules, which would match
+/// the links of the document, and rules with requirements, since none of them
+/// can be met.
+/// <https://wicg.github.io/nav-speculation/speculation-rules.html#parse-a-speculation-rule-set-string>
+pub fn parse_speculation_rules(text: &str, base: &ServoUrl) -> Option<SpeculationRules> {
+    let value: Value = serde_json::from_str(text).ok()?;
+    let object = value.as_object()?;
+    let rules = |key: &str| {
+        object
+            .get(key)
+            .and_then(Value::as_array)
+            .map_or(vec![], |rules| {
+                rules
+                    .iter()
+                    .filter_map(|rule| parse_speculation_rule(rule, base))
+                    .collect()
+            })
+    };
+    Some(SpeculationRules {
+        prefetch: rules("prefetch"),
+        prerender: rules("prerender"),
+    })
+}
+
+/// <https://wicg.github.io/nav-speculation/speculation-rules.html#parse-a-speculation-rule>
+fn parse_speculation_rule(rule: &Value, base: &ServoUrl) -> Option<SpeculationRule> {
+    let rule = rule.as_object()?;
+    match rule.get("source") {
+        None => {},
+        Some(source) if source.as_str() == Some("list") => {},
+        Some(_) => return None,
+    }
+    if rule.contains_key("where") {
+        return None;
+    }
+    if let Some(requires) = rule.get("requires") {
+        if !requires.as_array()?.is_empty() {
+            return None;
+        }
+    }
+    let referrer_policy = match rule.get("referrer_policy") {
+        None => None,
+        Some(policy) => match policy.as_str()? {
+            "" => None,
+            policy => Some(determine_policy_for_token(policy)?),
+        },
+    };
+    let urls = rule
+        .get("urls")?
+        .as_array()?
+        .iter()
+        .map(|url| ServoUrl::parse_with_base(Some(base), url.as_str()?).ok())
+        .collect::<Option<Vec<_>>>()?;
+    Some(SpeculationRule {
+        urls: urls
+            .into_iter()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .collect(),
+        referrer_policy,
+    })
+}
+
+/// A request for a resource linked to with `rel=prefetch`, which is likely to
+/// be needed by a future navigation rather than the current document. Unlike
+/// preloads, these have no destination, and only populate the HTTP cache, as
+/// the `Sec-Purpose` header tells the server.
+/// <https://html.spec.whatwg.org/multipage/#link-type-prefetch>
+pub fn next_navigation_prefetch_request(
+    url: ServoUrl,
+    cors_setting: Option<CorsSettings>,
+    origin: ImmutableOrigin,
+    referrer: Referrer,
+) -> RequestBuilder {
+    let mut request =
+        create_a_potential_cors_request(url, Destination::None, cors_setting, None, referrer)
+            .origin(origin);
+    request.headers.insert(
+        HeaderName::from_static("sec-purpose"),
+        HeaderValue::from_static("prefetch"),
+    );
+    request
+}
+
+/// Adds the `Sec-Fetch-Dest`, `Sec-Fetch-Mode` and `Sec-Fetch-Site` headers
+/// to a speculative request, from its destination, mode and origin, so that
+/// servers which pick a response from them give the same response to the
+/// prefetch as to the load of the resource itself. `Sec-Fetch-User` is only
+/// sent for navigations, which are never prefetched. Nothing is added if the
+/// URL isn't potentially trustworthy.
+/// <https://w3c.github.io/webappsec-fetch-metadata/#fetch-integration>
+pub fn with_fetch_metadata(mut request: RequestBuilder) -> RequestBuilder {
+    if !request.url.is_potentially_trustworthy() {
+        return request;
+    }
+    let url_origin = request.url.origin();
+    let site = if request.origin == url_origin {
+        "same-origin"
+    } else if site_of(&request.origin).is_some() && site_of(&request.origin) == site_of(&url_origin)
+    {
+        "same-site"
+    } else {
+        "cross-site"
+    };
+    let headers = [
+        (
+            "sec-fetch-dest",
+            fetch_metadata_destination(request.destination),
+        ),
+        ("sec-fetch-mode", fetch_metadata_mode(&request.mode)),
+        ("sec-fetch-site", site),
+    ];
+    for (name, value) in headers {
+        request.headers.insert(
+            HeaderName::from_static(name),
+            HeaderValue::from_static(value),
+        );
+    }
+    request
+}
+
+/// The scheme and registrable domain of `origin`, or its host if it is an IP
+/// address, which two origins share if they are same site.
+/// <https://html.spec.whatwg.org/multipage/#same-site>
+fn site_of(origin: &ImmutableOrigin) -> Option<(&str, Host)> {
+    match *origin {
+        ImmutableOrigin::Tuple(ref scheme, Host::Domain(ref domain), _) => Some((
+            scheme.as_str(),
+            Host::Domain(String::from(reg_suffix(domain))),
+        )),
+        ImmutableOrigin::Tuple(ref scheme, ref host, _) => Some((scheme.as_str(), host.clone())),
+        ImmutableOrigin::Opaque(_) => None,
+    }
+}
+
+/// <https://w3c.github.io/webappsec-fetch-metadata/#abstract-opdef-set-dest>
+fn fetch_metadata_destination(destination: Destination) -> &'static str {
+    match destination {
+        Destination::None => "empty",
+        Destination::Audio => "audio",
+        Destination::AudioWorklet => "audioworklet",
+        Destination::Document => "document",
+        Destination::Embed => "embed",
+        Destination::Font => "font",
+        Destination::Image => "image",
+        Destination::Manifest => "manifest",
+        Destination::Object => "object",
+        Destination::PaintWorklet => "paintworklet",
+        Destination::Report => "report",
+        Destination::Script => "script",
+        Destination::ServiceWorker => "serviceworker",
+        Destination::SharedWorker => "sharedworker",
+        Destination::Style => "style",
+        Destination::Track => "track",
+        Destination::Video => "video",
+        Destination::Worker => "worker",
+        Destination::Xslt => "xslt",
+    }
+}
+
+/// <https://w3c.github.io/webappsec-fetch-metadata/#abstract-opdef-set-mode>
+fn fetch_metadata_mode(mode: &RequestMode) -> &'static str {
+    match *mode {
+        RequestMode::Navigate => "navigate",
+        RequestMode::SameOrigin => "same-origin",
+        RequestMode::NoCors => "no-cors",
+        RequestMode::CorsMode => "cors",
+        RequestMode::WebSocket { .. } => "websocket",
+    }
+}
+
+/// Whether a `Link` header is a `rel=preload` or `rel=modulepreload` one.
+fn is_link_preload(link: &LinkHeaderValue) -> bool {
+    link.has_rel("preload") || link.has_rel("modulepreload")
+}
+
+/// The destination and CORS settings of the request for the resource that a
+/// `rel=preload` or `rel=modulepreload` `Link` header preloads, if it's one
+/// we know how to prefetch.
+pub fn link_preload(link: &LinkHeaderValue) -> Option<(Destination, Option<CorsSettings>)> {
+    let cors_setting = link.param("crossorigin").map(parse_cors_settings);
+    if link.has_rel("preload") {
+        let destination = link.param("as").and_then(preload_destination)?;
+        return Some((destination, preload_cors_setting(destination, cors_setting)));
+    }
+    if !link.has_rel("modulepreload") {
+        return None;
+    }
+    // https://html.spec.whatwg.org/multipage/#link-type-modulepreload
+    // Module scripts are always fetched in CORS mode.
+    match link.param("as") {
+        None => {},
+        Some(as_) if as_.eq_ignore_ascii_case("script") => {},
+        Some(_) => return None,
+    }
+    Some((
+        Destination::Script,
+        cors_setting.or(Some(CorsSettings::Anonymous)),
+    ))
+}
+
+/// The requests for the resources preloaded by the `Link` headers of a `103
+/// Early Hints` response to the navigation to `url`. These are known before
+/// the final response, so there is no document to make them for yet: they're
+/// made for the origin of `url`, with `url` as the referrer. Preconnects are
+/// ignored, as they are in the final response, and so are prefetches, which
+/// are for the next navigation.
+/// <https://html.spec.whatwg.org/multipage/#early-hints>
+pub fn early_hint_requests(
+    links: &[LinkHeaderValue],
+    url: &ServoUrl,
+    pipeline_id: PipelineId,
+) -> Vec<RequestBuilder> {
+    links
+        .iter()
+        .filter_map(|link| {
+            let target = ServoUrl::parse_with_base(Some(url), &link.target).ok()?;
+            if link.has_rel("preconnect") {
+                debug!("Ignoring early hint preconnect to {}", target);
+            }
+            let (destination, cors_setting) = link_preload(link)?;
+            debug!("Preload {} as {:?} from early hints", target, destination);
+            let referrer_policy = link
+                .param("referrerpolicy")
+                .and_then(determine_policy_for_token);
+            let integrity_metadata = link.param("integrity").unwrap_or_default().to_owned();
+            let request = create_a_potential_cors_request(
+                target,
+                destination,
+                cors_setting,
+                None,
+                Referrer::ReferrerUrl(url.clone()),
+            )
+            .origin(url.origin())
+            .pipeline_id(Some(pipeline_id))
+            .referrer_policy(referrer_policy)
+            .integrity_metadata(integrity_metadata);
+            Some(with_fetch_metadata(request))
+        })
+        .collect()
+}
+
+/// Maps the value of a `<link rel=preload>` element's `as` attribute to the
+/// destination of the request, for the destinations we know how to prefetch.
+/// <https://html.spec.whatwg.org/multipage/#match-preload-type>
+pub fn preload_destination(as_: &str) -> Option<Destination> {
+    if as_.eq_ignore_ascii_case("font") {
+        Some(Destination::Font)
+    } else if as_.eq_ignore_ascii_case("style") {
+        Some(Destination::Style)
+    } else {
+        None
+    }
+}
+
+/// The CORS settings to use when preloading a resource with the given
+/// destination. Fonts are always fetched in CORS mode, even in the absence
+/// of a `crossorigin` attribute, whereas other destinations only use CORS
+/// when the author asks for it.
+/// <https://drafts.csswg.org/css-fonts/#font-fetching-requirements>
+pub fn preload_cors_setting(
+    destination: Destination,
+    cors_setting: Option<CorsSettings>,
+) -> Option<CorsSettings> {
+    match destination {
+        Destination::Font => cors_setting.or(Some(CorsSettings::Anonymous)),
+        _ => cors_setting,
+    }
+}
+
+/// The number of requests the prefetcher may still send while parsing a
+/// document, see `dom.servoparser.max_prefetch_requests`. This keeps a page
+/// listing a huge number of resources from flooding the network.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PrefetchBudget {
+    /// `None` if there is no cap.
+    remaining: Option<usize>,
+}
+
+impl PrefetchBudget {
+    /// A budget of `max` requests, or an unlimited one if `max` isn't positive.
+    pub fn new(max: i64) -> PrefetchBudget {
+        PrefetchBudget {
+            remaining: if max > 0 { Some(max as usize) } else { None },
+        }
+    }
+
+    /// Spends one request from the budget, returning false if none are left.
+    pub fn spend(&mut self) -> bool {
+        match self.remaining {
+            None => true,
+            Some(0) => false,
+            Some(ref mut remaining) => {
+                *remaining -= 1;
+                true
+            },
+        }
+    }
+}
+
+/// The requests the prefetcher sent for resources the document hasn't
+/// requested itself yet. If the parser is aborted these are cancelled, while
+/// the ones the document did request are left alone, as its loads may be
+/// waiting on their responses in the HTTP cache. Requests stop being tracked
+/// once their fetch is done, which it reports on a channel shared by all of
+/// them, so that the channels of finished requests don't pile up.
+#[derive(Default)]
+pub struct PendingPrefetches {
+    cancel_chans: HashMap<ServoUrl, IpcSender<()>>,
+    /// The channel fetches send their URL on once they're done, created with
+    /// the first request tracked.
+    done_chan: Option<(IpcSender<ServoUrl>, IpcReceiver<ServoUrl>)>,
+}
+
+impl PendingPrefetches {
+    /// Tracks a request for `url`, returning the receiver to cancel it and
+    /// the sender to report it done to send to the fetch along with it, or
+    /// `None` if no channel could be created, in which case the request can
+    /// be sent without them.
+    pub fn track(&mut self, url: ServoUrl) -> Option<(IpcReceiver<()>, IpcSender<ServoUrl>)> {
+        self.forget_done();
+        if self.done_chan.is_none() {
+            self.done_chan = ipc::channel().ok();
+        }
+        let done_chan = self.done_chan.as_ref()?.0.clone();
+        let (sender, receiver) = match ipc::channel() {
+            Ok(channel) => channel,
+            Err(error) => {
+                warn!("Not tracking the prefetch of {}: {:?}", url, error);
+                return None;
+            },
+        };
+        self.cancel_chans.insert(url, sender);
+        Some((receiver, done_chan))
+    }
+
+    /// Whether the request for `url` is still tracked, i.e. it's neither
+    /// done, adopted nor cancelled.
+    pub fn is_tracked(&mut self, url: &ServoUrl) -> bool {
+        self.forget_done();
+        self.cancel_chans.contains_key(url)
+    }
+
+    /// Stops tracking the requests whose fetch reported being done.
+    fn forget_done(&mut self) {
+        if let Some((_, ref receiver)) = self.done_chan {
+            while let Ok(url) = receiver.try_recv() {
+                self.cancel_chans.remove(&url);
+            }
+        }
+    }
+
+    /// Stops tracking the request for `url`, since the document requested
+    /// `url` too.
+    pub fn adopt(&mut self, url: &ServoUrl) {
+        // Dropping the sender doesn't cancel the fetch.
+        self.cancel_chans.remove(url);
+    }
+
+    /// Cancels the requests that weren't adopted.
+    pub fn cancel(&mut self) {
+        self.forget_done();
+        for (_, cancel_chan) in self.cancel_chans.drain() {
+            // The receiver is gone if the fetch is done, which is fine.
+            let _ = cancel_chan.send(());
+        }
+    }
+}
+
+/// The viewport a `srcset` attribute is evaluated against when prefetching.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SrcsetViewport {
+    /// The width of the viewport in CSS pixels. The prefetcher can't evaluate
+    /// `sizes`, so this is used as the source size of width descriptors.
+    pub width: f64,
+    pub device_pixel_ratio: f64,
+}
+
+impl SrcsetViewport {
+    fn for_window(window: &Window) -> SrcsetViewport {
+        let window_size = window.window_size();
+        SrcsetViewport {
+            width: window_size.initial_viewport.width as f64,
+            device_pixel_ratio: window_size.device_pixel_ratio.get() as f64,
+        }
+    }
+}
+
+/// Selects the candidate of a parsed `srcset` attribute to prefetch for the
+/// given viewport, resolved against `base`. This picks the candidate with the
+/// smallest pixel density that still covers the device pixel ratio, or the
+/// densest one if none does, with earlier candidates winning ties.
+/// <https://html.spec.whatwg.org/multipage/#select-an-image-source>
+pub fn select_source_from_srcset(
+    candidates: &[ImageSource],
+    viewport: SrcsetViewport,
+    base: &ServoUrl,
+) -> Option<ServoUrl> {
+    let density = |candidate: &ImageSource| match candidate.descriptor {
+        Descriptor { den: Some(den), .. } => den,
+        Descriptor { wid: Some(wid), .. } => wid as f64 / viewport.width,
+        _ => 1.,
+    };
+    let mut best: Option<(f64, &ImageSource)> = None;
+    for candidate in candidates {
+        let den = density(candidate);
+        let is_better = match best {
+            None => true,
+            Some((best_den, _)) if best_den < viewport.device_pixel_ratio => den > best_den,
+            Some((best_den, _)) => den >= viewport.device_pixel_ratio && den < best_den,
+        };
+        if is_better {
+            best = Some((den, candidate));
         }
     }
+    let (_, candidate) = best?;
+    ServoUrl::parse_with_base(Some(base), &candidate.url).ok()
 }
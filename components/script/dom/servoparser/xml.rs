@@ -4,6 +4,9 @@
 
 #![allow(crown::unrooted_must_root)]
 
+use std::mem;
+
+use encoding_rs::Encoding;
 use html5ever::tokenizer::TokenizerResult;
 use js::jsapi::JSTracer;
 use servo_url::ServoUrl;
@@ -16,7 +19,11 @@ use crate::dom::bindings::trace::{CustomTraceable, JSTraceable};
 use crate::dom::document::Document;
 use crate::dom::htmlscriptelement::HTMLScriptElement;
 use crate::dom::node::Node;
-use crate::dom::servoparser::{ParsingAlgorithm, Sink};
+use crate::dom::servoparser::{
+    max_attributes_per_element, BodyChildCompleteObserver, CommentTransformer, DeprecatedFeature,
+    DeprecationWarning, DoctypeObserver, ElementBlocker, HeadCompleteObserver,
+    ParserElementCounters, ParsingAlgorithm, RawAttributesObserver, Sink, StrictParseError,
+};
 
 #[derive(JSTraceable, MallocSizeOf)]
 #[crown::unrooted_must_root_lint::must_root]
@@ -33,6 +40,29 @@ impl Tokenizer {
             current_line: 1,
             script: Default::default(),
             parsing_algorithm: ParsingAlgorithm::Normal,
+            quirks_mode_override: None,
+            comment_transformer: None,
+            raw_attributes_observer: None,
+            element_blocker: None,
+            blocked_elements: vec![],
+            strict_parse_error: None,
+            parse_errors: None,
+            doctype_observer: None,
+            element_counters: Default::default(),
+            inserted_nodes: 0,
+            sanitize_input_values: true,
+            max_attributes: max_attributes_per_element(),
+            declarative_shadow_roots: vec![],
+            head_complete_observer: None,
+            head_complete: false,
+            body_child_complete_observer: None,
+            text_extractor: None,
+            line_tracker: Default::default(),
+            deprecation_warnings: vec![],
+            scripting_enabled: true,
+            script_blocking_disabled: false,
+            declared_encoding: None,
+            encoding_declared: false,
         };
 
         let tb = XmlTreeBuilder::new(sink, Default::default());
@@ -57,6 +87,72 @@ impl Tokenizer {
     pub fn url(&self) -> &ServoUrl {
         &self.inner.sink.sink.base_url
     }
+
+    pub fn set_comment_transformer(&mut self, transformer: Option<CommentTransformer>) {
+        self.inner.sink.sink.comment_transformer = transformer;
+    }
+
+    pub fn set_raw_attributes_observer(&mut self, observer: Option<RawAttributesObserver>) {
+        self.inner.sink.sink.raw_attributes_observer = observer;
+    }
+
+    pub fn set_element_blocker(&mut self, blocker: Option<ElementBlocker>) {
+        self.inner.sink.sink.element_blocker = blocker;
+    }
+
+    pub fn set_script_blocking_disabled(&mut self, disabled: bool) {
+        self.inner.sink.sink.script_blocking_disabled = disabled;
+    }
+
+    pub fn set_doctype_observer(&mut self, observer: Option<DoctypeObserver>) {
+        self.inner.sink.sink.doctype_observer = observer;
+    }
+
+    pub fn set_head_complete_observer(&mut self, observer: Option<HeadCompleteObserver>) {
+        self.inner.sink.sink.head_complete_observer = observer;
+    }
+
+    pub fn set_body_child_complete_observer(
+        &mut self,
+        observer: Option<BodyChildCompleteObserver>,
+    ) {
+        self.inner.sink.sink.body_child_complete_observer = observer;
+    }
+
+    pub fn enable_text_extraction(&mut self) {
+        let sink = &mut self.inner.sink.sink;
+        sink.text_extractor.get_or_insert_with(Default::default);
+    }
+
+    pub fn extracted_text(&self) -> Option<String> {
+        let text_extractor = self.inner.sink.sink.text_extractor.as_ref();
+        text_extractor.map(|text_extractor| text_extractor.text().to_owned())
+    }
+
+    pub fn warn_deprecated(&mut self, feature: DeprecatedFeature) {
+        let sink = &mut self.inner.sink.sink;
+        super::warn_deprecated(&mut sink.deprecation_warnings, sink.current_line, feature);
+    }
+
+    pub fn deprecation_warnings(&self) -> &[DeprecationWarning] {
+        &self.inner.sink.sink.deprecation_warnings
+    }
+
+    pub fn set_input_value_sanitization(&mut self, enabled: bool) {
+        self.inner.sink.sink.sanitize_input_values = enabled;
+    }
+
+    pub fn element_counters(&self) -> ParserElementCounters {
+        self.inner.sink.sink.element_counters
+    }
+
+    pub fn take_inserted_node_count(&mut self) -> usize {
+        mem::take(&mut self.inner.sink.sink.inserted_nodes)
+    }
+
+    pub fn take_strict_parse_error(&mut self) -> Option<StrictParseError> {
+        self.inner.sink.sink.strict_parse_error.take()
+    }
 }
 
 #[allow(unsafe_code)]
@@ -80,3 +176,56 @@ unsafe impl CustomTraceable for XmlTokenizer<XmlTreeBuilder<Dom<Node>, Sink>> {
         tree_builder.sink.trace(trc);
     }
 }
+
+/// Returns the encoding declared in the XML declaration at the start of
+/// `bytes`, if any. The declaration must be complete and is read as ASCII, so
+/// declarations of UTF-16 encodings are ignored, as they can't possibly be
+/// accurate for bytes that could be read that way.
+/// <https://www.w3.org/TR/xml/#NT-EncodingDecl>
+pub fn xml_declaration_encoding(bytes: &[u8]) -> Option<&'static Encoding> {
+    let declaration = bytes.strip_prefix(b"<?xml")?;
+    if !declaration.first()?.is_ascii_whitespace() {
+        return None;
+    }
+    let end = declaration.windows(2).position(|w| w == b"?>")?;
+    let declaration = &declaration[..end];
+
+    let position = declaration
+        .windows(b"encoding".len())
+        .position(|w| w == b"encoding")?;
+    let value = skip_ascii_whitespace(&declaration[position + b"encoding".len()..]);
+    let value = skip_ascii_whitespace(value.strip_prefix(b"=")?);
+    let quote = *value.first()?;
+    if quote != b'"' && quote != b'\'' {
+        return None;
+    }
+    let value = &value[1..];
+    let label = &value[..value.iter().position(|&b| b == quote)?];
+
+    match Encoding::for_label(label)? {
+        encoding if encoding == encoding_rs::UTF_16BE || encoding == encoding_rs::UTF_16LE => None,
+        encoding => Some(encoding),
+    }
+}
+
+/// Whether `bytes` could be the start of an XML declaration that doesn't end
+/// yet, so that more of the input is needed to tell the encoding it declares.
+pub fn is_partial_xml_declaration(bytes: &[u8]) -> bool {
+    match bytes.strip_prefix(b"<?xml") {
+        None => b"<?xml".starts_with(bytes),
+        Some(declaration) => match declaration.split_first() {
+            None => true,
+            Some((first, rest)) => {
+                first.is_ascii_whitespace() && !rest.windows(2).any(|w| w == b"?>")
+            },
+        },
+    }
+}
+
+fn skip_ascii_whitespace(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(bytes.len());
+    &bytes[start..]
+}
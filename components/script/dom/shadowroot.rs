@@ -2,6 +2,8 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use std::cell::Cell;
+
 use dom_struct::dom_struct;
 use servo_arc::Arc;
 use servo_atoms::Atom;
@@ -47,6 +49,16 @@ pub struct ShadowRoot {
     author_styles: DomRefCell<AuthorStyles<StyleSheetInDocument>>,
     stylesheet_list: MutNullableDom<StyleSheetList>,
     window: Dom<Window>,
+    /// <https://dom.spec.whatwg.org/#shadowroot-mode>
+    ///
+    /// Only declarative shadow roots can be open for now.
+    mode: Cell<ShadowRootMode>,
+    /// <https://dom.spec.whatwg.org/#shadowroot-clonable>
+    clonable: Cell<bool>,
+    /// <https://dom.spec.whatwg.org/#shadowroot-declarative>
+    declarative: Cell<bool>,
+    /// <https://dom.spec.whatwg.org/#shadowroot-delegates-focus>
+    delegates_focus: Cell<bool>,
 }
 
 impl ShadowRoot {
@@ -67,6 +79,10 @@ impl ShadowRoot {
             author_styles: DomRefCell::new(AuthorStyles::new()),
             stylesheet_list: MutNullableDom::new(None),
             window: Dom::from_ref(document.window()),
+            mode: Cell::new(ShadowRootMode::Closed),
+            clonable: Cell::new(false),
+            declarative: Cell::new(false),
+            delegates_focus: Cell::new(false),
         }
     }
 
@@ -85,6 +101,28 @@ impl ShadowRoot {
         self.host.set(None);
     }
 
+    /// Marks this shadow root as attached by the parser for a `<template
+    /// shadowrootmode>`, or by cloning such a shadow root, with the mode,
+    /// clonability and focus delegation it was declared with.
+    pub fn set_declarative(&self, mode: ShadowRootMode, clonable: bool, delegates_focus: bool) {
+        self.mode.set(mode);
+        self.clonable.set(clonable);
+        self.delegates_focus.set(delegates_focus);
+        self.declarative.set(true);
+    }
+
+    pub fn delegates_focus(&self) -> bool {
+        self.delegates_focus.get()
+    }
+
+    pub fn is_declarative(&self) -> bool {
+        self.declarative.get()
+    }
+
+    pub fn is_clonable(&self) -> bool {
+        self.clonable.get()
+    }
+
     pub fn get_focused_element(&self) -> Option<DomRoot<Element>> {
         //XXX get retargeted focused element
         None
@@ -151,6 +189,7 @@ impl ShadowRoot {
             to_unregister,
             &id,
         );
+        self.document.reset_form_owner_for_listeners(&id);
     }
 
     /// Associate an element present in this shadow tree with the provided id.
@@ -166,6 +205,7 @@ impl ShadowRoot {
             &id,
             root,
         );
+        self.document.reset_form_owner_for_listeners(&id);
     }
 }
 
@@ -219,7 +259,12 @@ impl ShadowRootMethods for ShadowRoot {
 
     /// <https://dom.spec.whatwg.org/#dom-shadowroot-mode>
     fn Mode(&self) -> ShadowRootMode {
-        ShadowRootMode::Closed
+        self.mode.get()
+    }
+
+    /// <https://dom.spec.whatwg.org/#dom-shadowroot-delegatesfocus>
+    fn DelegatesFocus(&self) -> bool {
+        self.delegates_focus.get()
     }
 
     /// <https://dom.spec.whatwg.org/#dom-shadowroot-host>
@@ -35,6 +35,7 @@ use crate::dom::bindings::codegen::UnionTypes::{
     StringSequenceOrUnsignedLong, UnsignedLongOrBoolean,
 };
 use crate::dom::bindings::error::{Error, Fallible};
+use crate::dom::bindings::inheritance::Castable;
 use crate::dom::bindings::num::Finite;
 use crate::dom::bindings::record::Record;
 use crate::dom::bindings::refcounted::TrustedPromise;
@@ -44,10 +45,12 @@ use crate::dom::bindings::str::{ByteString, DOMString, USVString};
 use crate::dom::bindings::trace::RootedTraceableBox;
 use crate::dom::bindings::weakref::MutableWeakRef;
 use crate::dom::blob::Blob;
+use crate::dom::document::Document;
 use crate::dom::globalscope::GlobalScope;
 use crate::dom::node::Node;
 use crate::dom::promise::Promise;
 use crate::dom::promisenativehandler::{Callback, PromiseNativeHandler};
+use crate::dom::servoparser::ServoParser;
 use crate::dom::url::URL;
 use crate::realms::InRealm;
 use crate::script_runtime::JSContext as SafeJSContext;
@@ -1050,6 +1053,45 @@ impl TestBindingMethods for TestBinding {
         self.global().as_window().advance_animation_clock(ms);
     }
 
+    fn FeedScriptBytes(&self, document: &Document, bytes: ByteString) {
+        let parser = document
+            .get_current_parser()
+            .filter(|parser| parser.is_script_created());
+        if let Some(parser) = parser {
+            parser.feed_script_bytes(bytes.into());
+        }
+    }
+
+    fn ParseHTMLWithoutScripting(&self, document: &Document, html: DOMString) {
+        Node::replace_all(None, document.upcast());
+        ServoParser::parse_html_document_without_scripting(document, html, document.url());
+    }
+
+    fn SetScriptBlockingDisabled(&self, document: &Document, disabled: bool) {
+        if let Some(parser) = document.get_current_parser() {
+            parser.set_script_blocking_disabled(disabled);
+        }
+    }
+
+    fn HaltParser(&self, document: &Document) {
+        if let Some(parser) = document.get_current_parser() {
+            parser.halt();
+        }
+    }
+
+    fn HaltAbortedParser(&self, document: &Document) {
+        if let Some(parser) = document.get_current_parser() {
+            parser.abort();
+            parser.halt();
+        }
+    }
+
+    fn LoadSynthesizedDocument(&self, document: &Document, html: DOMString) {
+        if let Some(parser) = document.get_current_parser() {
+            parser.load_synthesized_document(String::from(html));
+        }
+    }
+
     fn Panic(&self) {
         panic!("explicit panic from script")
     }
@@ -1956,6 +1956,14 @@ impl Window {
         self.Document().ensure_safe_to_run_script_or_layout();
         let for_display = reflow_goal == ReflowGoal::Full;
 
+        if for_display && self.Document().is_render_blocked() {
+            debug!(
+                "Document is render-blocked - skipping reflow (reason {:?})",
+                reason
+            );
+            return false;
+        }
+
         let mut issued_reflow = false;
         let condition = self.Document().needs_reflow();
         if !for_display || condition.is_some() {
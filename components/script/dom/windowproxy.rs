@@ -610,6 +610,16 @@ impl WindowProxy {
         self.parent.as_deref()
     }
 
+    /// The parent browsing context of a nested browsing context, or the opener
+    /// of an auxiliary one, if it's in this script thread.
+    /// <https://html.spec.whatwg.org/multipage/#creator-browsing-context>
+    pub fn creator(&self) -> Option<DomRoot<WindowProxy>> {
+        match self.parent() {
+            Some(parent) => Some(DomRoot::from_ref(parent)),
+            None => ScriptThread::find_window_proxy(self.opener?),
+        }
+    }
+
     pub fn top(&self) -> &WindowProxy {
         let mut result = self;
         while let Some(parent) = result.parent() {
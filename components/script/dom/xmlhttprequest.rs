@@ -1498,7 +1498,13 @@ impl XMLHttpRequest {
         let (decoded, _, _) = charset.decode(&response);
         let document = self.new_doc(IsHTMLDocument::HTMLDocument);
         // TODO: Disable scripting while parsing
-        ServoParser::parse_html_document(&document, Some(DOMString::from(decoded)), wr.get_url());
+        ServoParser::parse_html_document(
+            &document,
+            Some(DOMString::from(decoded)),
+            wr.get_url(),
+            None,
+            None,
+        );
         document
     }
 
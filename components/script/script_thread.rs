@@ -42,7 +42,7 @@ use embedder_traits::EmbedderMsg;
 use euclid::default::{Point2D, Rect};
 use euclid::Vector2D;
 use gfx::font_cache_thread::FontCacheThread;
-use headers::{HeaderMapExt, LastModified, ReferrerPolicy as ReferrerPolicyHeader};
+use headers::{ContentLength, HeaderMapExt, LastModified, ReferrerPolicy as ReferrerPolicyHeader};
 use html5ever::{local_name, namespace_url, ns};
 use hyper_serde::Serde;
 use ipc_channel::ipc::{self, IpcSender};
@@ -134,7 +134,7 @@ use crate::dom::node::{window_from_node, Node, ShadowIncluding};
 use crate::dom::performanceentry::PerformanceEntry;
 use crate::dom::performancepainttiming::PerformancePaintTiming;
 use crate::dom::serviceworker::TrustedServiceWorkerAddress;
-use crate::dom::servoparser::{ParserContext, ServoParser};
+use crate::dom::servoparser::{is_no_content_status, ParserContext, ServoParser};
 use crate::dom::uievent::UIEvent;
 use crate::dom::window::{ReflowReason, Window};
 use crate::dom::windowproxy::{CreatorBrowsingContextInfo, WindowProxy};
@@ -2795,35 +2795,27 @@ impl ScriptThread {
             Some(idx) => {
                 // https://html.spec.whatwg.org/multipage/#process-a-navigate-response
                 // 2. If response's status is 204 or 205, then abort these steps.
-                match metadata {
-                    Some(Metadata {
-                        status: Some((204..=205, _)),
-                        ..
-                    }) => {
-                        // If we have an existing window that is being navigated:
-                        if let Some(window) = self.documents.borrow().find_window(id.clone()) {
-                            let window_proxy = window.window_proxy();
-                            // https://html.spec.whatwg.org/multipage/
-                            // #navigating-across-documents:delaying-load-events-mode-2
-                            if window_proxy.parent().is_some() {
-                                // The user agent must take this nested browsing context
-                                // out of the delaying load events mode
-                                // when this navigation algorithm later matures,
-                                // or when it terminates (whether due to having run all the steps,
-                                // or being canceled, or being aborted), whichever happens first.
-                                window_proxy.stop_delaying_load_events_mode();
-                            }
-                        }
-                        self.script_sender
-                            .send((id.clone(), ScriptMsg::AbortLoadUrl))
-                            .unwrap();
-                        return None;
-                    },
-                    _ => (),
-                };
+                let status = metadata
+                    .as_ref()
+                    .and_then(|meta| meta.status.as_ref())
+                    .map(|&(code, _)| code);
+                if is_no_content_status(status) {
+                    // The current document is kept, and the navigation is
+                    // over as far as the constellation is concerned.
+                    self.abort_navigation(id);
+                    return None;
+                }
 
                 let load = self.incomplete_loads.borrow_mut().remove(idx);
-                metadata.map(|meta| self.load(meta, load))
+                match metadata {
+                    Some(meta) => Some(self.load(meta, load)),
+                    None => {
+                        // The navigation failed without a response to make a
+                        // document from, so the load is over.
+                        self.abort_navigation(id);
+                        None
+                    },
+                }
             },
             None => {
                 assert!(self.closed_pipelines.borrow().contains(id));
@@ -2832,6 +2824,28 @@ impl ScriptThread {
         }
     }
 
+    /// Tells the constellation that the navigation of the pipeline `id` won't
+    /// produce a document, which keeps the current one.
+    fn abort_navigation(&self, id: &PipelineId) {
+        // If we have an existing window that is being navigated:
+        if let Some(window) = self.documents.borrow().find_window(id.clone()) {
+            let window_proxy = window.window_proxy();
+            // https://html.spec.whatwg.org/multipage/
+            // #navigating-across-documents:delaying-load-events-mode-2
+            if window_proxy.parent().is_some() {
+                // The user agent must take this nested browsing context
+                // out of the delaying load events mode
+                // when this navigation algorithm later matures,
+                // or when it terminates (whether due to having run all the steps,
+                // or being canceled, or being aborted), whichever happens first.
+                window_proxy.stop_delaying_load_events_mode();
+            }
+        }
+        self.script_sender
+            .send((id.clone(), ScriptMsg::AbortLoadUrl))
+            .unwrap();
+    }
+
     pub fn dom_manipulation_task_source(
         &self,
         pipeline_id: PipelineId,
@@ -3383,6 +3397,13 @@ impl ScriptThread {
             .and_then(|h| h.typed_get::<ReferrerPolicyHeader>())
             .map(ReferrerPolicy::from);
 
+        let content_length = metadata
+            .headers
+            .as_ref()
+            .map(Serde::deref)
+            .and_then(|h| h.typed_get::<ContentLength>())
+            .map(|length| length.0);
+
         let document = Document::new(
             &window,
             HasBrowsingContext::Yes,
@@ -3439,7 +3460,7 @@ impl ScriptThread {
         if is_html_document == IsHTMLDocument::NonHTMLDocument {
             ServoParser::parse_xml_document(&document, None, final_url);
         } else {
-            ServoParser::parse_html_document(&document, None, final_url);
+            ServoParser::parse_html_document(&document, None, final_url, None, content_length);
         }
 
         if incomplete.activity == DocumentActivity::FullyActive {
@@ -3844,7 +3865,12 @@ impl ScriptThread {
             .origin(incomplete.origin.immutable().clone())
             .crash(load_data.crash);
 
-        let context = ParserContext::new(id, load_data.url);
+        let mut context = ParserContext::new(id, load_data.url);
+        if let (None, LoadOrigin::Script(origin)) = (incomplete.parent_info, load_data.load_origin)
+        {
+            context.set_initiator_origin(origin);
+        }
+        context.set_resource_threads(self.resource_threads.clone());
         self.incomplete_parser_contexts
             .0
             .borrow_mut()
@@ -213,6 +213,7 @@ impl FetchResponseListener for StylesheetContext {
         document.finish_load(LoadType::Stylesheet(self.url.clone()));
 
         if let Some(any_failed) = owner.load_finished(successful) {
+            document.update_render_blocking();
             let event = if any_failed {
                 atom!("error")
             } else {
@@ -14,6 +14,54 @@ pub mod area {
     pub use crate::dom::htmlareaelement::{Area, Shape};
 }
 
+pub mod prefetch {
+    pub use ipc_channel::ipc;
+    pub use msg::constellation_msg::TEST_PIPELINE_ID;
+    pub use net_traits::request::{
+        CorsSettings, CredentialsMode, Destination, Referrer, RequestBuilder, RequestMode,
+    };
+    pub use net_traits::{CoreResourceMsg, FetchChannels, ReferrerPolicy, ResourceThreads};
+
+    pub use crate::dom::servoparser::prefetch::{
+        early_hint_requests, link_preload, next_navigation_prefetch_request, parse_link_header,
+        parse_speculation_rules, preload_cors_setting, preload_destination,
+        select_source_from_srcset, speculative_script_request, with_fetch_metadata,
+        LinkHeaderValue, PendingPrefetches, PrefetchBudget, SpeculationRule, SrcsetViewport,
+        Tokenizer,
+    };
+}
+
+pub mod servoparser {
+    pub use embedder_traits::SupportedColorSchemes;
+    pub use html5ever::buffer_queue::BufferQueue;
+    pub use html5ever::tendril::StrTendril;
+    pub use html5ever::tokenizer::states::RawKind;
+    pub use html5ever::tokenizer::{
+        TagKind, Token, TokenSink, TokenSinkResult, Tokenizer as HtmlTokenizer, TokenizerResult,
+    };
+    pub use html5ever::{Attribute, LocalName, Namespace, QualName};
+    pub use net_traits::image::base::is_supported_image_mime_type;
+
+    pub use crate::dom::servoparser::async_html::take_feed_batch;
+    pub use crate::dom::servoparser::xml::{is_partial_xml_declaration, xml_declaration_encoding};
+    pub use crate::dom::servoparser::{
+        cap_attributes, close_action, compact_input, consumed_chars, content_location_url,
+        content_type_encoding, decodes_identically, doctype_mismatches_document,
+        feed_prefetch_input, file_extension_content_type, font_specimen_page, fragment_chunk_size,
+        icon_entries, is_blocked_element, is_font_file, is_legacy_doctype,
+        is_mixed_content_navigation, is_no_content_status, is_nosniff, is_obsolete_element,
+        is_parsed_as_html, is_render_blocking_element, meta_content_encoding, mixed_content_page,
+        no_content_type_page, parse_clear_site_data, parse_color_scheme, parse_retry_after,
+        pdf_viewer_page, prepend_script_input, sniff_content_encoding, strip_bom_at_start_of_input,
+        strip_leading_bom, substitute_html, undecoded_suffix_len, unknown_content_type_page,
+        unsupported_image_page, uses_async_html_tokenizer, BufferedInputSize, ClearSiteData,
+        CloseAction, ElementAttribute, IconEntry, LineTracker, NetworkDecoder, ParseBudget,
+        ParseBudgetExceeded, SourceViewer, TextExtractor, UnknownContentTypeHandling,
+    };
+    #[cfg(feature = "testing")]
+    pub use crate::dom::servoparser::{ParseErrorRecord, ServoParser};
+}
+
 #[allow(non_snake_case)]
 pub mod size_of {
     use std::mem::size_of;
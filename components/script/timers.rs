@@ -25,7 +25,7 @@ use crate::dom::bindings::cell::DomRefCell;
 use crate::dom::bindings::codegen::Bindings::FunctionBinding::Function;
 use crate::dom::bindings::reflector::DomObject;
 use crate::dom::bindings::str::DOMString;
-use crate::dom::document::FakeRequestAnimationFrameCallback;
+use crate::dom::document::{FakeRequestAnimationFrameCallback, RenderBlockingTimeoutCallback};
 use crate::dom::eventsource::EventSourceTimeoutCallback;
 use crate::dom::globalscope::GlobalScope;
 use crate::dom::htmlmetaelement::RefreshRedirectDue;
@@ -91,6 +91,7 @@ pub enum OneshotTimerCallback {
     TestBindingCallback(TestBindingCallback),
     FakeRequestAnimationFrame(FakeRequestAnimationFrameCallback),
     RefreshRedirectDue(RefreshRedirectDue),
+    RenderBlockingTimeout(RenderBlockingTimeoutCallback),
 }
 
 impl OneshotTimerCallback {
@@ -102,6 +103,7 @@ impl OneshotTimerCallback {
             OneshotTimerCallback::TestBindingCallback(callback) => callback.invoke(),
             OneshotTimerCallback::FakeRequestAnimationFrame(callback) => callback.invoke(),
             OneshotTimerCallback::RefreshRedirectDue(callback) => callback.invoke(),
+            OneshotTimerCallback::RenderBlockingTimeout(callback) => callback.invoke(),
         }
     }
 }
@@ -214,6 +214,13 @@ pub enum EmbedderMsg {
     ReadyToPresent,
     /// The given event was delivered to a pipeline in the given browser.
     EventDelivered(CompositorEventVariant),
+    /// A resource with the given content type that can't be shown was
+    /// navigated to, and should be downloaded instead.
+    Download(ServoUrl, String),
+    /// A PDF was navigated to, which should be shown in a PDF viewer.
+    OpenPdf(ServoUrl),
+    /// The page declared the color schemes it supports, before it's rendered.
+    ColorSchemeDeclared(SupportedColorSchemes),
 }
 
 /// The variant of CompositorEvent that was delivered to a pipeline.
@@ -268,10 +275,25 @@ impl Debug for EmbedderMsg {
             EmbedderMsg::ShowContextMenu(..) => write!(f, "ShowContextMenu"),
             EmbedderMsg::ReadyToPresent => write!(f, "ReadyToPresent"),
             EmbedderMsg::EventDelivered(..) => write!(f, "HitTestedEvent"),
+            EmbedderMsg::Download(..) => write!(f, "Download"),
+            EmbedderMsg::OpenPdf(..) => write!(f, "OpenPdf"),
+            EmbedderMsg::ColorSchemeDeclared(..) => write!(f, "ColorSchemeDeclared"),
         }
     }
 }
 
+/// The color schemes a page supports, as declared by a
+/// `<meta name="color-scheme">`.
+/// <https://html.spec.whatwg.org/multipage/#meta-color-scheme>
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct SupportedColorSchemes {
+    /// The color schemes, in order of preference, e.g. `light` and `dark`.
+    /// There are none for `normal`.
+    pub schemes: Vec<String>,
+    /// Whether the page forbids rendering it in another color scheme.
+    pub only: bool,
+}
+
 /// Filter for file selection;
 /// the `String` content is expected to be extension (e.g, "doc", without the prefixing ".")
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -71,6 +71,11 @@ pub enum Resource {
     MediaControlsCSS,
     MediaControlsJS,
     CrashHTML,
+    ImageViewerHTML,
+    XMLViewerCSS,
+    ServiceUnavailableHTML,
+    UnknownContentTypeHTML,
+    EmptyDocumentHTML,
 }
 
 impl Resource {
@@ -90,6 +95,11 @@ impl Resource {
             Resource::MediaControlsCSS => "media-controls.css",
             Resource::MediaControlsJS => "media-controls.js",
             Resource::CrashHTML => "crash.html",
+            Resource::ImageViewerHTML => "imageviewer.html",
+            Resource::XMLViewerCSS => "xmlviewer.css",
+            Resource::ServiceUnavailableHTML => "serviceunavailable.html",
+            Resource::UnknownContentTypeHTML => "unknowncontenttype.html",
+            Resource::EmptyDocumentHTML => "emptydocument.html",
         }
     }
 }
@@ -146,6 +156,19 @@ fn resources_for_tests() -> Box<dyn ResourceReaderMethods + Sync + Send> {
                     &include_bytes!("../../../resources/media-controls.js")[..]
                 },
                 Resource::CrashHTML => &include_bytes!("../../../resources/crash.html")[..],
+                Resource::ImageViewerHTML => {
+                    &include_bytes!("../../../resources/imageviewer.html")[..]
+                },
+                Resource::XMLViewerCSS => &include_bytes!("../../../resources/xmlviewer.css")[..],
+                Resource::ServiceUnavailableHTML => {
+                    &include_bytes!("../../../resources/serviceunavailable.html")[..]
+                },
+                Resource::UnknownContentTypeHTML => {
+                    &include_bytes!("../../../resources/unknowncontenttype.html")[..]
+                },
+                Resource::EmptyDocumentHTML => {
+                    &include_bytes!("../../../resources/emptydocument.html")[..]
+                },
             }
             .to_owned()
         }
@@ -8,6 +8,7 @@ use image::ImageFormat;
 use ipc_channel::ipc::IpcSharedMemory;
 use log::debug;
 use malloc_size_of_derive::MallocSizeOf;
+use mime::Mime;
 use pixels::PixelFormat;
 use serde::{Deserialize, Serialize};
 use webrender_api::ImageKey;
@@ -96,6 +97,18 @@ pub fn detect_image_format(buffer: &[u8]) -> Result<ImageFormat, &str> {
     }
 }
 
+/// Whether images of the MIME type `mime` can be decoded, which is the case for
+/// the formats `detect_image_format` knows of.
+pub fn is_supported_image_mime_type(mime: &Mime) -> bool {
+    if mime.type_() != mime::IMAGE {
+        return false;
+    }
+    matches!(
+        mime.subtype().as_str(),
+        "gif" | "jpeg" | "pjpeg" | "png" | "webp" | "bmp" | "x-icon" | "vnd.microsoft.icon"
+    )
+}
+
 fn is_gif(buffer: &[u8]) -> bool {
     buffer.starts_with(b"GIF87a") || buffer.starts_with(b"GIF89a")
 }
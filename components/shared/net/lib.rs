@@ -294,6 +294,28 @@ impl FetchTaskTarget for DiscardFetch {
     fn process_response_eof(&mut self, _: &Response) {}
 }
 
+/// A fetch task that discards all data it's sent, like [`DiscardFetch`], and
+/// sends the URL it was started for on `done_chan` once it's done, so that
+/// whoever started it can stop keeping track of it.
+pub struct DiscardTrackedFetch {
+    pub url: ServoUrl,
+    pub done_chan: IpcSender<ServoUrl>,
+}
+
+impl FetchTaskTarget for DiscardTrackedFetch {
+    fn process_request_body(&mut self, _: &Request) {}
+
+    fn process_request_eof(&mut self, _: &Request) {}
+
+    fn process_response(&mut self, _: &Response) {}
+
+    fn process_response_chunk(&mut self, _: Vec<u8>) {}
+
+    fn process_response_eof(&mut self, _: &Response) {
+        let _ = self.done_chan.send(self.url.clone());
+    }
+}
+
 pub trait Action<Listener> {
     fn process(self, listener: &mut Listener);
 }
@@ -430,8 +452,12 @@ pub enum FetchChannels {
         action_receiver: IpcReceiver<WebSocketDomAction>,
     },
     /// If the fetch is just being done to populate the cache,
-    /// not because the data is needed now.
-    Prefetch,
+    /// not because the data is needed now. The URL of the request is sent
+    /// on `done_chan`, if any, once the fetch is done.
+    Prefetch(
+        /* cancel_chan */ Option<IpcReceiver<()>>,
+        /* done_chan */ Option<IpcSender<ServoUrl>>,
+    ),
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -457,6 +483,8 @@ pub enum CoreResourceMsg {
         CookieSource,
     ),
     DeleteCookies(ServoUrl),
+    /// Delete the cookies of a registrable domain, and of its subdomains
+    DeleteCookiesForRegistrableDomain(String),
     /// Get a history state by a given history state id
     GetHistoryState(HistoryStateId, IpcSender<Option<Vec<u8>>>),
     /// Set a history state for a given history state id
@@ -467,6 +495,8 @@ pub enum CoreResourceMsg {
     Synchronize(IpcSender<()>),
     /// Clear the network cache.
     ClearCache,
+    /// Clear the responses cached for an origin.
+    ClearCacheForOrigin(ImmutableOrigin),
     /// Send the service worker network mediator for an origin to CoreResourceThread
     NetworkMediator(IpcSender<CustomResponseMediator>, ImmutableOrigin),
     /// Message forwarded to file manager's handler
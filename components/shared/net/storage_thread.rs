@@ -43,6 +43,9 @@ pub enum StorageThreadMsg {
     /// clears the associated storage data by removing all the key/value pairs
     Clear(IpcSender<bool>, ServoUrl, StorageType),
 
+    /// clears both the local and session storage data of the origin of the url, without replying
+    ClearOrigin(ServoUrl),
+
     /// send a reply when done cleaning up thread resources and then shut it down
     Exit(IpcSender<()>),
 }
@@ -108,6 +108,7 @@ pub enum ProfilerCategory {
     ScriptHistoryEvent = 0x7c,
     ScriptPortMessage = 0x7d,
     ScriptWebGPUMsg = 0x7e,
+    ScriptParseHTMLFragment = 0x7f,
     TimeToFirstPaint = 0x80,
     TimeToFirstContentfulPaint = 0x81,
     TimeToInteractive = 0x82,
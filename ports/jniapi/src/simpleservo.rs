@@ -831,7 +831,10 @@ impl ServoGlue {
                 EmbedderMsg::HeadParsed |
                 EmbedderMsg::SetFullscreenState(..) |
                 EmbedderMsg::ReportProfile(..) |
-                EmbedderMsg::EventDelivered(..) => {},
+                EmbedderMsg::EventDelivered(..) |
+                EmbedderMsg::Download(..) |
+                EmbedderMsg::OpenPdf(..) |
+                EmbedderMsg::ColorSchemeDeclared(..) => {},
             }
         }
 
@@ -936,6 +939,17 @@ impl ResourceReaderMethods for ResourceReaderInstance {
                 &include_bytes!("../../../resources/media-controls.js")[..]
             },
             Resource::CrashHTML => &include_bytes!("../../../resources/crash.html")[..],
+            Resource::ImageViewerHTML => &include_bytes!("../../../resources/imageviewer.html")[..],
+            Resource::XMLViewerCSS => &include_bytes!("../../../resources/xmlviewer.css")[..],
+            Resource::ServiceUnavailableHTML => {
+                &include_bytes!("../../../resources/serviceunavailable.html")[..]
+            },
+            Resource::UnknownContentTypeHTML => {
+                &include_bytes!("../../../resources/unknowncontenttype.html")[..]
+            },
+            Resource::EmptyDocumentHTML => {
+                &include_bytes!("../../../resources/emptydocument.html")[..]
+            },
         })
     }
 
@@ -675,6 +675,15 @@ where
                     },
                     (_, _) => {},
                 },
+                EmbedderMsg::Download(url, content_type) => {
+                    info!("Not downloading {} ({})", url, content_type);
+                },
+                EmbedderMsg::OpenPdf(url) => {
+                    info!("No PDF viewer to open {}", url);
+                },
+                EmbedderMsg::ColorSchemeDeclared(_schemes) => {
+                    // FIXME: theme the UI after the page
+                },
             }
         }
 
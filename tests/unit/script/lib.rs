@@ -2,6 +2,11 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+#![cfg_attr(test, feature(test))]
+
+#[cfg(test)]
+extern crate test;
+
 #[cfg(test)]
 mod headers;
 #[cfg(test)]
@@ -10,6 +15,10 @@ mod htmlareaelement;
 mod htmlimageelement;
 #[cfg(test)]
 mod origin;
+#[cfg(test)]
+mod prefetch;
+#[cfg(test)]
+mod servoparser;
 #[cfg(all(test, target_pointer_width = "64"))]
 mod size_of;
 #[cfg(test)]
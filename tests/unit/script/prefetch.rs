@@ -0,0 +1,676 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use script::test::prefetch::{
+    early_hint_requests, ipc, link_preload, next_navigation_prefetch_request, parse_link_header,
+    parse_speculation_rules, preload_cors_setting, preload_destination, select_source_from_srcset,
+    speculative_script_request, with_fetch_metadata, CoreResourceMsg, CorsSettings,
+    CredentialsMode, Destination, FetchChannels, LinkHeaderValue, PendingPrefetches,
+    PrefetchBudget, Referrer, ReferrerPolicy, RequestBuilder, RequestMode, ResourceThreads,
+    SpeculationRule, SrcsetViewport, Tokenizer, TEST_PIPELINE_ID,
+};
+use script::test::servoparser::{
+    feed_prefetch_input, prepend_script_input, BufferQueue, StrTendril,
+};
+use script::test::srcset::{Descriptor, ImageSource};
+use servo_url::ServoUrl;
+
+#[test]
+fn preload_destination_font_and_style() {
+    assert_eq!(preload_destination("font"), Some(Destination::Font));
+    assert_eq!(preload_destination("STYLE"), Some(Destination::Style));
+    assert_eq!(preload_destination("fetch"), None);
+}
+
+#[test]
+fn preload_font_without_crossorigin_is_anonymous_cors() {
+    assert_eq!(
+        preload_cors_setting(Destination::Font, None),
+        Some(CorsSettings::Anonymous)
+    );
+}
+
+#[test]
+fn preload_font_with_crossorigin_keeps_setting() {
+    assert_eq!(
+        preload_cors_setting(Destination::Font, Some(CorsSettings::UseCredentials)),
+        Some(CorsSettings::UseCredentials)
+    );
+}
+
+#[test]
+fn prefetch_is_for_the_next_navigation() {
+    let url = ServoUrl::parse("https://example.com/next.html").unwrap();
+    let origin = ServoUrl::parse("https://example.com/").unwrap().origin();
+    let request = next_navigation_prefetch_request(url, None, origin, Referrer::NoReferrer);
+    assert_eq!(request.destination, Destination::None);
+    assert_eq!(
+        request
+            .headers
+            .get("sec-purpose")
+            .map(|value| value.to_str().unwrap()),
+        Some("prefetch")
+    );
+}
+
+#[test]
+fn preload_is_for_the_current_document() {
+    let url = ServoUrl::parse("https://example.com/next.html").unwrap();
+    let origin = ServoUrl::parse("https://example.com/").unwrap().origin();
+    let prefetch = next_navigation_prefetch_request(url, None, origin, Referrer::NoReferrer);
+    let preload = preload_destination("style").unwrap();
+    assert_ne!(prefetch.destination, preload);
+}
+
+#[test]
+fn preload_style_without_crossorigin_is_not_cors() {
+    assert_eq!(preload_cors_setting(Destination::Style, None), None);
+}
+
+#[test]
+fn preload_style_with_crossorigin_keeps_setting() {
+    assert_eq!(
+        preload_cors_setting(Destination::Style, Some(CorsSettings::Anonymous)),
+        Some(CorsSettings::Anonymous)
+    );
+}
+
+fn script_request(crossorigin: Option<&str>, integrity: Option<&str>) -> RequestBuilder {
+    let url = ServoUrl::parse("https://cdn.example.com/app.js").unwrap();
+    let origin = ServoUrl::parse("https://example.com/").unwrap().origin();
+    speculative_script_request(
+        url,
+        crossorigin,
+        integrity,
+        origin,
+        TEST_PIPELINE_ID,
+        Referrer::NoReferrer,
+        None,
+    )
+}
+
+#[test]
+fn script_prefetch_carries_integrity_and_crossorigin() {
+    let request = script_request(Some("anonymous"), Some("sha384-abc"));
+    assert_eq!(request.destination, Destination::Script);
+    assert_eq!(request.integrity_metadata, "sha384-abc");
+    assert_eq!(request.mode, RequestMode::CorsMode);
+    assert_eq!(
+        request.credentials_mode,
+        CredentialsMode::CredentialsSameOrigin
+    );
+}
+
+#[test]
+fn script_prefetch_with_credentials() {
+    let request = script_request(Some("USE-CREDENTIALS"), None);
+    assert_eq!(request.mode, RequestMode::CorsMode);
+    assert_eq!(request.credentials_mode, CredentialsMode::Include);
+    assert_eq!(request.integrity_metadata, "");
+}
+
+#[test]
+fn script_prefetch_with_invalid_crossorigin_is_anonymous() {
+    let request = script_request(Some(""), None);
+    assert_eq!(request.mode, RequestMode::CorsMode);
+    assert_eq!(
+        request.credentials_mode,
+        CredentialsMode::CredentialsSameOrigin
+    );
+}
+
+#[test]
+fn script_prefetch_without_crossorigin_is_not_cors() {
+    let request = script_request(None, Some("sha256-def"));
+    assert_eq!(request.mode, RequestMode::NoCors);
+    assert_eq!(request.integrity_metadata, "sha256-def");
+}
+
+fn candidate(url: &str, wid: Option<u32>, den: Option<f64>) -> ImageSource {
+    ImageSource {
+        url: url.to_string(),
+        descriptor: Descriptor { wid, den },
+    }
+}
+
+fn select(candidates: &[ImageSource], width: f64, device_pixel_ratio: f64) -> Option<String> {
+    let base = ServoUrl::parse("https://example.com/page/").unwrap();
+    let viewport = SrcsetViewport {
+        width,
+        device_pixel_ratio,
+    };
+    select_source_from_srcset(candidates, viewport, &base).map(|url| url.to_string())
+}
+
+#[test]
+fn srcset_no_candidates() {
+    assert_eq!(select(&[], 800., 1.), None);
+}
+
+#[test]
+fn srcset_density_descriptors() {
+    let candidates = [
+        candidate("a.jpg", None, Some(1.)),
+        candidate("b.jpg", None, Some(2.)),
+        candidate("c.jpg", None, Some(3.)),
+    ];
+    assert_eq!(
+        select(&candidates, 800., 1.),
+        Some("https://example.com/page/a.jpg".to_owned())
+    );
+    assert_eq!(
+        select(&candidates, 800., 1.5),
+        Some("https://example.com/page/b.jpg".to_owned())
+    );
+    assert_eq!(
+        select(&candidates, 800., 2.),
+        Some("https://example.com/page/b.jpg".to_owned())
+    );
+    assert_eq!(
+        select(&candidates, 800., 4.),
+        Some("https://example.com/page/c.jpg".to_owned())
+    );
+}
+
+#[test]
+fn srcset_width_descriptors() {
+    let candidates = [
+        candidate("small.jpg", Some(320), None),
+        candidate("medium.jpg", Some(640), None),
+        candidate("large.jpg", Some(1280), None),
+    ];
+    assert_eq!(
+        select(&candidates, 320., 1.),
+        Some("https://example.com/page/small.jpg".to_owned())
+    );
+    assert_eq!(
+        select(&candidates, 320., 2.),
+        Some("https://example.com/page/medium.jpg".to_owned())
+    );
+    assert_eq!(
+        select(&candidates, 640., 1.),
+        Some("https://example.com/page/medium.jpg".to_owned())
+    );
+    assert_eq!(
+        select(&candidates, 1280., 2.),
+        Some("https://example.com/page/large.jpg".to_owned())
+    );
+}
+
+#[test]
+fn srcset_without_descriptor_is_1x() {
+    let candidates = [
+        candidate("/default.jpg", None, None),
+        candidate("/retina.jpg", None, Some(2.)),
+    ];
+    assert_eq!(
+        select(&candidates, 800., 1.),
+        Some("https://example.com/default.jpg".to_owned())
+    );
+    assert_eq!(
+        select(&candidates, 800., 2.),
+        Some("https://example.com/retina.jpg".to_owned())
+    );
+}
+
+#[test]
+fn srcset_ties_prefer_first_candidate() {
+    let candidates = [
+        candidate("first.jpg", None, Some(2.)),
+        candidate("second.jpg", Some(1600), None),
+    ];
+    assert_eq!(
+        select(&candidates, 800., 2.),
+        Some("https://example.com/page/first.jpg".to_owned())
+    );
+}
+
+fn link(target: &str, params: &[(&str, &str)]) -> LinkHeaderValue {
+    LinkHeaderValue {
+        target: target.to_owned(),
+        params: params
+            .iter()
+            .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+            .collect(),
+    }
+}
+
+#[test]
+fn link_header_single_value() {
+    assert_eq!(
+        parse_link_header("</style.css>; rel=preload; as=style"),
+        vec![link("/style.css", &[("rel", "preload"), ("as", "style")])]
+    );
+}
+
+#[test]
+fn link_header_multiple_values() {
+    let links = parse_link_header(
+        "</font.woff2>; rel=preload; as=font; crossorigin, \
+         <https://cdn.example/>; rel=preconnect,</app.mjs>;rel=modulepreload",
+    );
+    assert_eq!(
+        links,
+        vec![
+            link(
+                "/font.woff2",
+                &[("rel", "preload"), ("as", "font"), ("crossorigin", "")]
+            ),
+            link("https://cdn.example/", &[("rel", "preconnect")]),
+            link("/app.mjs", &[("rel", "modulepreload")]),
+        ]
+    );
+    assert!(links[0].has_rel("preload"));
+    assert_eq!(links[0].param("as"), Some("font"));
+    assert!(links[2].has_rel("modulepreload"));
+}
+
+#[test]
+fn link_header_quoted_values() {
+    let links = parse_link_header(r#"</a,b.css>; REL="preload stylesheet"; title="a \"b\", c""#);
+    assert_eq!(
+        links,
+        vec![link(
+            "/a,b.css",
+            &[("rel", "preload stylesheet"), ("title", "a \"b\", c")]
+        )]
+    );
+    assert!(links[0].has_rel("preload"));
+    assert!(links[0].has_rel("stylesheet"));
+    assert!(!links[0].has_rel("prefetch"));
+}
+
+#[test]
+fn link_header_first_param_wins() {
+    let links = parse_link_header("</a.css>; rel=preload; rel=prefetch");
+    assert_eq!(links[0].param("rel"), Some("preload"));
+    assert!(!links[0].has_rel("prefetch"));
+}
+
+#[test]
+fn link_header_skips_malformed_values() {
+    assert_eq!(
+        parse_link_header("/no-brackets.css; rel=preload, </ok.css>; rel=preload; as=style"),
+        vec![link("/ok.css", &[("rel", "preload"), ("as", "style")])]
+    );
+    assert_eq!(parse_link_header(r#"</a.css>; rel="preload"#), vec![]);
+    assert_eq!(parse_link_header(""), vec![]);
+}
+
+#[test]
+fn script_prefetch_carries_fetch_metadata() {
+    let request = with_fetch_metadata(script_request(None, None));
+    assert_eq!(request.headers.get("sec-fetch-dest").unwrap(), "script");
+    assert_eq!(request.headers.get("sec-fetch-mode").unwrap(), "no-cors");
+    assert_eq!(request.headers.get("sec-fetch-site").unwrap(), "same-site");
+    assert!(request.headers.get("sec-fetch-user").is_none());
+
+    let request = with_fetch_metadata(script_request(Some("anonymous"), None));
+    assert_eq!(request.headers.get("sec-fetch-mode").unwrap(), "cors");
+}
+
+#[test]
+fn fetch_metadata_site_compares_origins() {
+    let origin = ServoUrl::parse("https://example.com/").unwrap().origin();
+    let request = |url: &str| {
+        with_fetch_metadata(
+            RequestBuilder::new(ServoUrl::parse(url).unwrap(), Referrer::NoReferrer)
+                .origin(origin.clone()),
+        )
+    };
+    let site = |url: &str| request(url).headers.get("sec-fetch-site").cloned().unwrap();
+    assert_eq!(site("https://example.com/a.css"), "same-origin");
+    assert_eq!(site("https://static.example.com/a.css"), "same-site");
+    assert_eq!(site("https://example.com:8443/a.css"), "same-site");
+    assert_eq!(site("https://example.org/a.css"), "cross-site");
+    assert_eq!(
+        request("https://example.com/")
+            .headers
+            .get("sec-fetch-dest")
+            .unwrap(),
+        "empty"
+    );
+}
+
+#[test]
+fn fetch_metadata_requires_a_potentially_trustworthy_url() {
+    let request = with_fetch_metadata(RequestBuilder::new(
+        ServoUrl::parse("http://example.com/app.js").unwrap(),
+        Referrer::NoReferrer,
+    ));
+    assert!(request.headers.get("sec-fetch-dest").is_none());
+}
+
+#[test]
+fn prefetch_budget_caps_fetches() {
+    let (core_sender, core_receiver) = ipc::channel().unwrap();
+    let (storage_sender, _storage_receiver) = ipc::channel().unwrap();
+    let mut tokenizer = Tokenizer::for_url(
+        ServoUrl::parse("https://example.com/").unwrap(),
+        ResourceThreads::new(core_sender, storage_sender),
+        3,
+    );
+    let mut input = BufferQueue::new();
+    input.push_back(StrTendril::from(
+        "<link rel=stylesheet href=a.css><link rel=preload href=b.css as=style>\
+         <img src=c.png><script src=d.js></script><link rel=stylesheet href=e.css>",
+    ));
+    tokenizer.rescan(&mut input);
+
+    let mut fetched = vec![];
+    while let Ok(msg) = core_receiver.try_recv() {
+        if let CoreResourceMsg::Fetch(request, _) = msg {
+            fetched.push(request.url.path().to_owned());
+        }
+    }
+    assert_eq!(fetched, vec!["/a.css", "/b.css", "/c.png"]);
+    assert_eq!(tokenizer.fetched_urls().len(), 3);
+}
+
+#[test]
+fn cancelling_tokenizer_fetches_spares_adopted_urls() {
+    let (core_sender, core_receiver) = ipc::channel().unwrap();
+    let (storage_sender, _storage_receiver) = ipc::channel().unwrap();
+    let mut tokenizer = Tokenizer::for_url(
+        ServoUrl::parse("https://example.com/").unwrap(),
+        ResourceThreads::new(core_sender, storage_sender),
+        0,
+    );
+    let mut input = BufferQueue::new();
+    input.push_back(StrTendril::from(
+        "<link rel=stylesheet href=style.css><script src=script.js></script>",
+    ));
+    tokenizer.rescan(&mut input);
+    tokenizer.adopt_url(&ServoUrl::parse("https://example.com/style.css").unwrap());
+    tokenizer.cancel_pending_fetches();
+
+    let mut cancelled = vec![];
+    while let Ok(msg) = core_receiver.try_recv() {
+        if let CoreResourceMsg::Fetch(request, FetchChannels::Prefetch(Some(cancel), _)) = msg {
+            if cancel.try_recv().is_ok() {
+                cancelled.push(request.url.path().to_owned());
+            }
+        }
+    }
+    assert_eq!(cancelled, vec!["/script.js"]);
+}
+
+#[test]
+fn prefetch_budget_without_cap_is_unlimited() {
+    for max in [0, -1] {
+        let mut budget = PrefetchBudget::new(max);
+        assert!((0..1000).all(|_| budget.spend()));
+    }
+}
+
+#[test]
+fn aborting_cancels_prefetches_not_adopted() {
+    let style = ServoUrl::parse("https://example.com/style.css").unwrap();
+    let script = ServoUrl::parse("https://example.com/script.js").unwrap();
+    let mut pending = PendingPrefetches::default();
+    let (style_cancel, _) = pending.track(style.clone()).unwrap();
+    let (script_cancel, _) = pending.track(script).unwrap();
+    pending.adopt(&style);
+    pending.cancel();
+    assert!(style_cancel.try_recv().is_err());
+    assert!(script_cancel.try_recv().is_ok());
+}
+
+#[test]
+fn completed_prefetches_are_no_longer_tracked() {
+    let style = ServoUrl::parse("https://example.com/style.css").unwrap();
+    let script = ServoUrl::parse("https://example.com/script.js").unwrap();
+    let mut pending = PendingPrefetches::default();
+    let (style_cancel, style_done) = pending.track(style.clone()).unwrap();
+    let (script_cancel, _) = pending.track(script.clone()).unwrap();
+    assert!(pending.is_tracked(&style));
+    style_done.send(style.clone()).unwrap();
+    assert!(!pending.is_tracked(&style));
+    assert!(pending.is_tracked(&script));
+    pending.cancel();
+    assert!(style_cancel.try_recv().is_err());
+    assert!(script_cancel.try_recv().is_ok());
+}
+
+#[test]
+fn tokenizer_prefetches_report_being_done() {
+    let (core_sender, core_receiver) = ipc::channel().unwrap();
+    let (storage_sender, _storage_receiver) = ipc::channel().unwrap();
+    let mut tokenizer = Tokenizer::for_url(
+        ServoUrl::parse("https://example.com/").unwrap(),
+        ResourceThreads::new(core_sender, storage_sender),
+        0,
+    );
+    let mut input = BufferQueue::new();
+    input.push_back(StrTendril::from(
+        "<link rel=stylesheet href=style.css><script src=script.js></script>",
+    ));
+    tokenizer.rescan(&mut input);
+
+    // Complete the fetches as the resource thread would.
+    while let Ok(msg) = core_receiver.try_recv() {
+        if let CoreResourceMsg::Fetch(request, FetchChannels::Prefetch(_, Some(done_chan))) = msg {
+            done_chan.send(request.url.clone()).unwrap();
+        }
+    }
+    let style = ServoUrl::parse("https://example.com/style.css").unwrap();
+    let script = ServoUrl::parse("https://example.com/script.js").unwrap();
+    assert!(!tokenizer.is_tracking_fetch(&style));
+    assert!(!tokenizer.is_tracking_fetch(&script));
+}
+
+#[test]
+fn speculation_rules_list_prefetch_urls() {
+    let base = ServoUrl::parse("https://example.com/dir/page.html").unwrap();
+    let rules = parse_speculation_rules(
+        r#"{
+            "prefetch": [
+                {"source": "list", "urls": ["next.html", "/other.html"]},
+                {"urls": ["https://cdn.example.com/a.html"], "referrer_policy": "no-referrer"}
+            ]
+        }"#,
+        &base,
+    )
+    .unwrap();
+    assert_eq!(
+        rules.prefetch,
+        vec![
+            SpeculationRule {
+                urls: vec![
+                    ServoUrl::parse("https://example.com/dir/next.html").unwrap(),
+                    ServoUrl::parse("https://example.com/other.html").unwrap(),
+                ],
+                referrer_policy: None,
+            },
+            SpeculationRule {
+                urls: vec![ServoUrl::parse("https://cdn.example.com/a.html").unwrap()],
+                referrer_policy: Some(ReferrerPolicy::NoReferrer),
+            },
+        ]
+    );
+    assert!(rules.prerender.is_empty());
+}
+
+#[test]
+fn speculation_rules_skip_unsupported_rules() {
+    let base = ServoUrl::parse("https://example.com/").unwrap();
+    let rules = parse_speculation_rules(
+        r#"{
+            "prefetch": [
+                {"source": "document", "where": {"href_matches": "/*"}},
+                {"urls": ["a.html"], "requires": ["anonymous-client-ip-when-cross-origin"]},
+                {"urls": ["b.html", 1]},
+                {"urls": ["c.html", "mailto:someone@example.com"]}
+            ],
+            "prerender": [{"urls": ["d.html"]}]
+        }"#,
+        &base,
+    )
+    .unwrap();
+    let urls = |rules: &[SpeculationRule]| -> Vec<String> {
+        rules
+            .iter()
+            .flat_map(|rule| rule.urls.iter().map(|url| url.path().to_owned()))
+            .collect()
+    };
+    assert_eq!(urls(&rules.prefetch), vec!["/c.html"]);
+    assert_eq!(urls(&rules.prerender), vec!["/d.html"]);
+}
+
+#[test]
+fn invalid_speculation_rules_are_ignored() {
+    let base = ServoUrl::parse("https://example.com/").unwrap();
+    assert_eq!(parse_speculation_rules("", &base), None);
+    assert_eq!(parse_speculation_rules("[]", &base), None);
+    assert_eq!(
+        parse_speculation_rules("{}", &base).unwrap(),
+        Default::default()
+    );
+}
+
+#[test]
+fn link_preload_of_module_scripts_is_cors() {
+    let links = parse_link_header(
+        "</app.js>; rel=modulepreload, </data.json>; rel=modulepreload; as=fetch",
+    );
+    assert_eq!(
+        link_preload(&links[0]),
+        Some((Destination::Script, Some(CorsSettings::Anonymous)))
+    );
+    assert_eq!(link_preload(&links[1]), None);
+}
+
+#[test]
+fn early_hints_preload_for_the_navigation() {
+    let url = ServoUrl::parse("https://example.com/articles/42.html").unwrap();
+    let links = parse_link_header(
+        "</style.css>; rel=preload; as=style, \
+         <https://fonts.example.net/a.woff2>; rel=preload; as=font, \
+         <https://cdn.example.net>; rel=preconnect, \
+         </next.html>; rel=prefetch, \
+         </unknown>; rel=preload; as=fetch",
+    );
+    let requests = early_hint_requests(&links, &url, TEST_PIPELINE_ID);
+    let preloads: Vec<_> = requests
+        .iter()
+        .map(|request| (request.url.as_str(), request.destination))
+        .collect();
+    assert_eq!(
+        preloads,
+        vec![
+            ("https://example.com/style.css", Destination::Style),
+            ("https://fonts.example.net/a.woff2", Destination::Font),
+        ]
+    );
+    assert_eq!(requests[0].origin, url.origin());
+    assert_eq!(requests[0].pipeline_id, Some(TEST_PIPELINE_ID));
+    assert_eq!(requests[0].referrer, Referrer::ReferrerUrl(url.clone()));
+    assert_eq!(requests[1].mode, RequestMode::CorsMode);
+}
+
+fn fetched_paths(core_receiver: &ipc::IpcReceiver<CoreResourceMsg>) -> Vec<String> {
+    let mut fetched = vec![];
+    while let Ok(msg) = core_receiver.try_recv() {
+        if let CoreResourceMsg::Fetch(request, _) = msg {
+            fetched.push(request.url.path().to_owned());
+        }
+    }
+    fetched
+}
+
+/// Receives `html` from the network, which goes to both the main parser and
+/// the prefetcher, while input written by scripts only goes to the main parser.
+fn receive(network_input: &mut BufferQueue, prefetch_input: &mut BufferQueue, html: &str) {
+    network_input.push_back(StrTendril::from(html));
+    prefetch_input.push_back(StrTendril::from(html));
+}
+
+#[test]
+fn paused_prefetch_input_is_fed_in_order() {
+    let (core_sender, core_receiver) = ipc::channel().unwrap();
+    let (storage_sender, _storage_receiver) = ipc::channel().unwrap();
+    let mut tokenizer = Tokenizer::for_url(
+        ServoUrl::parse("https://example.com/").unwrap(),
+        ResourceThreads::new(core_sender, storage_sender),
+        0,
+    );
+    let mut network_input = BufferQueue::new();
+    let mut script_input = BufferQueue::new();
+    let mut prefetch_input = BufferQueue::new();
+
+    receive(
+        &mut network_input,
+        &mut prefetch_input,
+        "<script src=blocking.js></script><img src=a.png>",
+    );
+    assert!(feed_prefetch_input(
+        &mut tokenizer,
+        &mut prefetch_input,
+        false,
+        true
+    ));
+    assert_eq!(fetched_paths(&core_receiver), vec!["/a.png"]);
+
+    // The parser is blocked on the script, and more input arrives from the
+    // network and from the script in turns.
+    receive(&mut network_input, &mut prefetch_input, "<img src=b.png>");
+    assert!(!feed_prefetch_input(
+        &mut tokenizer,
+        &mut prefetch_input,
+        true,
+        true
+    ));
+    script_input.push_back(StrTendril::from("<img src=written.png>"));
+    receive(
+        &mut network_input,
+        &mut prefetch_input,
+        "<link rel=stylesheet href=c.css>",
+    );
+    assert!(!feed_prefetch_input(
+        &mut tokenizer,
+        &mut prefetch_input,
+        true,
+        true
+    ));
+    assert!(fetched_paths(&core_receiver).is_empty());
+
+    // Once the parser resumes, the input kept is prefetched in the order it
+    // was received, before the written input goes ahead of the network input.
+    assert!(feed_prefetch_input(
+        &mut tokenizer,
+        &mut prefetch_input,
+        false,
+        true
+    ));
+    assert!(prefetch_input.is_empty());
+    assert_eq!(fetched_paths(&core_receiver), vec!["/b.png", "/c.css"]);
+    prepend_script_input(&mut script_input, &mut network_input);
+    assert_eq!(
+        network_input.pop_front().as_deref(),
+        Some("<img src=written.png>")
+    );
+}
+
+#[test]
+fn prefetch_input_is_fed_while_suspended_without_pausing() {
+    let (core_sender, core_receiver) = ipc::channel().unwrap();
+    let (storage_sender, _storage_receiver) = ipc::channel().unwrap();
+    let mut tokenizer = Tokenizer::for_url(
+        ServoUrl::parse("https://example.com/").unwrap(),
+        ResourceThreads::new(core_sender, storage_sender),
+        0,
+    );
+    let mut input = BufferQueue::new();
+    input.push_back(StrTendril::from("<script src=blocking.js></script>"));
+    assert!(feed_prefetch_input(
+        &mut tokenizer,
+        &mut input,
+        false,
+        false
+    ));
+    input.push_back(StrTendril::from("<img src=a.png>"));
+    input.push_back(StrTendril::from("<img src=b.png>"));
+    assert!(feed_prefetch_input(&mut tokenizer, &mut input, true, false));
+    assert_eq!(fetched_paths(&core_receiver), vec!["/a.png", "/b.png"]);
+}
@@ -0,0 +1,1444 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use mime::Mime;
+use script::test::servoparser::{
+    cap_attributes, close_action, compact_input, consumed_chars, content_location_url,
+    content_type_encoding, decodes_identically, doctype_mismatches_document,
+    file_extension_content_type, font_specimen_page, fragment_chunk_size, icon_entries,
+    is_blocked_element, is_font_file, is_legacy_doctype, is_mixed_content_navigation,
+    is_no_content_status, is_nosniff, is_obsolete_element, is_parsed_as_html,
+    is_partial_xml_declaration, is_render_blocking_element, is_supported_image_mime_type,
+    meta_content_encoding, mixed_content_page, no_content_type_page, parse_clear_site_data,
+    parse_color_scheme, parse_retry_after, pdf_viewer_page, prepend_script_input,
+    sniff_content_encoding, strip_bom_at_start_of_input, strip_leading_bom, substitute_html,
+    take_feed_batch, undecoded_suffix_len, unknown_content_type_page, unsupported_image_page,
+    uses_async_html_tokenizer, xml_declaration_encoding, Attribute, BufferQueue, BufferedInputSize,
+    ClearSiteData, CloseAction, ElementAttribute, HtmlTokenizer, IconEntry, LineTracker, LocalName,
+    Namespace, NetworkDecoder, ParseBudget, ParseBudgetExceeded, QualName, RawKind, SourceViewer,
+    StrTendril, SupportedColorSchemes, TagKind, TextExtractor, Token, TokenSink, TokenSinkResult,
+    TokenizerResult, UnknownContentTypeHandling,
+};
+use script::test::DOMString;
+use servo_url::{ImmutableOrigin, ServoUrl};
+use test::{self, Bencher};
+
+#[test]
+fn replacement_encoding_decodes_to_a_single_replacement_character() {
+    let mut decoder = NetworkDecoder::new(encoding_rs::REPLACEMENT);
+    let mut decoded = String::new();
+    decoded.push_str(&decoder.decode(b"<script>alert(1)</script>".to_vec()));
+    decoded.push_str(&decoder.decode(b"<p>more".to_vec()));
+    decoded.push_str(&decoder.finish());
+    assert_eq!(decoded, "\u{FFFD}");
+}
+
+#[test]
+fn content_type_charset() {
+    let content_type: Mime = "text/plain; charset=windows-1251".parse().unwrap();
+    assert_eq!(
+        content_type_encoding(&content_type),
+        Some(encoding_rs::WINDOWS_1251)
+    );
+    let content_type: Mime = "text/plain; charset=\"koi8-r\"".parse().unwrap();
+    assert_eq!(
+        content_type_encoding(&content_type),
+        Some(encoding_rs::KOI8_R)
+    );
+}
+
+#[test]
+fn content_type_without_known_charset() {
+    let content_type: Mime = "text/plain".parse().unwrap();
+    assert_eq!(content_type_encoding(&content_type), None);
+    let content_type: Mime = "text/plain; charset=not-a-charset".parse().unwrap();
+    assert_eq!(content_type_encoding(&content_type), None);
+}
+
+#[test]
+fn content_type_charset_of_replacement_label() {
+    let content_type: Mime = "text/plain; charset=iso-2022-kr".parse().unwrap();
+    assert_eq!(
+        content_type_encoding(&content_type),
+        Some(encoding_rs::REPLACEMENT)
+    );
+}
+
+#[test]
+fn ascii_decodes_identically() {
+    let bytes = b"<!DOCTYPE html><meta charset=windows-1251>";
+    assert!(decodes_identically(
+        bytes,
+        encoding_rs::UTF_8,
+        encoding_rs::WINDOWS_1251
+    ));
+    assert!(decodes_identically(
+        bytes,
+        encoding_rs::WINDOWS_1252,
+        encoding_rs::SHIFT_JIS
+    ));
+}
+
+#[test]
+fn non_ascii_decodes_differently() {
+    let bytes = "<title>caf\u{e9}</title>".as_bytes();
+    assert!(!decodes_identically(
+        bytes,
+        encoding_rs::UTF_8,
+        encoding_rs::WINDOWS_1252
+    ));
+    assert!(decodes_identically(
+        bytes,
+        encoding_rs::UTF_8,
+        encoding_rs::UTF_8
+    ));
+}
+
+#[test]
+fn malformed_input_decodes_differently() {
+    assert!(!decodes_identically(
+        b"<title>\xff</title>",
+        encoding_rs::UTF_8,
+        encoding_rs::EUC_KR
+    ));
+}
+
+#[test]
+fn meta_content_charset() {
+    assert_eq!(
+        meta_content_encoding("text/html; charset=windows-1251"),
+        Some(encoding_rs::WINDOWS_1251)
+    );
+    assert_eq!(
+        meta_content_encoding("text/html;CHARSET = \"koi8-r\" ; foo"),
+        Some(encoding_rs::KOI8_R)
+    );
+    assert_eq!(
+        meta_content_encoding("text/html; charset='shift_jis'"),
+        Some(encoding_rs::SHIFT_JIS)
+    );
+}
+
+#[test]
+fn meta_content_charset_after_charset_without_value() {
+    assert_eq!(
+        meta_content_encoding("charset; charset=utf-8"),
+        Some(encoding_rs::UTF_8)
+    );
+}
+
+#[test]
+fn meta_content_without_charset() {
+    assert_eq!(meta_content_encoding("text/html"), None);
+    assert_eq!(meta_content_encoding("text/html; charset="), None);
+    assert_eq!(meta_content_encoding("text/html; charset=\"utf-8"), None);
+    assert_eq!(
+        meta_content_encoding("text/html; charset=not-a-charset"),
+        None
+    );
+}
+
+#[test]
+fn xml_declaration_latin1() {
+    let document = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>\n<root>caf\xe9</root>";
+    assert_eq!(
+        xml_declaration_encoding(document),
+        Some(encoding_rs::WINDOWS_1252)
+    );
+}
+
+#[test]
+fn xml_declaration_single_quotes_and_whitespace() {
+    let document = b"<?xml version='1.0' encoding = 'Shift_JIS' ?><root/>";
+    assert_eq!(
+        xml_declaration_encoding(document),
+        Some(encoding_rs::SHIFT_JIS)
+    );
+}
+
+#[test]
+fn xml_declaration_without_encoding() {
+    assert_eq!(
+        xml_declaration_encoding(b"<?xml version=\"1.0\"?><root/>"),
+        None
+    );
+    assert_eq!(xml_declaration_encoding(b"<root/>"), None);
+    assert_eq!(
+        xml_declaration_encoding(b"<?xml-stylesheet href=\"a.css\"?>"),
+        None
+    );
+}
+
+#[test]
+fn xml_declaration_ignored_encodings() {
+    // Incomplete declarations, unknown labels and UTF-16 are all ignored.
+    assert_eq!(
+        xml_declaration_encoding(b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\""),
+        None
+    );
+    assert_eq!(
+        xml_declaration_encoding(b"<?xml version=\"1.0\" encoding=\"bogus\"?>"),
+        None
+    );
+    assert_eq!(
+        xml_declaration_encoding(b"<?xml version=\"1.0\" encoding=\"UTF-16\"?>"),
+        None
+    );
+}
+
+#[test]
+fn xml_declaration_split_across_chunks() {
+    // The first chunk ends in the middle of the declaration, so it's held
+    // back until the second one completes it.
+    let mut input = b"<?xml version=\"1.0\" enc".to_vec();
+    assert!(is_partial_xml_declaration(&input));
+    assert_eq!(xml_declaration_encoding(&input), None);
+    input.extend_from_slice(b"oding=\"ISO-8859-1\"?>\n<root>caf\xe9</root>");
+    assert!(!is_partial_xml_declaration(&input));
+    assert_eq!(
+        xml_declaration_encoding(&input),
+        Some(encoding_rs::WINDOWS_1252)
+    );
+}
+
+#[test]
+fn xml_declaration_prefixes_are_partial() {
+    assert!(is_partial_xml_declaration(b""));
+    assert!(is_partial_xml_declaration(b"<"));
+    assert!(is_partial_xml_declaration(b"<?xm"));
+    assert!(is_partial_xml_declaration(b"<?xml"));
+    assert!(is_partial_xml_declaration(b"<?xml version=\"1.0\"?"));
+    assert!(!is_partial_xml_declaration(b"<root/>"));
+    assert!(!is_partial_xml_declaration(
+        b"<?xml-stylesheet href=\"a.css\"?>"
+    ));
+    assert!(!is_partial_xml_declaration(b"\xef\xbb\xbf<?xml"));
+}
+
+#[test]
+fn xhtml_doctype_mismatches_html_document() {
+    assert!(doctype_mismatches_document(
+        true,
+        "-//W3C//DTD XHTML 1.0 Strict//EN",
+        "http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd"
+    ));
+    assert!(!doctype_mismatches_document(
+        false,
+        "-//W3C//DTD XHTML 1.0 Strict//EN",
+        "http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd"
+    ));
+}
+
+#[test]
+fn html4_doctype_mismatches_xml_document() {
+    assert!(doctype_mismatches_document(
+        false,
+        "-//w3c//dtd html 4.01//en",
+        ""
+    ));
+    assert!(!doctype_mismatches_document(
+        true,
+        "-//W3C//DTD HTML 4.01//EN",
+        "http://www.w3.org/TR/html4/strict.dtd"
+    ));
+}
+
+#[test]
+fn html5_doctype_matches_any_document() {
+    assert!(!doctype_mismatches_document(true, "", ""));
+    assert!(!doctype_mismatches_document(false, "", ""));
+}
+
+fn file_content_type(url: &str, content_type: Option<&str>) -> Option<String> {
+    let url = ServoUrl::parse(url).unwrap();
+    let content_type: Option<Mime> = content_type.map(|content_type| content_type.parse().unwrap());
+    file_extension_content_type(&url, content_type.as_ref()).map(|mime| mime.to_string())
+}
+
+#[test]
+fn file_extension_gives_xhtml_and_html_content_types() {
+    assert_eq!(
+        file_content_type("file:///tmp/page.xhtml", None).as_deref(),
+        Some("application/xhtml+xml")
+    );
+    assert_eq!(
+        file_content_type("file:///tmp/page.XHT", Some("application/octet-stream")).as_deref(),
+        Some("application/xhtml+xml")
+    );
+    assert_eq!(
+        file_content_type("file:///tmp/page.htm", None).as_deref(),
+        Some("text/html")
+    );
+}
+
+#[test]
+fn file_extension_is_ignored_for_known_content_types() {
+    assert_eq!(
+        file_content_type("file:///tmp/page.xhtml", Some("text/plain")),
+        None
+    );
+    assert_eq!(file_content_type("file:///tmp/page.txt", None), None);
+    assert_eq!(file_content_type("file:///tmp/xhtml", None), None);
+}
+
+#[test]
+fn file_extension_is_ignored_for_the_network() {
+    assert_eq!(
+        file_content_type("https://example.com/page.xhtml", None),
+        None
+    );
+}
+
+#[test]
+fn attributes_beyond_the_maximum_are_dropped() {
+    let mut attrs: Vec<usize> = (0..1_000_000).collect();
+    assert_eq!(cap_attributes(&mut attrs, 0, 10_000), 990_000);
+    assert_eq!(attrs.len(), 10_000);
+    assert_eq!(attrs.last(), Some(&9_999));
+}
+
+#[test]
+fn attributes_count_towards_the_maximum_with_existing_ones() {
+    let mut attrs = vec!["a", "b", "c"];
+    assert_eq!(cap_attributes(&mut attrs, 8, 10), 1);
+    assert_eq!(attrs, ["a", "b"]);
+
+    let mut attrs = vec!["a"];
+    assert_eq!(cap_attributes(&mut attrs, 12, 10), 1);
+    assert!(attrs.is_empty());
+}
+
+#[test]
+fn attributes_within_the_maximum_are_kept() {
+    let mut attrs = vec!["a", "b"];
+    assert_eq!(cap_attributes(&mut attrs, 0, 10), 0);
+    assert_eq!(attrs, ["a", "b"]);
+}
+
+#[test]
+fn leading_bom_is_stripped_from_strings() {
+    assert_eq!(strip_leading_bom("\u{FEFF}<p>hi</p>"), "<p>hi</p>");
+    assert_eq!(strip_leading_bom("\u{FEFF}"), "");
+    assert_eq!(strip_leading_bom("<p>hi</p>"), "<p>hi</p>");
+    assert_eq!(strip_leading_bom(""), "");
+}
+
+#[test]
+fn only_one_leading_bom_is_stripped() {
+    assert_eq!(strip_leading_bom("\u{FEFF}\u{FEFF}<p>"), "\u{FEFF}<p>");
+    assert_eq!(strip_leading_bom("<p>\u{FEFF}</p>"), "<p>\u{FEFF}</p>");
+}
+
+/// Strips the BOM from each of `chunks`, in order, as the parser does for the
+/// input it receives.
+fn strip_bom_from_chunks(chunks: &[&str]) -> Vec<String> {
+    let mut at_start = true;
+    chunks
+        .iter()
+        .map(|chunk| {
+            let mut chunk = StrTendril::from(*chunk);
+            strip_bom_at_start_of_input(&mut chunk, &mut at_start);
+            String::from(&*chunk)
+        })
+        .collect()
+}
+
+#[test]
+fn document_write_input_drops_one_leading_bom() {
+    // document.write("\u{FEFF}<p>", "\u{FEFF}") on a new document.
+    assert_eq!(
+        strip_bom_from_chunks(&["\u{FEFF}<p>", "\u{FEFF}"]),
+        vec!["<p>", "\u{FEFF}"]
+    );
+    // Empty strings written first don't start the input.
+    assert_eq!(strip_bom_from_chunks(&["", "\u{FEFF}<p>"]), vec!["", "<p>"]);
+}
+
+#[test]
+fn synthesized_input_drops_one_leading_bom() {
+    // A synthesized page, such as the source viewer, pushed in pieces.
+    assert_eq!(
+        strip_bom_from_chunks(&["\u{FEFF}", "\u{FEFF}<pre>", "</pre>"]),
+        vec!["", "\u{FEFF}<pre>", "</pre>"]
+    );
+    assert_eq!(
+        strip_bom_from_chunks(&["<html>\u{FEFF}", "\u{FEFF}</html>"]),
+        vec!["<html>\u{FEFF}", "\u{FEFF}</html>"]
+    );
+}
+
+#[test]
+fn network_decoder_drops_the_bom() {
+    let mut decoder = NetworkDecoder::new(encoding_rs::UTF_8);
+    assert_eq!(
+        &*decoder.decode(b"\xEF\xBB\xBF<p>hi</p>".to_vec()),
+        "<p>hi</p>"
+    );
+
+    // A BOM split between chunks.
+    let mut decoder = NetworkDecoder::new(encoding_rs::UTF_8);
+    assert_eq!(&*decoder.decode(b"\xEF\xBB".to_vec()), "");
+    assert_eq!(&*decoder.decode(b"\xBF<p>".to_vec()), "<p>");
+
+    // Only the first one is a BOM.
+    let mut decoder = NetworkDecoder::new(encoding_rs::UTF_8);
+    let text = decoder.decode(b"\xEF\xBB\xBF\xEF\xBB\xBF<p>".to_vec());
+    assert_eq!(&*text, "\u{FEFF}<p>");
+}
+
+#[test]
+fn network_decoder_bom_overrides_the_encoding() {
+    let mut decoder = NetworkDecoder::new(encoding_rs::WINDOWS_1252);
+    assert_eq!(
+        &*decoder.decode(b"\xEF\xBB\xBFcaf\xC3\xA9".to_vec()),
+        "caf\u{e9}"
+    );
+
+    let mut decoder = NetworkDecoder::new(encoding_rs::WINDOWS_1252);
+    assert_eq!(&*decoder.decode(b"\xFF\xFEh\x00i\x00".to_vec()), "hi");
+}
+
+#[test]
+fn utf16_lone_surrogates_decode_to_replacement_characters() {
+    // A BOM, then "a", a lone high surrogate, "b", a lone low surrogate, "c".
+    let mut decoder = NetworkDecoder::new(encoding_rs::UTF_16LE);
+    let text = decoder.decode(b"\xFF\xFEa\x00\x00\xD8b\x00\x00\xDCc\x00".to_vec());
+    assert_eq!(&*text, "a\u{FFFD}b\u{FFFD}c");
+    assert_eq!(&*decoder.finish(), "");
+
+    let mut decoder = NetworkDecoder::new(encoding_rs::UTF_16BE);
+    let text = decoder.decode(b"\xFE\xFF\x00a\xD8\x00\x00b".to_vec());
+    assert_eq!(&*text, "a\u{FFFD}b");
+}
+
+#[test]
+fn utf16_surrogate_pairs_split_between_chunks() {
+    // U+1F600, whose surrogates are D83D and DE00.
+    let mut decoder = NetworkDecoder::new(encoding_rs::UTF_16LE);
+    assert_eq!(&*decoder.decode(b"\xFF\xFE\x3D\xD8".to_vec()), "");
+    assert_eq!(&*decoder.decode(b"\x00\xDE".to_vec()), "\u{1F600}");
+    assert_eq!(&*decoder.finish(), "");
+}
+
+#[test]
+fn utf16_high_surrogate_at_the_end_of_the_input() {
+    let mut decoder = NetworkDecoder::new(encoding_rs::UTF_16LE);
+    assert_eq!(&*decoder.decode(b"\xFF\xFEa\x00\x3D\xD8".to_vec()), "a");
+    assert_eq!(&*decoder.finish(), "\u{FFFD}");
+}
+
+#[test]
+fn undecoded_utf8_suffix() {
+    let len = |tail: &[u8]| undecoded_suffix_len(encoding_rs::UTF_8, tail, tail.len());
+    assert_eq!(len(b"ab\xC3"), Some(1));
+    assert_eq!(len(b"\xF0\x9F\x98"), Some(3));
+    assert_eq!(len(b"\xC3\xA9"), Some(0));
+    assert_eq!(len(b"\x9F\x98\x80"), Some(0));
+    // E0 80 is never the start of a valid sequence, so is already replaced.
+    assert_eq!(len(b"\xE0\x80"), Some(0));
+}
+
+#[test]
+fn undecoded_utf16_suffix() {
+    let len = |tail: &[u8], input_len| undecoded_suffix_len(encoding_rs::UTF_16LE, tail, input_len);
+    assert_eq!(len(b"a\x00", 4), Some(0));
+    assert_eq!(len(b"\x00a", 5), Some(1));
+    assert_eq!(len(b"a\x00\x3D\xD8", 6), Some(2));
+    assert_eq!(len(b"\x3D\xD8\x00", 7), Some(3));
+    assert_eq!(len(b"\x00\xDE", 8), Some(0));
+}
+
+#[test]
+fn undecoded_suffix_of_other_encodings() {
+    assert_eq!(
+        undecoded_suffix_len(encoding_rs::WINDOWS_1252, b"\xE9", 1),
+        Some(0)
+    );
+    assert_eq!(
+        undecoded_suffix_len(encoding_rs::SHIFT_JIS, b"\x82", 1),
+        None
+    );
+}
+
+#[test]
+fn decoder_restored_from_undecoded_bytes() {
+    let mut decoder = NetworkDecoder::new(encoding_rs::UTF_8);
+    assert_eq!(&*decoder.decode(b"caf\xC3".to_vec()), "caf");
+    let undecoded_bytes = decoder.undecoded_bytes().unwrap().to_vec();
+    assert_eq!(undecoded_bytes, b"\xC3");
+
+    let mut decoder = NetworkDecoder::restore(encoding_rs::UTF_8, &undecoded_bytes);
+    assert_eq!(&*decoder.decode(b"\xA9!".to_vec()), "\u{E9}!");
+    assert_eq!(&*decoder.finish(), "");
+}
+
+#[test]
+fn image_viewer_supported_formats() {
+    let supported = |mime: &str| is_supported_image_mime_type(&mime.parse::<Mime>().unwrap());
+    assert!(supported("image/png"));
+    assert!(supported("image/webp"));
+    assert!(supported("image/jpeg"));
+    assert!(!supported("image/avif"));
+    assert!(!supported("image/jxl"));
+    assert!(!supported("text/html"));
+}
+
+#[test]
+fn mixed_content_navigations() {
+    let origin = |url: &str| ServoUrl::parse(url).unwrap().origin();
+    let url = |url: &str| ServoUrl::parse(url).unwrap();
+    let secure = origin("https://example.com/");
+    assert!(is_mixed_content_navigation(
+        &secure,
+        &url("http://example.org/")
+    ));
+    assert!(!is_mixed_content_navigation(
+        &secure,
+        &url("https://example.org/")
+    ));
+    assert!(!is_mixed_content_navigation(
+        &secure,
+        &url("http://localhost/")
+    ));
+    assert!(!is_mixed_content_navigation(
+        &origin("http://example.com/"),
+        &url("http://example.org/")
+    ));
+    assert!(!is_mixed_content_navigation(
+        &ImmutableOrigin::new_opaque(),
+        &url("http://example.org/")
+    ));
+}
+
+#[test]
+fn extracted_text_collapses_whitespace() {
+    let mut extractor = TextExtractor::default();
+    extractor.push("\n  Hello,\t");
+    extractor.push("  world");
+    extractor.push("!\r\n\x0C");
+    assert_eq!(extractor.text(), "Hello, world!");
+    extractor.push("\u{A0}again ");
+    assert_eq!(extractor.text(), "Hello, world! \u{A0}again");
+}
+
+#[test]
+fn extracted_text_of_whitespace_only() {
+    let mut extractor = TextExtractor::default();
+    extractor.push(" \n ");
+    assert_eq!(extractor.text(), "");
+}
+
+#[test]
+fn retry_after_delay_seconds() {
+    let now = SystemTime::now();
+    assert_eq!(
+        parse_retry_after("120", now),
+        Some(Duration::from_secs(120))
+    );
+    assert_eq!(parse_retry_after(" 0 ", now), Some(Duration::from_secs(0)));
+    assert_eq!(parse_retry_after("-5", now), None);
+    assert_eq!(parse_retry_after("1.5", now), None);
+    assert_eq!(parse_retry_after("", now), None);
+}
+
+#[test]
+fn retry_after_http_date() {
+    // Wed, 21 Oct 2015 07:28:00 GMT
+    let date = UNIX_EPOCH + Duration::from_secs(1445412480);
+    let value = "Wed, 21 Oct 2015 07:28:00 GMT";
+    assert_eq!(
+        parse_retry_after(value, date - Duration::from_secs(90)),
+        Some(Duration::from_secs(90))
+    );
+    assert_eq!(
+        parse_retry_after(value, date + Duration::from_secs(90)),
+        Some(Duration::from_secs(0))
+    );
+    assert_eq!(parse_retry_after("tomorrow", date), None);
+}
+
+#[test]
+fn sniffs_japanese_content() {
+    let shift_jis = b"<p>\x93\xFA\x96{\x8C\xEA\x82\xCC\x83e\x83L\x83X\x83g\x82\xC5\x82\xB7\x81B\
+        \x82\xB1\x82\xEA\x82\xCD\x95\xB6\x8E\x9A\x83R\x81[\x83h\x82\xCC\x94\xBB\x92\xE8\x82\xF0\
+        \x83e\x83X\x83g\x82\xB7\x82\xE9\x82\xBD\x82\xDF\x82\xCC\x95\xB6\x8F\xCD\x82\xC5\x81A\
+        \x82\xD0\x82\xE7\x82\xAA\x82\xC8\x82\xC6\x83J\x83^\x83J\x83i\x82\xC6\x8A\xBF\x8E\x9A\
+        \x82\xF0\x8A\xDC\x82\xF1\x82\xC5\x82\xA2\x82\xDC\x82\xB7\x81B</p>";
+    assert_eq!(
+        sniff_content_encoding(shift_jis, Some("jp"), true),
+        Some(encoding_rs::SHIFT_JIS)
+    );
+}
+
+#[test]
+fn sniffs_cyrillic_content() {
+    let windows_1251 =
+        b"<p>\xDD\xF2\xEE \xF2\xE5\xEA\xF1\xF2 \xED\xE0 \xF0\xF3\xF1\xF1\xEA\xEE\xEC \
+        \xFF\xE7\xFB\xEA\xE5, \xEA\xEE\xF2\xEE\xF0\xFB\xE9 \xED\xF3\xE6\xE5\xED \xE4\xEB\xFF \
+        \xEF\xF0\xEE\xE2\xE5\xF0\xEA\xE8 \xEE\xEF\xF0\xE5\xE4\xE5\xEB\xE5\xED\xE8\xFF \
+        \xEA\xEE\xE4\xE8\xF0\xEE\xE2\xEA\xE8 \xE4\xEE\xEA\xF3\xEC\xE5\xED\xF2\xE0 \xE1\xE5\xE7 \
+        \xEE\xE1\xFA\xFF\xE2\xEB\xE5\xED\xE8\xFF.</p>";
+    assert_eq!(
+        sniff_content_encoding(windows_1251, None, true),
+        Some(encoding_rs::WINDOWS_1251)
+    );
+}
+
+#[test]
+fn does_not_sniff_ascii_content() {
+    assert_eq!(
+        sniff_content_encoding(b"<p>Hello, world.</p>", None, true),
+        None
+    );
+}
+
+#[test]
+fn nosniff_header_values() {
+    assert!(is_nosniff("nosniff"));
+    assert!(is_nosniff(" NoSniff\t"));
+    assert!(is_nosniff("nosniff,foo"));
+    assert!(!is_nosniff("foo,nosniff"));
+    assert!(!is_nosniff(""));
+    assert!(!is_nosniff("sniff"));
+}
+
+#[test]
+fn nosniff_script_is_not_parsed_as_html() {
+    let javascript: Mime = "text/javascript".parse().unwrap();
+    assert!(!is_parsed_as_html(Some(&javascript), true));
+    assert!(!is_parsed_as_html(Some(&javascript), false));
+    assert!(!is_parsed_as_html(None, true));
+    assert!(is_parsed_as_html(None, false));
+    assert!(is_parsed_as_html(Some(&mime::TEXT_HTML), true));
+}
+
+#[test]
+fn written_input_gets_the_line_of_its_script() {
+    let mut lines = LineTracker::default();
+    assert_eq!(lines.set_tokenizer_line(3), 3);
+    // <script>document.write("<p>\n<p>\r\n<p>")</script>, on line 3.
+    lines.insert_script_input("<p>\n<p>\r\n<p>");
+    assert_eq!(lines.set_tokenizer_line(4), 3);
+    assert_eq!(lines.set_tokenizer_line(5), 3);
+    assert_eq!(lines.set_tokenizer_line(6), 4);
+    assert_eq!(lines.set_tokenizer_line(7), 5);
+}
+
+#[test]
+fn nested_and_later_writes_get_the_line_of_their_script() {
+    let mut lines = LineTracker::default();
+    assert_eq!(lines.set_tokenizer_line(2), 2);
+    lines.insert_script_input("<script>\n</script>\n");
+    assert_eq!(lines.set_tokenizer_line(3), 2);
+    // Written by the script written above.
+    lines.insert_script_input("\r\r");
+    assert_eq!(lines.set_tokenizer_line(5), 2);
+    assert_eq!(lines.set_tokenizer_line(6), 2);
+    assert_eq!(lines.set_tokenizer_line(7), 3);
+    // A later script, on line 3.
+    lines.insert_script_input("\n");
+    assert_eq!(lines.set_tokenizer_line(8), 3);
+    assert_eq!(lines.set_tokenizer_line(9), 4);
+}
+
+#[test]
+fn writes_without_newlines_keep_the_lines() {
+    let mut lines = LineTracker::default();
+    lines.insert_script_input("<p>");
+    assert_eq!(lines.set_tokenizer_line(1), 1);
+    assert_eq!(lines.set_tokenizer_line(2), 2);
+    assert_eq!(lines.set_tokenizer_line(u64::MAX), u64::MAX);
+}
+
+fn html_name(local: &str) -> QualName {
+    QualName::new(
+        None,
+        Namespace::from("http://www.w3.org/1999/xhtml"),
+        LocalName::from(local),
+    )
+}
+
+fn attribute(name: &str, value: &str) -> Attribute {
+    Attribute {
+        name: QualName::new(None, Namespace::from(""), LocalName::from(name)),
+        value: value.into(),
+    }
+}
+
+fn blocks_trackers(_: &QualName, attrs: &[Attribute]) -> bool {
+    attrs
+        .iter()
+        .any(|attr| &*attr.name.local == "src" && &*attr.value == "tracker")
+}
+
+#[test]
+fn blocks_tracker_script() {
+    let tracker = [attribute("src", "tracker")];
+    assert!(is_blocked_element(
+        Some(blocks_trackers),
+        &html_name("script"),
+        &tracker
+    ));
+    assert!(is_blocked_element(
+        Some(blocks_trackers),
+        &html_name("iframe"),
+        &tracker
+    ));
+    assert!(!is_blocked_element(None, &html_name("script"), &tracker));
+    let script = [attribute("src", "script.js")];
+    assert!(!is_blocked_element(
+        Some(blocks_trackers),
+        &html_name("script"),
+        &script
+    ));
+}
+
+#[test]
+fn never_blocks_structural_elements() {
+    let tracker = [attribute("src", "tracker")];
+    for name in &["html", "head", "body", "template"] {
+        assert!(!is_blocked_element(
+            Some(blocks_trackers),
+            &html_name(name),
+            &tracker
+        ));
+    }
+}
+
+fn icon_directory(sizes: &[(u8, u8, u16)]) -> Vec<u8> {
+    let mut bytes = vec![0, 0, 1, 0, sizes.len() as u8, 0];
+    for &(width, height, bits_per_pixel) in sizes {
+        bytes.extend_from_slice(&[width, height, 0, 0, 1, 0]);
+        bytes.extend_from_slice(&bits_per_pixel.to_le_bytes());
+        bytes.extend_from_slice(&[0; 8]);
+    }
+    bytes
+}
+
+#[test]
+fn lists_icon_entries_largest_first() {
+    let bytes = icon_directory(&[(16, 16, 32), (0, 0, 32), (48, 48, 8)]);
+    let entry = |width, height, bits_per_pixel| IconEntry {
+        width,
+        height,
+        bits_per_pixel,
+    };
+    assert_eq!(
+        icon_entries(&bytes),
+        Some(vec![
+            entry(256, 256, 32),
+            entry(48, 48, 8),
+            entry(16, 16, 32)
+        ])
+    );
+}
+
+#[test]
+fn rejects_invalid_icons() {
+    assert_eq!(icon_entries(b""), None);
+    assert_eq!(icon_entries(&icon_directory(&[])), None);
+    // A cursor, not an icon.
+    let mut cursor = icon_directory(&[(32, 32, 32)]);
+    cursor[2] = 2;
+    assert_eq!(icon_entries(&cursor), None);
+    let truncated = icon_directory(&[(32, 32, 32), (16, 16, 32)]);
+    assert_eq!(icon_entries(&truncated[..truncated.len() - 1]), None);
+}
+
+#[test]
+fn closing_a_blocked_parser_defers_parsing() {
+    assert_eq!(close_action(false, true, 0), CloseAction::Defer);
+    assert_eq!(close_action(false, false, 0), CloseAction::Parse);
+}
+
+#[test]
+fn closing_a_parser_from_its_script_does_not_finish_it_twice() {
+    // The script runs while the parser tokenizes, or resumes it.
+    assert_eq!(close_action(false, false, 1), CloseAction::Defer);
+    assert_eq!(close_action(false, false, 2), CloseAction::Defer);
+    // The parser already finished, or was aborted.
+    assert_eq!(close_action(true, false, 0), CloseAction::Ignore);
+    assert_eq!(close_action(true, true, 1), CloseAction::Ignore);
+}
+
+#[test]
+fn parses_clear_site_data_types() {
+    assert_eq!(
+        parse_clear_site_data("\"cache\", \"storage\""),
+        ClearSiteData {
+            cache: true,
+            cookies: false,
+            storage: true,
+        }
+    );
+    assert_eq!(
+        parse_clear_site_data("\"cookies\",\"executionContexts\""),
+        ClearSiteData {
+            cache: false,
+            cookies: true,
+            storage: false,
+        }
+    );
+}
+
+#[test]
+fn parses_clear_site_data_wildcard() {
+    assert_eq!(
+        parse_clear_site_data("\"*\""),
+        ClearSiteData {
+            cache: true,
+            cookies: true,
+            storage: true,
+        }
+    );
+}
+
+#[test]
+fn ignores_unquoted_and_unknown_clear_site_data_types() {
+    assert_eq!(
+        parse_clear_site_data("cache, *, \"Cookies\", \"storage"),
+        ClearSiteData::default()
+    );
+    assert_eq!(parse_clear_site_data(""), ClearSiteData::default());
+}
+
+#[test]
+fn obsolete_elements() {
+    assert!(is_obsolete_element("marquee"));
+    assert!(is_obsolete_element("center"));
+    assert!(is_obsolete_element("font"));
+    assert!(!is_obsolete_element("div"));
+    assert!(!is_obsolete_element("script"));
+}
+
+#[test]
+fn legacy_doctypes() {
+    assert!(!is_legacy_doctype("html", "", ""));
+    assert!(!is_legacy_doctype("HTML", "", ""));
+    assert!(!is_legacy_doctype("html", "", "about:legacy-compat"));
+    assert!(is_legacy_doctype(
+        "html",
+        "-//W3C//DTD HTML 4.01//EN",
+        "http://www.w3.org/TR/html4/strict.dtd"
+    ));
+    assert!(is_legacy_doctype("html", "", "http://example.com/html.dtd"));
+    assert!(is_legacy_doctype("svg", "", ""));
+}
+
+#[test]
+fn unknown_content_type_handling_from_pref_value() {
+    assert_eq!(
+        UnknownContentTypeHandling::from_pref_value("plaintext"),
+        UnknownContentTypeHandling::PlainText
+    );
+    assert_eq!(
+        UnknownContentTypeHandling::from_pref_value("download"),
+        UnknownContentTypeHandling::Download
+    );
+    assert_eq!(
+        UnknownContentTypeHandling::from_pref_value("custom"),
+        UnknownContentTypeHandling::CustomHtml
+    );
+    assert_eq!(
+        UnknownContentTypeHandling::from_pref_value(""),
+        UnknownContentTypeHandling::Warning
+    );
+    assert_eq!(
+        UnknownContentTypeHandling::from_pref_value("Download"),
+        UnknownContentTypeHandling::Warning
+    );
+}
+
+#[test]
+fn buffered_input_size_keeps_peak_across_measures() {
+    let mut size = BufferedInputSize::default();
+    size.push(10);
+    size.push(20);
+    assert_eq!(size.peak(), 30);
+    size.measure(Some(5));
+    size.push(10);
+    assert_eq!(size.peak(), 30);
+    size.push(20);
+    assert_eq!(size.peak(), 35);
+    // Queues being tokenized can't be measured, so the size pushed so far
+    // is kept.
+    size.measure(None);
+    size.push(1);
+    assert_eq!(size.peak(), 36);
+}
+
+#[test]
+fn no_content_responses_keep_the_current_document() {
+    assert!(is_no_content_status(Some(204)));
+    assert!(is_no_content_status(Some(205)));
+    assert!(!is_no_content_status(Some(200)));
+    assert!(!is_no_content_status(Some(206)));
+    assert!(!is_no_content_status(None));
+}
+
+#[test]
+fn font_files_are_told_by_their_signature() {
+    assert!(is_font_file(&[0, 1, 0, 0, 0, 12]));
+    assert!(is_font_file(b"OTTO\0\x0c"));
+    assert!(is_font_file(b"wOF2\0\x01\0\0"));
+    assert!(is_font_file(b"ttcf"));
+    assert!(!is_font_file(b"wOF"));
+    assert!(!is_font_file(b"<!DOCTYPE html>"));
+    assert!(!is_font_file(b""));
+}
+
+fn drain(queue: &mut BufferQueue) -> String {
+    let mut input = String::new();
+    while let Some(chunk) = queue.pop_front() {
+        input.push_str(&chunk);
+    }
+    input
+}
+
+#[test]
+fn compacted_input_is_unchanged() {
+    let mut input = BufferQueue::new();
+    let mut chunk = StrTendril::from("<!DOCTYPE html><p>a");
+    chunk.pop_front(15);
+    input.push_back(chunk);
+    input.push_back(StrTendril::new());
+    input.push_back(StrTendril::from("b</p>"));
+    compact_input(&mut input);
+    assert_eq!(drain(&mut input), "<p>ab</p>");
+
+    let mut input = BufferQueue::new();
+    compact_input(&mut input);
+    assert!(input.is_empty());
+}
+
+#[test]
+fn feed_batches_split_at_character_boundaries() {
+    let mut input = BufferQueue::new();
+    input.push_back(StrTendril::from("<p>a"));
+    input.push_back(StrTendril::from("é</p>"));
+    let batch = take_feed_batch(&mut input, 5);
+    assert_eq!(
+        batch.iter().map(|t| &**t).collect::<Vec<_>>(),
+        vec!["<p>a", "é"]
+    );
+    assert_eq!(drain(&mut input), "</p>");
+}
+
+#[test]
+fn feed_batch_without_size_takes_all_input() {
+    let mut input = BufferQueue::new();
+    input.push_back(StrTendril::from("<p>a"));
+    input.push_back(StrTendril::from("b</p>"));
+    assert_eq!(take_feed_batch(&mut input, 0).len(), 2);
+    assert!(input.is_empty());
+}
+
+/// Splits a megabyte of markup, received in 16 KB chunks, in the batches the
+/// async HTML tokenizer is fed with for a few values of
+/// `dom.servoparser.async_html_tokenizer.feed_size`.
+fn bench_feed_batches(b: &mut Bencher, feed_size: usize) {
+    let chunk = "<div class=item><a href=/page>Link</a> text é</div>\n".repeat(320);
+    b.iter(|| {
+        let mut input = BufferQueue::new();
+        for _ in 0..64 {
+            input.push_back(StrTendril::from(&*chunk));
+        }
+        while !input.is_empty() {
+            test::black_box(take_feed_batch(&mut input, feed_size));
+        }
+    })
+}
+
+#[bench]
+fn feed_batches_unbounded(b: &mut Bencher) {
+    bench_feed_batches(b, 0)
+}
+
+#[bench]
+fn feed_batches_of_4k(b: &mut Bencher) {
+    bench_feed_batches(b, 4096)
+}
+
+#[bench]
+fn feed_batches_of_64k(b: &mut Bencher) {
+    bench_feed_batches(b, 65536)
+}
+
+#[test]
+fn prepended_script_input_comes_first() {
+    let mut script_input = BufferQueue::new();
+    let mut network_input = BufferQueue::new();
+    script_input.push_back(StrTendril::from("<p>a"));
+    script_input.push_back(StrTendril::from("<p>b"));
+    network_input.push_back(StrTendril::from("<p>c"));
+    network_input.push_back(StrTendril::from("<p>d"));
+    prepend_script_input(&mut script_input, &mut network_input);
+    assert!(script_input.is_empty());
+    assert_eq!(drain(&mut network_input), "<p>a<p>b<p>c<p>d");
+}
+
+/// Records the names of the start tags it is given, and blocks the tokenizer
+/// on every `</script>` like the parser does for a parser-blocking script.
+#[derive(Default)]
+struct StartTagRecorder {
+    names: Vec<String>,
+}
+
+impl TokenSink for StartTagRecorder {
+    type Handle = ();
+
+    fn process_token(&mut self, token: Token, _line_number: u64) -> TokenSinkResult<()> {
+        match token {
+            Token::TagToken(tag) if tag.name == LocalName::from("script") => match tag.kind {
+                TagKind::StartTag => TokenSinkResult::RawData(RawKind::ScriptData),
+                TagKind::EndTag => TokenSinkResult::Script(()),
+            },
+            Token::TagToken(tag) if tag.kind == TagKind::StartTag => {
+                self.names.push(tag.name.to_string());
+                TokenSinkResult::Continue
+            },
+            _ => TokenSinkResult::Continue,
+        }
+    }
+}
+
+#[test]
+fn consumed_chars_counts_the_front_of_the_input() {
+    let chunks = [StrTendril::from("<p>é"), StrTendril::from("ü</p>")];
+    assert_eq!(consumed_chars(&chunks, 0), 9);
+    assert_eq!(consumed_chars(&chunks, 6), 4);
+    assert_eq!(consumed_chars(&chunks, 4), 5);
+    assert_eq!(consumed_chars(&chunks, 11), 0);
+    assert_eq!(consumed_chars(&[], 0), 0);
+}
+
+/// A tokenizer blocked on a script only consumed the input up to the end of
+/// the script, and what it didn't consume isn't counted until it's fed again.
+#[test]
+fn consumed_chars_stops_at_a_blocking_script() {
+    let mut tokenizer = HtmlTokenizer::new(StartTagRecorder::default(), Default::default());
+    let chunks = [
+        StrTendril::from("<p>é<script>"),
+        StrTendril::from("x</script><p>ü"),
+    ];
+    let mut input = BufferQueue::new();
+    for chunk in &chunks {
+        input.push_back(chunk.clone());
+    }
+    assert!(feed_until_script(&mut tokenizer, &mut input));
+    let remaining = drain(&mut input);
+    assert_eq!(remaining, "<p>ü");
+    assert_eq!(
+        consumed_chars(&chunks, remaining.len()),
+        "<p>é<script>x</script>".chars().count()
+    );
+}
+
+/// Input not tokenized yet in the order of the input stream, where `None`
+/// stands for a parser-blocking script.
+type PendingStream = VecDeque<Option<String>>;
+
+/// Takes the tags off the front of `pending` until it reaches a script,
+/// returning whether it did.
+fn run_until_script(pending: &mut PendingStream, expected: &mut Vec<String>) -> bool {
+    while let Some(item) = pending.pop_front() {
+        match item {
+            Some(name) => expected.push(name),
+            None => return true,
+        }
+    }
+    false
+}
+
+/// Feeds `input` to `tokenizer`, returning whether it stopped at a script.
+fn feed_until_script(
+    tokenizer: &mut HtmlTokenizer<StartTagRecorder>,
+    input: &mut BufferQueue,
+) -> bool {
+    matches!(tokenizer.feed(input), TokenizerResult::Script(()))
+}
+
+/// Interleaves network chunks, some of which end a parser-blocking script,
+/// `document.write()` calls and the execution of the blocking scripts. Those
+/// are fed to an HTML tokenizer through the queues the parser uses, with
+/// `prepend_script_input` moving the writes in front of the network input once
+/// a script ran, and the tags must come out in the order of the input stream:
+/// what a blocking script wrote goes right after it, ahead of the rest of the
+/// network input.
+/// <https://html.spec.whatwg.org/multipage/#insertion-point>
+#[test]
+fn interleaved_input_is_tokenized_in_order() {
+    // A fixed linear congruential generator keeps failures reproducible.
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = |bound: u64| {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) % bound
+    };
+    for _ in 0..200 {
+        let mut tokenizer = HtmlTokenizer::new(StartTagRecorder::default(), Default::default());
+        let mut script_input = BufferQueue::new();
+        let mut network_input = BufferQueue::new();
+        let mut pending = PendingStream::new();
+        let mut expected = vec![];
+        let mut written_while_blocked = 0;
+        let mut blocked = false;
+        for step in 0..next(40) {
+            let name = format!("p{}", step);
+            match next(4) {
+                // A network chunk, which may end a blocking script before the
+                // tag.
+                n @ 0..=1 => {
+                    let chunk = if n == 0 {
+                        format!("<{}>", name)
+                    } else {
+                        pending.push_back(None);
+                        format!("<script></script><{}>", name)
+                    };
+                    pending.push_back(Some(name));
+                    network_input.push_back(StrTendril::from(chunk));
+                    if !blocked {
+                        blocked = feed_until_script(&mut tokenizer, &mut network_input);
+                        assert_eq!(blocked, run_until_script(&mut pending, &mut expected));
+                    }
+                },
+                // A write, tokenized at once unless a script blocks the parser,
+                // in which case it is inserted after what was written before.
+                2 => {
+                    let chunk = StrTendril::from(format!("<{}>", name));
+                    if blocked {
+                        script_input.push_back(chunk);
+                        pending.insert(written_while_blocked, Some(name));
+                        written_while_blocked += 1;
+                    } else {
+                        let mut input = BufferQueue::new();
+                        input.push_back(chunk);
+                        assert!(matches!(tokenizer.feed(&mut input), TokenizerResult::Done));
+                        expected.push(name);
+                    }
+                },
+                // The blocking script ran.
+                _ if blocked => {
+                    prepend_script_input(&mut script_input, &mut network_input);
+                    written_while_blocked = 0;
+                    blocked = feed_until_script(&mut tokenizer, &mut network_input);
+                    assert_eq!(blocked, run_until_script(&mut pending, &mut expected));
+                },
+                _ => {},
+            }
+        }
+        assert_eq!(tokenizer.sink.names, expected);
+    }
+}
+
+#[test]
+fn content_location_is_resolved_against_the_response_url() {
+    let url = ServoUrl::parse("https://example.com/articles/latest").unwrap();
+    assert_eq!(
+        content_location_url("https://example.com/articles/42.html", &url),
+        Some(ServoUrl::parse("https://example.com/articles/42.html").unwrap())
+    );
+    assert_eq!(
+        content_location_url(" 42.html ", &url),
+        Some(ServoUrl::parse("https://example.com/articles/42.html").unwrap())
+    );
+    assert_eq!(
+        content_location_url("/archive/42/", &url),
+        Some(ServoUrl::parse("https://example.com/archive/42/").unwrap())
+    );
+    assert_eq!(content_location_url("", &url), None);
+    assert_eq!(content_location_url("https://[::1", &url), None);
+}
+
+#[test]
+fn content_location_of_another_origin_is_ignored() {
+    let url = ServoUrl::parse("https://example.com/articles/latest").unwrap();
+    assert_eq!(
+        content_location_url("https://cdn.example.com/articles/42.html", &url),
+        None
+    );
+    assert_eq!(
+        content_location_url("http://example.com/articles/42.html", &url),
+        None
+    );
+    assert_eq!(
+        content_location_url("data:text/html,<script>alert(1)</script>", &url),
+        None
+    );
+    assert_eq!(content_location_url("javascript:alert(1)", &url), None);
+}
+
+#[test]
+fn async_html_tokenizer_is_selected_by_size() {
+    // Without a threshold, or without a known size, the pref decides.
+    assert!(!uses_async_html_tokenizer(false, 0, Some(1 << 20)));
+    assert!(uses_async_html_tokenizer(true, 0, Some(10)));
+    assert!(!uses_async_html_tokenizer(false, 65536, None));
+    assert!(uses_async_html_tokenizer(true, 65536, None));
+    // Otherwise documents from the threshold on are tokenized off-thread.
+    assert!(!uses_async_html_tokenizer(true, 65536, Some(65535)));
+    assert!(uses_async_html_tokenizer(true, 65536, Some(65536)));
+    assert!(uses_async_html_tokenizer(true, 65536, Some(1 << 20)));
+    assert!(uses_async_html_tokenizer(true, -1, Some(10)));
+    // The threshold never enables it when the pref is off.
+    assert!(!uses_async_html_tokenizer(false, 65536, Some(65536)));
+    assert!(!uses_async_html_tokenizer(false, 65536, Some(1 << 20)));
+}
+
+#[test]
+fn fragments_are_parsed_in_chunks_past_the_threshold() {
+    // Nothing is parsed in chunks with the pref off, or without a chunk size.
+    assert_eq!(fragment_chunk_size(false, 1024, 0, 1 << 20), None);
+    assert_eq!(fragment_chunk_size(true, 0, 0, 1 << 20), None);
+    assert_eq!(fragment_chunk_size(true, -1, 0, 1 << 20), None);
+    // Fragments that fit in a single chunk are parsed whole.
+    assert_eq!(fragment_chunk_size(true, 1024, 0, 1024), None);
+    assert_eq!(fragment_chunk_size(true, 1024, 0, 1025), Some(1024));
+    // Otherwise fragments from the threshold on are parsed in chunks.
+    assert_eq!(fragment_chunk_size(true, 1024, 65536, 65535), None);
+    assert_eq!(fragment_chunk_size(true, 1024, 65536, 65536), Some(1024));
+    assert_eq!(fragment_chunk_size(true, 1024, -1, 2048), Some(1024));
+}
+
+#[test]
+fn parse_budget_is_exceeded_past_either_maximum() {
+    let mut budget = ParseBudget::new(100, 10);
+    budget.add_bytes(60);
+    budget.add_nodes(10);
+    assert_eq!(budget.exceeded(), None);
+    budget.add_bytes(40);
+    assert_eq!(budget.exceeded(), None);
+    budget.add_bytes(1);
+    assert_eq!(budget.exceeded(), Some(ParseBudgetExceeded::Bytes(100)));
+
+    let mut budget = ParseBudget::new(100, 10);
+    budget.add_nodes(11);
+    assert_eq!(budget.exceeded(), Some(ParseBudgetExceeded::Nodes(10)));
+    budget.add_nodes(usize::MAX);
+    assert_eq!(budget.exceeded(), Some(ParseBudgetExceeded::Nodes(10)));
+}
+
+#[test]
+fn source_viewer_numbers_escaped_lines() {
+    let mut viewer = SourceViewer::new(encoding_rs::UTF_8);
+    assert!(SourceViewer::header(None).ends_with("<pre class=\"source\">\n"));
+    assert_eq!(
+        viewer.push(b"<b>a & b</b>\r\n</pre><script>x()</script>\n"),
+        "<span class=\"line\" id=\"L1\">&lt;b&gt;a &amp; b&lt;/b&gt;</span>\n\
+         <span class=\"line\" id=\"L2\">&lt;/pre&gt;&lt;script&gt;x()&lt;/script&gt;</span>\n"
+    );
+    assert_eq!(viewer.finish(), "</pre></body></html>");
+
+    let mut viewer = SourceViewer::new(encoding_rs::UTF_8);
+    assert!(SourceViewer::header(Some("webvtt")).ends_with("<pre class=\"source webvtt\">\n"));
+    assert_eq!(viewer.push(b"WEBVTT"), "");
+    assert_eq!(
+        viewer.finish(),
+        "<span class=\"line\" id=\"L1\">WEBVTT</span>\n</pre></body></html>"
+    );
+}
+
+#[test]
+fn source_viewer_shows_lines_as_they_are_received() {
+    let mut viewer = SourceViewer::new(encoding_rs::UTF_8);
+    // Only the terminated lines are shown, the rest is carried over, even
+    // when a chunk ends within a character or between a CR and its LF.
+    assert_eq!(
+        viewer.push(b"one\ntw"),
+        "<span class=\"line\" id=\"L1\">one</span>\n"
+    );
+    assert_eq!(viewer.push(b"o \xC3"), "");
+    assert_eq!(viewer.push(b"\xA9\r"), "");
+    assert_eq!(
+        viewer.push(b"\n\nthree"),
+        "<span class=\"line\" id=\"L2\">two \u{e9}</span>\n\
+         <span class=\"line\" id=\"L3\"></span>\n"
+    );
+    assert_eq!(
+        viewer.finish(),
+        "<span class=\"line\" id=\"L4\">three</span>\n</pre></body></html>"
+    );
+
+    let mut viewer = SourceViewer::new(encoding_rs::UTF_8);
+    assert_eq!(
+        viewer.finish(),
+        "<span class=\"line\" id=\"L1\"></span>\n</pre></body></html>"
+    );
+}
+
+#[test]
+fn unknown_content_type_page_escapes_the_content_type() {
+    assert_eq!(
+        unknown_content_type_page("application", "x-foo"),
+        "<html><body><p>Unknown content type (application/x-foo).</p></body></html>"
+    );
+    let page =
+        unknown_content_type_page("<img src=x onerror=alert(1)>", "</p><script>\"&\"</script>");
+    assert!(!page.contains("<img"));
+    assert!(!page.contains("<script>"));
+    assert!(page.contains(
+        "(&lt;img src=x onerror=alert(1)&gt;/&lt;/p&gt;&lt;script&gt;&quot;&amp;&quot;&lt;/script&gt;)"
+    ));
+}
+
+#[test]
+fn synthesized_pages_escape_what_they_show() {
+    assert_eq!(
+        unsupported_image_page("image/x-foo"),
+        "<html><body><p>Images of type image/x-foo are not supported.</p></body></html>"
+    );
+    let page = unsupported_image_page("image/</p><script>x()</script>");
+    assert!(!page.contains("<script>"));
+    assert!(page.contains("image/&lt;/p&gt;&lt;script&gt;x()&lt;/script&gt; are"));
+
+    let page = mixed_content_page("http://example.com/?a=1&b=<script>");
+    assert!(!page.contains("<script>"));
+    assert!(page.contains("<p>http://example.com/?a=1&amp;b=&lt;script&gt; was not shown"));
+
+    let page = no_content_type_page("http://example.com/\"<script>");
+    assert!(!page.contains("<script>"));
+    assert!(page.contains("<p>http://example.com/&quot;&lt;script&gt; has no content type"));
+}
+
+#[test]
+fn pdf_viewer_page_escapes_the_url() {
+    let url = ServoUrl::parse("https://example.com/a.pdf?x=1&y=2").unwrap();
+    assert_eq!(
+        pdf_viewer_page(&url),
+        "<html><head><title>https://example.com/a.pdf?x=1&amp;y=2</title></head><body>\
+         <p>https://example.com/a.pdf?x=1&amp;y=2 is opened in a PDF viewer.</p></body></html>"
+    );
+}
+
+#[test]
+fn font_specimen_page_escapes_the_url() {
+    let url = ServoUrl::parse("data:font/woff2,</style><script>alert(1)</script>").unwrap();
+    let page = font_specimen_page(&url);
+    assert!(!page.contains("<script>"));
+    assert_eq!(page.matches("</style>").count(), 1);
+    assert!(page.contains("src: url(\"data:font/woff2,%3C/style%3E%3Cscript%3Ealert(1)"));
+}
+
+#[test]
+fn error_page_substitutions_are_escaped() {
+    let page = substitute_html(
+        "<p>${reason}</p>",
+        "${reason}",
+        "bad certificate</p><script>alert(1)</script>",
+    );
+    assert_eq!(
+        page,
+        "<p>bad certificate&lt;/p&gt;&lt;script&gt;alert(1)&lt;/script&gt;</p>"
+    );
+    let page = substitute_html(
+        "<pre>${details}</pre><a title=\"${details}\">",
+        "${details}",
+        "\"><script src=x></script>&",
+    );
+    assert!(!page.contains("<script"));
+    assert_eq!(
+        page.matches("&quot;&gt;&lt;script src=x&gt;&lt;/script&gt;&amp;")
+            .count(),
+        2
+    );
+}
+
+fn element_attributes(attrs: &[(&str, &str)]) -> Vec<ElementAttribute> {
+    attrs
+        .iter()
+        .map(|&(name, value)| {
+            ElementAttribute::new(
+                QualName::new(None, Namespace::from(""), LocalName::from(name)),
+                DOMString::from(value),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn render_blocking_stylesheet_blocks_paint() {
+    let link = element_attributes(&[("rel", "stylesheet"), ("blocking", "render")]);
+    assert!(is_render_blocking_element(&html_name("link"), &link));
+    let link = element_attributes(&[("rel", "Stylesheet"), ("blocking", " foo  RENDER ")]);
+    assert!(is_render_blocking_element(&html_name("link"), &link));
+    let link = element_attributes(&[("rel", "stylesheet")]);
+    assert!(!is_render_blocking_element(&html_name("link"), &link));
+    let link = element_attributes(&[("rel", "stylesheet"), ("blocking", "rendering")]);
+    assert!(!is_render_blocking_element(&html_name("link"), &link));
+}
+
+#[test]
+fn only_loading_elements_block_rendering() {
+    let blocking = element_attributes(&[("blocking", "render")]);
+    assert!(is_render_blocking_element(&html_name("script"), &blocking));
+    assert!(is_render_blocking_element(&html_name("style"), &blocking));
+    assert!(!is_render_blocking_element(&html_name("div"), &blocking));
+    let icon = element_attributes(&[("rel", "icon"), ("blocking", "render")]);
+    assert!(!is_render_blocking_element(&html_name("link"), &icon));
+    let disabled = element_attributes(&[
+        ("rel", "stylesheet"),
+        ("disabled", ""),
+        ("blocking", "render"),
+    ]);
+    assert!(!is_render_blocking_element(&html_name("link"), &disabled));
+    let svg = QualName::new(
+        None,
+        Namespace::from("http://www.w3.org/2000/svg"),
+        LocalName::from("style"),
+    );
+    assert!(!is_render_blocking_element(&svg, &blocking));
+}
+
+fn color_schemes(schemes: &[&str], only: bool) -> Option<SupportedColorSchemes> {
+    Some(SupportedColorSchemes {
+        schemes: schemes.iter().map(|scheme| scheme.to_string()).collect(),
+        only,
+    })
+}
+
+#[test]
+fn parses_color_schemes() {
+    assert_eq!(parse_color_scheme("normal"), color_schemes(&[], false));
+    assert_eq!(parse_color_scheme(" Normal "), color_schemes(&[], false));
+    assert_eq!(
+        parse_color_scheme("dark light"),
+        color_schemes(&["dark", "light"], false)
+    );
+    assert_eq!(
+        parse_color_scheme("LIGHT\tmy-scheme"),
+        color_schemes(&["light", "my-scheme"], false)
+    );
+    assert_eq!(
+        parse_color_scheme("only light"),
+        color_schemes(&["light"], true)
+    );
+    assert_eq!(
+        parse_color_scheme("dark only"),
+        color_schemes(&["dark"], true)
+    );
+}
+
+#[test]
+fn rejects_invalid_color_schemes() {
+    for content in &[
+        "",
+        "  ",
+        "only",
+        "normal light",
+        "light normal",
+        "light only dark",
+        "only light only",
+        "inherit",
+        "light initial",
+        "2dark",
+        "-1",
+        "light, dark",
+    ] {
+        assert_eq!(parse_color_scheme(content), None, "{:?}", content);
+    }
+}
@@ -0,0 +1 @@
+// Served slowly, so that the parser waits for it.
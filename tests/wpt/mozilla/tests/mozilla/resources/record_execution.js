@@ -0,0 +1 @@
+parent.ran.push(document.currentScript.src);